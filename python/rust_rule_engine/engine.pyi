@@ -1,11 +1,45 @@
-from typing import Union
+import datetime
+from decimal import Decimal
+from typing import Any, Callable, Literal, Optional, Union
+
+class Context:
+    def __init__(
+        self,
+        *,
+        collation: Optional[str] = None,
+        max_sequence_length: int = 1_000_000,
+        timezone: str = "UTC",
+        now: Union[datetime.datetime, Callable[[], datetime.datetime], None] = None,
+        decimal_literals: bool = False,
+        integer_overflow: Literal["promote", "error"] = "promote",
+        exchange_rates: Optional[dict[str, dict[str, Decimal]]] = None,
+        rel_tol: float = 1e-09,
+        abs_tol: float = 0.0,
+    ) -> None: ...
+
+    def load_calendars(self, path: str) -> None: ...
+
+    def builtins(self) -> list[dict[str, Any]]: ...
+
+    def register_function(
+        self,
+        name: str,
+        function: Callable[..., Any],
+        *,
+        arg_types: Optional[list[Optional[str]]] = None,
+        return_type: Optional[str] = None,
+        pure: bool = False,
+        doc: Optional[str] = None,
+    ) -> None: ...
 
 class Rule:
-    def __init__(self, text: str) -> None: ...
+    def __init__(self, text: str, ctx: Optional[Context] = None) -> None: ...
 
     @classmethod
-    def is_valid(cls, text: str, _ctx=None) -> bool: ...
+    def is_valid(cls, text: str, ctx: Optional[Context] = None) -> bool: ...
 
     def evaluate(self, thing: dict[str, any] = None, _ctx=None) -> bool: ...
 
+    def lint(self, thing: dict[str, any], ctx: Optional[Context] = None) -> list[str]: ...
+
     def matches(self, thing: dict[str, any] = None) -> Union[bool, float, int, str]: ...
@@ -1,72 +1,439 @@
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use ipnet::IpNet;
+use num_bigint::BigInt;
+use num_integer::Integer as _;
+use num_traits::{FromPrimitive, Signed};
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
+use pyo3::types::{PyBytes, PyDict, PySet};
 use pyo3::IntoPy;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use crate::engine::Context;
-use crate::errors::EvaluationError;
+use crate::engine::IntegerOverflow;
+use crate::errors::{
+    EvaluationError, IncomparableTypesError, IntegerOverflowError, InvalidParameterTypeError,
+    ParseError,
+};
+use crate::mapping::ValueMap;
+use crate::money::Money;
+use crate::registry::{Builtin, BuiltinRegistry};
+use crate::set::ValueSet;
 
 #[derive(Clone, Debug)]
 pub enum EvalResultTypes {
+    /// Python's `None`
+    Null,
     Boolean(bool),
     Float(f64),
     Integer(i64),
+    /// An integer outside the range of `i64`. Results that fit are always stored as `Integer`.
+    BigInt(BigInt),
     String(String),
+    /// Binary data. Bytes never equal a string, even one with the same characters.
+    Bytes(Vec<u8>),
     List(Vec<EvalResultTypes>),
+    Datetime(DateTime<FixedOffset>),
+    Timedelta(TimeDelta),
+    Decimal(Decimal),
+    Money(Money),
+    Set(ValueSet),
+    /// A Python dict, other than one shaped like money
+    Mapping(ValueMap),
+    /// An IPv4 or IPv6 address
+    IpAddress(IpAddr),
+    /// A range of addresses written in CIDR notation, e.g. `10.0.0.0/8`
+    Network(IpNet),
 }
 impl EvalResultTypes {
     pub fn is_truthy(&self) -> bool {
         match self {
+            EvalResultTypes::Null => false,
             EvalResultTypes::Boolean(value) => *value,
             EvalResultTypes::Float(value) => *value != 0.0,
             EvalResultTypes::Integer(value) => *value != 0,
+            EvalResultTypes::BigInt(_) => true,
             EvalResultTypes::String(value) => !value.is_empty(),
+            EvalResultTypes::Bytes(value) => !value.is_empty(),
             EvalResultTypes::List(value) => !value.is_empty(),
+            EvalResultTypes::Datetime(_) => true,
+            EvalResultTypes::Timedelta(value) => !value.is_zero(),
+            EvalResultTypes::Decimal(value) => !value.is_zero(),
+            EvalResultTypes::Money(value) => !value.amount.is_zero(),
+            EvalResultTypes::Set(value) => !value.is_empty(),
+            EvalResultTypes::Mapping(value) => !value.is_empty(),
+            EvalResultTypes::IpAddress(_) | EvalResultTypes::Network(_) => true,
             // TODO: Ensure collections are not empty
         }
     }
+
+    /// The name of the value's type as shown to rule authors in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            EvalResultTypes::Null => "null",
+            EvalResultTypes::Boolean(_) => "boolean",
+            EvalResultTypes::Float(_) => "float",
+            EvalResultTypes::Integer(_) | EvalResultTypes::BigInt(_) => "integer",
+            EvalResultTypes::String(_) => "string",
+            EvalResultTypes::Bytes(_) => "bytes",
+            EvalResultTypes::List(_) => "list",
+            EvalResultTypes::Datetime(_) => "datetime",
+            EvalResultTypes::Timedelta(_) => "timedelta",
+            EvalResultTypes::Decimal(_) => "decimal",
+            EvalResultTypes::Money(_) => "money",
+            EvalResultTypes::Set(_) => "set",
+            EvalResultTypes::Mapping(_) => "mapping",
+            EvalResultTypes::IpAddress(_) => "ip",
+            EvalResultTypes::Network(_) => "network",
+        }
+    }
 }
 impl PartialEq for EvalResultTypes {
+    /// Values of types that can't be compared, and money in different currencies, are unequal
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (EvalResultTypes::Boolean(lhs), EvalResultTypes::Boolean(rhs)) => lhs == rhs,
-            (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => lhs == rhs,
-            (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => *lhs as f64 == *rhs,
-            (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => *lhs == *rhs as f64,
-            (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => lhs == rhs,
-            (EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => lhs == rhs,
-            (EvalResultTypes::List(lhs), EvalResultTypes::List(rhs)) => lhs == rhs,
-            _ => false,
-        }
+        matches!(
+            relate(Comparison::Equality, self, other),
+            Ok(Some(Ordering::Equal))
+        )
     }
 }
 impl IntoPy<PyObject> for EvalResultTypes {
     fn into_py(self, py: pyo3::Python) -> pyo3::PyObject {
         match self {
+            EvalResultTypes::Null => py.None(),
             EvalResultTypes::Boolean(value) => value.into_py(py),
             EvalResultTypes::Float(value) => value.into_py(py),
             EvalResultTypes::Integer(value) => value.into_py(py),
+            EvalResultTypes::BigInt(value) => value.into_py(py),
             EvalResultTypes::String(value) => value.into_py(py),
+            EvalResultTypes::Bytes(value) => PyBytes::new(py, &value).into_py(py),
             EvalResultTypes::List(value) => value.into_py(py),
+            EvalResultTypes::Datetime(value) => value.into_py(py),
+            EvalResultTypes::Timedelta(value) => value.into_py(py),
+            EvalResultTypes::Decimal(value) => value.into_py(py),
+            // The same mapping shape that is accepted as input
+            EvalResultTypes::Money(value) => {
+                let dict = PyDict::new(py);
+                // Setting str and Decimal values on a new dict can't fail
+                dict.set_item("amount", value.amount).unwrap();
+                dict.set_item("currency", value.currency).unwrap();
+                dict.into_py(py)
+            }
+            EvalResultTypes::Set(value) => {
+                let items: Vec<PyObject> =
+                    value.iter().map(|item| item.clone().into_py(py)).collect();
+                // Money converts to a dict, which Python can't hash, so such sets become lists
+                match PySet::new(py, &items) {
+                    Ok(set) => set.into_py(py),
+                    Err(_) => items.into_py(py),
+                }
+            }
+            EvalResultTypes::IpAddress(value) => python_ip("ip_address", value.to_string(), py),
+            EvalResultTypes::Network(value) => python_ip("ip_network", value.to_string(), py),
+            EvalResultTypes::Mapping(value) => {
+                let dict = PyDict::new(py);
+                for (key, value) in value.iter() {
+                    // Mapping keys are always hashable in Python
+                    dict.set_item(key.clone().into_py(py), value.clone().into_py(py))
+                        .unwrap();
+                }
+                dict.into_py(py)
+            }
         }
     }
 }
 pub type EvalResult = Result<EvalResultTypes, EvaluationError>;
 
+/// Builds an object of Python's `ipaddress` module from its text, falling back to the text itself
+/// if that somehow fails
+fn python_ip(constructor: &str, text: String, py: Python) -> PyObject {
+    py.import("ipaddress")
+        .and_then(|module| module.call_method1(constructor, (text.as_str(),)))
+        .map(|value| value.into_py(py))
+        .unwrap_or_else(|_| text.into_py(py))
+}
+
+/// A rule program: zero or more `let` bindings and `fn` definitions followed by the expression that
+/// produces the result.
+pub struct Program {
+    statements: Vec<Statement>,
+}
+impl Program {
+    pub fn new(statements: Vec<Statement>) -> Self {
+        Program { statements }
+    }
+
+    /// Checks the program for errors that can be caught before evaluation. Bindings and functions
+    /// may only be referenced by the statements after them and may not be redefined, calls must
+    /// pass the number of arguments the function declares, and function bodies may only reference
+    /// their own parameters. A function can't call itself, since without a conditional expression
+    /// the recursion could never end. Builtins must exist in the given registry and be passed arguments
+    /// their signatures accept.
+    pub fn validate(&self, builtins: &BuiltinRegistry) -> Result<(), ParseError> {
+        let names: HashSet<&str> = self
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Let(name, _) => Some(name.as_str()),
+                Statement::Function(function) => Some(function.name.as_str()),
+                Statement::Expression(_) => None,
+            })
+            .collect();
+        // Maps each name defined so far to the arity of the function, or `None` for bindings
+        let mut defined: HashMap<&str, Option<usize>> = HashMap::new();
+        for statement in &self.statements {
+            match statement {
+                Statement::Let(name, expr) => {
+                    expr.walk(&mut |primary| check_reference(primary, &names, &defined, builtins))?;
+                    define(&mut defined, name, None)?;
+                }
+                Statement::Function(function) => {
+                    function.validate(&names, &defined, builtins)?;
+                    define(
+                        &mut defined,
+                        &function.name,
+                        Some(function.parameters.len()),
+                    )?;
+                }
+                Statement::Expression(expr) => {
+                    expr.walk(&mut |primary| check_reference(primary, &names, &defined, builtins))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Visits every primary expression that is evaluated against the input. Function bodies are
+    /// skipped since they can only see their arguments.
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        for statement in &self.statements {
+            match statement {
+                Statement::Let(_, expr) | Statement::Expression(expr) => expr.walk(visit)?,
+                Statement::Function(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+        // Plain expressions don't bind anything, so skip creating a scope for them
+        if let [Statement::Expression(expr)] = self.statements.as_slice() {
+            return expr.evaluate(ctx, thing);
+        }
+        let mut scope = ctx.clone();
+        let mut result = None;
+        for statement in &self.statements {
+            match statement {
+                Statement::Let(name, expr) => {
+                    let value = expr.evaluate(&scope, thing)?;
+                    scope.bind(name, value);
+                }
+                Statement::Function(function) => scope.define_function(function.clone()),
+                Statement::Expression(expr) => result = Some(expr.evaluate(&scope, thing)?),
+            }
+        }
+        result.ok_or_else(|| EvaluationError::new("Program has no result expression"))
+    }
+}
+
+fn define<'a>(
+    defined: &mut HashMap<&'a str, Option<usize>>,
+    name: &'a str,
+    arity: Option<usize>,
+) -> Result<(), ParseError> {
+    match defined.insert(name, arity) {
+        Some(_) => Err(ParseError::new(&format!(
+            "Symbol {} is already defined",
+            name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Ensures a reference to a program-level name only occurs after the name is defined and that
+/// calls match the arity of the function they target.
+fn check_reference(
+    primary: &PrimaryExpression,
+    names: &HashSet<&str>,
+    defined: &HashMap<&str, Option<usize>>,
+    builtins: &BuiltinRegistry,
+) -> Result<(), ParseError> {
+    let check_arity = |builtin: &Builtin, count: usize| {
+        builtin
+            .signature
+            .check_arity(count)
+            .map_err(|err| ParseError::new(&err.to_string()))
+    };
+    match primary {
+        PrimaryExpression::Call(call) => check_call(call, names, defined, builtins),
+        // A namespace is only checked as the receiver of the builtin it qualifies
+        PrimaryExpression::Builtin(name)
+            if builtins.symbol(name).is_some() || builtins.is_namespace(name) =>
+        {
+            Ok(())
+        }
+        PrimaryExpression::Builtin(name) => Err(ParseError::new(&format!(
+            "Unknown builtin symbol ${}",
+            name
+        ))),
+        PrimaryExpression::Member(receiver, name) => match builtin_namespace(receiver, builtins) {
+            Some(namespace) => match builtins.symbol(&format!("{}.{}", namespace, name)) {
+                Some(_) => Ok(()),
+                None => Err(ParseError::new(&format!(
+                    "Unknown builtin symbol ${}.{}",
+                    namespace, name
+                ))),
+            },
+            None => Ok(()),
+        },
+        PrimaryExpression::MethodCall(call) => match call.target(builtins) {
+            (Some(builtin), receiver) => {
+                check_arity(builtin, call.args.len() + usize::from(receiver))
+            }
+            (None, _) => Err(ParseError::new(&format!("Unknown method {}", call.name))),
+        },
+        PrimaryExpression::BuiltinCall(call) => match builtins.callable(&call.name) {
+            Some(builtin) => check_arity(builtin, call.args.len()),
+            None => Err(ParseError::new(&format!(
+                "Unknown builtin function ${}",
+                call.name
+            ))),
+        },
+        _ => match primary.root_symbol() {
+            Some(name) if names.contains(name) && !defined.contains_key(name) => Err(
+                ParseError::new(&format!("Symbol {} used before definition", name)),
+            ),
+            _ => Ok(()),
+        },
+    }
+}
+
+fn check_call(
+    call: &FunctionCall,
+    names: &HashSet<&str>,
+    defined: &HashMap<&str, Option<usize>>,
+    builtins: &BuiltinRegistry,
+) -> Result<(), ParseError> {
+    let name = &call.name;
+    match defined.get(name.as_str()) {
+        Some(Some(arity)) if *arity == call.args.len() => Ok(()),
+        Some(Some(arity)) => Err(ParseError::new(&format!(
+            "Function {} expects {} arguments, got {}",
+            name,
+            arity,
+            call.args.len()
+        ))),
+        Some(None) => Err(ParseError::new(&format!("{} is not a function", name))),
+        None if names.contains(name.as_str()) => Err(ParseError::new(&format!(
+            "Function {} used before definition",
+            name
+        ))),
+        // Builtins such as `int(x)` can be called like functions when not shadowed by a definition
+        None => match builtins.callable(name) {
+            Some(builtin) => builtin
+                .signature
+                .check_arity(call.args.len())
+                .map_err(|err| ParseError::new(&err.to_string())),
+            None => Err(ParseError::new(&format!(
+                "Function {} is not defined",
+                name
+            ))),
+        },
+    }
+}
+
+/// The namespace a receiver such as `$math` names, e.g. in `$math.round(x)`
+fn builtin_namespace<'a>(
+    receiver: &'a PrimaryExpression,
+    builtins: &BuiltinRegistry,
+) -> Option<&'a str> {
+    match receiver {
+        PrimaryExpression::Builtin(name) if builtins.is_namespace(name) => Some(name),
+        _ => None,
+    }
+}
+
 pub enum Statement {
+    Let(String, Expression),
+    Function(Arc<FunctionDefinition>),
     Expression(Expression),
 }
-impl Statement {
-    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
-        match self {
-            Statement::Expression(expr) => expr.evaluate(ctx, thing),
+
+/// A function declared within the rule text with `fn name(params) = body;`
+pub struct FunctionDefinition {
+    pub name: String,
+    pub parameters: Vec<String>,
+    body: Expression,
+}
+impl FunctionDefinition {
+    pub fn new(name: String, parameters: Vec<String>, body: Expression) -> Self {
+        FunctionDefinition {
+            name,
+            parameters,
+            body,
+        }
+    }
+
+    fn validate(
+        &self,
+        names: &HashSet<&str>,
+        defined: &HashMap<&str, Option<usize>>,
+        builtins: &BuiltinRegistry,
+    ) -> Result<(), ParseError> {
+        let mut parameters = HashSet::new();
+        for parameter in &self.parameters {
+            if !parameters.insert(parameter.as_str()) {
+                return Err(ParseError::new(&format!(
+                    "Duplicate parameter {} in function {}",
+                    parameter, self.name
+                )));
+            }
+        }
+        // Functions are pure: their result may only depend on the arguments they are called with
+        self.body.walk(&mut |primary| match primary {
+            PrimaryExpression::Call(call) if call.name == self.name => Err(ParseError::new(
+                &format!("Function {} cannot call itself", self.name),
+            )),
+            _ => match primary.root_symbol() {
+                Some(name) if !parameters.contains(name) => Err(ParseError::new(&format!(
+                    "Function {} cannot reference {}, only its parameters",
+                    self.name, name
+                ))),
+                Some(_) => Ok(()),
+                None => check_reference(primary, names, defined, builtins),
+            },
+        })
+    }
+
+    pub fn call(&self, ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+        let mut frame = ctx.call_frame()?;
+        for (parameter, value) in self.parameters.iter().zip(args) {
+            frame.bind(parameter, value);
         }
+        // The input is deliberately withheld so the body can't observe anything but its arguments
+        self.body.evaluate(&frame, None)
     }
 }
 
+/// Callback used to visit every primary expression within a tree
+type PrimaryVisitor<'a> = dyn FnMut(&PrimaryExpression) -> Result<(), ParseError> + 'a;
+
 pub enum Expression {
     Logical(LogicalExpression),
 }
 impl Expression {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        match self {
+            Expression::Logical(expr) => expr.walk(visit),
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
             Expression::Logical(expr) => expr.evaluate(ctx, thing),
@@ -75,11 +442,21 @@ impl Expression {
 }
 
 pub enum LogicalExpression {
-    And(Box<EqualityExpression>, Box<EqualityExpression>),
-    Or(Box<EqualityExpression>, Box<EqualityExpression>),
-    Equality(EqualityExpression), // Value passthrough
+    And(Box<ComparisonExpression>, Box<ComparisonExpression>),
+    Or(Box<ComparisonExpression>, Box<ComparisonExpression>),
+    Comparison(ComparisonExpression), // Value passthrough
 }
 impl LogicalExpression {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        match self {
+            LogicalExpression::And(lhs, rhs) | LogicalExpression::Or(lhs, rhs) => {
+                lhs.walk(visit)?;
+                rhs.walk(visit)
+            }
+            LogicalExpression::Comparison(comp) => comp.walk(visit),
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
             LogicalExpression::And(lhs, rhs) => {
@@ -92,216 +469,766 @@ impl LogicalExpression {
                 let rhs = rhs.evaluate(ctx, thing)?;
                 Ok(EvalResultTypes::Boolean(lhs.is_truthy() || rhs.is_truthy()))
             }
-            LogicalExpression::Equality(eq) => eq.evaluate(ctx, thing),
+            LogicalExpression::Comparison(comp) => comp.evaluate(ctx, thing),
         }
     }
 }
 
-pub enum EqualityExpression {
-    Equal(Box<ComparisonExpression>, Box<ComparisonExpression>),
-    NotEqual(Box<ComparisonExpression>, Box<ComparisonExpression>),
-    Comparison(ComparisonExpression), // Value passthrough
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComparisonOperator {
+    Equal,
+    NotEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    /// `~=`, equal within the context's tolerance
+    ApproximatelyEqual,
+    /// `in`, whether the left value is contained in the right one
+    In,
+    NotIn,
 }
-impl EqualityExpression {
-    fn compare_eval_results(
+impl ComparisonOperator {
+    pub fn apply(
         &self,
-        lhs: EvalResultTypes,
-        rhs: EvalResultTypes,
-        equal: bool,
-    ) -> EvalResult {
-        let result = match (&lhs, &rhs) {
-            (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => lhs == rhs,
-            (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => &(*lhs as f64) == rhs,
-            (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => lhs == &(*rhs as f64),
-            (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => lhs == rhs,
-            (EvalResultTypes::Boolean(lhs), EvalResultTypes::Boolean(rhs)) => lhs == rhs,
-            (EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => lhs == rhs,
-            (EvalResultTypes::List(lhs), EvalResultTypes::List(rhs)) => lhs == rhs,
-            _ => return Err(EvaluationError::new("Cannot compare different types")),
-        };
+        ctx: &Context,
+        lhs: &EvalResultTypes,
+        rhs: &EvalResultTypes,
+    ) -> Result<bool, EvaluationError> {
+        match self {
+            ComparisonOperator::Equal => equals(lhs, rhs),
+            ComparisonOperator::NotEqual => equals(lhs, rhs).map(|equal| !equal),
+            ComparisonOperator::GreaterThan => Ok(order(ctx, lhs, rhs)? == Ordering::Greater),
+            ComparisonOperator::GreaterThanOrEqual => Ok(order(ctx, lhs, rhs)? != Ordering::Less),
+            ComparisonOperator::LessThan => Ok(order(ctx, lhs, rhs)? == Ordering::Less),
+            ComparisonOperator::LessThanOrEqual => Ok(order(ctx, lhs, rhs)? != Ordering::Greater),
+            ComparisonOperator::ApproximatelyEqual => match (to_float(lhs), to_float(rhs)) {
+                (Some(lhs), Some(rhs)) => Ok(ctx.tolerance().is_close(lhs, rhs)),
+                _ => Err(incomparable(lhs, rhs)),
+            },
+            ComparisonOperator::In => contains(lhs, rhs),
+            ComparisonOperator::NotIn => contains(lhs, rhs).map(|contained| !contained),
+        }
+    }
+}
+
+/// Whether an item is in a container: a substring of a string, a subsequence of bytes, an item of a
+/// list, a value of a set, a key of a mapping or an address within a network
+fn contains(item: &EvalResultTypes, container: &EvalResultTypes) -> Result<bool, EvaluationError> {
+    match (item, container) {
+        (EvalResultTypes::String(item), EvalResultTypes::String(container)) => {
+            Ok(container.contains(item.as_str()))
+        }
+        (EvalResultTypes::Bytes(item), EvalResultTypes::Bytes(container)) => Ok(item.is_empty()
+            || container
+                .windows(item.len())
+                .any(|window| window == item.as_slice())),
+        (item, EvalResultTypes::List(values)) => Ok(values.contains(item)),
+        (item, EvalResultTypes::Set(values)) => Ok(values.contains(item)),
+        (key, EvalResultTypes::Mapping(mapping)) => Ok(mapping.get(key).is_some()),
+        (EvalResultTypes::IpAddress(address), EvalResultTypes::Network(network)) => {
+            Ok(network.contains(address))
+        }
+        _ => Err(EvaluationError::new(&format!(
+            "Cannot check whether {} is in {}",
+            item.type_name(),
+            container.type_name()
+        ))),
+    }
+}
 
-        Ok(EvalResultTypes::Boolean(if equal {
-            result
+/// Ordering operators fail when NaN is involved rather than silently evaluating to false. `==`
+/// and `!=` follow IEEE 754 instead, where NaN is equal to nothing, including itself.
+pub fn order(
+    ctx: &Context,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+) -> Result<Ordering, EvaluationError> {
+    compare(ctx, lhs, rhs)?.ok_or_else(|| {
+        EvaluationError::new(&format!(
+            "Cannot order {} and {} because one is NaN",
+            lhs.type_name(),
+            rhs.type_name()
+        ))
+    })
+}
+
+fn incomparable(lhs: &EvalResultTypes, rhs: &EvalResultTypes) -> EvaluationError {
+    IncomparableTypesError::new(&format!(
+        "Cannot compare {} with {}",
+        lhs.type_name(),
+        rhs.type_name()
+    ))
+    .into()
+}
+
+fn equals(lhs: &EvalResultTypes, rhs: &EvalResultTypes) -> Result<bool, EvaluationError> {
+    Ok(relate(Comparison::Equality, lhs, rhs)? == Some(Ordering::Equal))
+}
+
+/// Orders two values of compatible types. Integers and floats compare by value, booleans order
+/// `false` before `true`, strings order by code point (or by the context's collation when one is
+/// configured), bytes order by value, lists order element-wise, with a shorter list ordering
+/// before any list it is a prefix of, and datetimes and timedeltas order chronologically. IP
+/// addresses order numerically, with every IPv4 address before any IPv6 one, and networks order by
+/// their first address and then by prefix length.
+///
+/// Returns `Ok(None)` when the values are unordered, which only happens when NaN is involved.
+pub fn compare(
+    ctx: &Context,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+) -> Result<Option<Ordering>, EvaluationError> {
+    relate(Comparison::Ordering(ctx), lhs, rhs)
+}
+
+/// What a comparison of two values is for
+#[derive(Clone, Copy)]
+enum Comparison<'a> {
+    /// Ordering the values, with strings collated as the context says
+    Ordering(&'a Context),
+    /// Checking the values for equality, which null, sets and mappings take part in too, and where
+    /// strings are equal only when they are identical
+    Equality,
+}
+
+/// The single table of which types compare with which, shared by `==`, the ordering operators
+/// and `PartialEq`. For an equality check, `Some(Ordering::Equal)` means equal and anything else
+/// means unequal.
+fn relate(
+    how: Comparison,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+) -> Result<Option<Ordering>, EvaluationError> {
+    if let Some(ordering) = compare_big_int(lhs, rhs) {
+        return Ok(ordering);
+    }
+    let equality = |equal: bool| {
+        Some(if equal {
+            Ordering::Equal
         } else {
-            !result
-        }))
+            Ordering::Less
+        })
+    };
+    match (how, lhs, rhs) {
+        // Any value may be checked against null, e.g. `discount == null`
+        (Comparison::Equality, EvalResultTypes::Null, other)
+        | (Comparison::Equality, other, EvalResultTypes::Null) => {
+            Ok(equality(matches!(other, EvalResultTypes::Null)))
+        }
+        (_, EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => Ok(Some(lhs.cmp(rhs))),
+        (_, EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
+            Ok((*lhs as f64).partial_cmp(rhs))
+        }
+        (_, EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
+            Ok(lhs.partial_cmp(&(*rhs as f64)))
+        }
+        (_, EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => Ok(lhs.partial_cmp(rhs)),
+        (_, EvalResultTypes::Boolean(lhs), EvalResultTypes::Boolean(rhs)) => Ok(Some(lhs.cmp(rhs))),
+        (Comparison::Ordering(ctx), EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => {
+            Ok(Some(ctx.collate(lhs, rhs)))
+        }
+        (Comparison::Equality, EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => {
+            Ok(Some(lhs.cmp(rhs)))
+        }
+        (_, EvalResultTypes::Bytes(lhs), EvalResultTypes::Bytes(rhs)) => Ok(Some(lhs.cmp(rhs))),
+        (_, EvalResultTypes::List(lhs_items), EvalResultTypes::List(rhs_items)) => {
+            for (lhs_item, rhs_item) in lhs_items.iter().zip(rhs_items) {
+                let ordering = match how {
+                    Comparison::Ordering(_) => relate(how, lhs_item, rhs_item)?,
+                    // Lists holding items that can't be compared are simply unequal
+                    Comparison::Equality => relate(how, lhs_item, rhs_item).unwrap_or(None),
+                };
+                match ordering {
+                    Some(Ordering::Equal) => continue,
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ok(Some(lhs_items.len().cmp(&rhs_items.len())))
+        }
+        (_, EvalResultTypes::Datetime(lhs), EvalResultTypes::Datetime(rhs)) => {
+            Ok(Some(lhs.cmp(rhs)))
+        }
+        (_, EvalResultTypes::Timedelta(lhs), EvalResultTypes::Timedelta(rhs)) => {
+            Ok(Some(lhs.cmp(rhs)))
+        }
+        (_, EvalResultTypes::Money(lhs), EvalResultTypes::Money(rhs)) => {
+            lhs.check_currency(rhs, "compare")?;
+            Ok(Some(lhs.amount.cmp(&rhs.amount)))
+        }
+        (_, EvalResultTypes::IpAddress(lhs), EvalResultTypes::IpAddress(rhs)) => {
+            Ok(Some(lhs.cmp(rhs)))
+        }
+        (_, EvalResultTypes::Network(lhs), EvalResultTypes::Network(rhs)) => Ok(Some(lhs.cmp(rhs))),
+        (_, EvalResultTypes::Decimal(decimal), other) if is_number(other) => {
+            Ok(compare_decimal(decimal, other))
+        }
+        (_, other, EvalResultTypes::Decimal(decimal)) if is_number(other) => {
+            Ok(compare_decimal(decimal, other).map(Ordering::reverse))
+        }
+        // Sets and mappings have no order, but can still be checked for equality
+        (Comparison::Equality, EvalResultTypes::Set(lhs), EvalResultTypes::Set(rhs)) => {
+            Ok(equality(lhs == rhs))
+        }
+        (Comparison::Equality, EvalResultTypes::Mapping(lhs), EvalResultTypes::Mapping(rhs)) => {
+            Ok(equality(lhs == rhs))
+        }
+        _ => Err(incomparable(lhs, rhs)),
     }
+}
 
-    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+/// Converts any number to the nearest float, or returns `None` for values that aren't numbers
+pub fn to_float(value: &EvalResultTypes) -> Option<f64> {
+    match value {
+        EvalResultTypes::Float(value) => Some(*value),
+        EvalResultTypes::Integer(value) => Some(*value as f64),
+        EvalResultTypes::BigInt(value) => value.to_f64(),
+        EvalResultTypes::Decimal(value) => value.to_f64(),
+        _ => None,
+    }
+}
+
+fn is_number(value: &EvalResultTypes) -> bool {
+    matches!(
+        value,
+        EvalResultTypes::Integer(_)
+            | EvalResultTypes::BigInt(_)
+            | EvalResultTypes::Float(_)
+            | EvalResultTypes::Decimal(_)
+    )
+}
+
+/// Orders a big integer against an integer or float exactly, rather than rounding the integer to
+/// the nearest float. Returns `None` when neither operand is a big integer, or the other operand
+/// is not an integer or float.
+fn compare_big_int(lhs: &EvalResultTypes, rhs: &EvalResultTypes) -> Option<Option<Ordering>> {
+    let exact = |big: &BigInt, other: &EvalResultTypes| match other {
+        EvalResultTypes::Integer(other) => Some(Some(big.cmp(&BigInt::from(*other)))),
+        EvalResultTypes::BigInt(other) => Some(Some(big.cmp(other))),
+        // Every big integer is finite, so it orders before infinity and is unordered with NaN
+        EvalResultTypes::Float(other) if !other.is_finite() => Some(0.0f64.partial_cmp(other)),
+        EvalResultTypes::Float(other) => {
+            let floor = BigInt::from_f64(other.floor())?;
+            Some(Some(match big.cmp(&floor) {
+                Ordering::Equal if other.fract() != 0.0 => Ordering::Less,
+                ordering => ordering,
+            }))
+        }
+        _ => None,
+    };
+    match (lhs, rhs) {
+        (EvalResultTypes::BigInt(lhs), rhs) => exact(lhs, rhs),
+        (lhs, EvalResultTypes::BigInt(rhs)) => {
+            exact(rhs, lhs).map(|ordering| ordering.map(Ordering::reverse))
+        }
+        _ => None,
+    }
+}
+
+/// Stores an integer result as an `Integer` when it fits, otherwise handles the overflow as the
+/// context is configured to
+pub fn integer_result(ctx: &Context, value: BigInt) -> EvalResult {
+    if let Some(value) = value.to_i64() {
+        return Ok(EvalResultTypes::Integer(value));
+    }
+    match ctx.integer_overflow() {
+        IntegerOverflow::Promote => Ok(EvalResultTypes::BigInt(value)),
+        IntegerOverflow::Error => Err(IntegerOverflowError::new("Integer overflow").into()),
+    }
+}
+
+/// Applies an integer operation when both operands are integers. The operation is first attempted
+/// on `i64`s, and only repeated on big integers when that overflows. Returns `None` when either
+/// operand is not an integer.
+fn integer_arithmetic(
+    ctx: &Context,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+    checked: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+) -> Option<EvalResult> {
+    let to_big = |value: &EvalResultTypes| match value {
+        EvalResultTypes::Integer(value) => Some(BigInt::from(*value)),
+        EvalResultTypes::BigInt(value) => Some(value.clone()),
+        _ => None,
+    };
+    if let (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) = (lhs, rhs) {
+        if let Some(value) = checked(*lhs, *rhs) {
+            return Some(Ok(EvalResultTypes::Integer(value)));
+        }
+    }
+    let (lhs, rhs) = (to_big(lhs)?, to_big(rhs)?);
+    Some(integer_result(ctx, big(lhs, rhs)))
+}
+
+/// Converts big integers to floats for operations whose result is a float, e.g. dividing or adding
+/// a float, matching how `Integer` operands promote
+fn widen_big_ints(
+    lhs: EvalResultTypes,
+    rhs: EvalResultTypes,
+) -> (EvalResultTypes, EvalResultTypes) {
+    let is_real = |value: &EvalResultTypes| {
+        matches!(
+            value,
+            EvalResultTypes::Integer(_) | EvalResultTypes::BigInt(_) | EvalResultTypes::Float(_)
+        )
+    };
+    if !is_real(&lhs) || !is_real(&rhs) {
+        return (lhs, rhs);
+    }
+    let widen = |value| match value {
+        EvalResultTypes::BigInt(value) => {
+            EvalResultTypes::Float(value.to_f64().unwrap_or(f64::NAN))
+        }
+        value => value,
+    };
+    (widen(lhs), widen(rhs))
+}
+
+/// Orders a decimal against another number. Floats that have no decimal equivalent (NaN, the
+/// infinities and magnitudes beyond the decimal range) are compared as floats instead.
+fn compare_decimal(lhs: &Decimal, rhs: &EvalResultTypes) -> Option<Ordering> {
+    match rhs {
+        EvalResultTypes::Decimal(rhs) => Some(lhs.cmp(rhs)),
+        EvalResultTypes::Integer(rhs) => Some(lhs.cmp(&Decimal::from(*rhs))),
+        // Big integers are beyond the range of a decimal when they fail to convert
+        EvalResultTypes::BigInt(rhs) => match big_int_to_decimal(rhs) {
+            Ok(rhs) => Some(lhs.cmp(&rhs)),
+            Err(_) if rhs.is_positive() => Some(Ordering::Less),
+            Err(_) => Some(Ordering::Greater),
+        },
+        EvalResultTypes::Float(rhs) => match float_to_decimal(*rhs) {
+            Ok(rhs) => Some(lhs.cmp(&rhs)),
+            Err(_) => lhs.to_f64()?.partial_cmp(rhs),
+        },
+        _ => None,
+    }
+}
+
+/// Converts a float to the shortest decimal that round-trips to it, so `0.1` becomes exactly `0.1`
+/// rather than the binary approximation a float actually holds.
+pub fn float_to_decimal(value: f64) -> Result<Decimal, EvaluationError> {
+    if !value.is_finite() {
+        return Err(EvaluationError::new(&format!(
+            "Cannot convert {} to a decimal",
+            value
+        )));
+    }
+    value
+        .to_string()
+        .parse()
+        .map_err(|_| EvaluationError::new("Decimal out of range"))
+}
+
+fn big_int_to_decimal(value: &BigInt) -> Result<Decimal, EvaluationError> {
+    value
+        .to_string()
+        .parse()
+        .map_err(|_| EvaluationError::new("Decimal out of range"))
+}
+
+/// Converts any number to a decimal, or returns `None` for values that aren't numbers
+fn to_decimal(value: &EvalResultTypes) -> Option<Result<Decimal, EvaluationError>> {
+    match value {
+        EvalResultTypes::Decimal(value) => Some(Ok(*value)),
+        EvalResultTypes::Integer(value) => Some(Ok(Decimal::from(*value))),
+        EvalResultTypes::BigInt(value) => Some(big_int_to_decimal(value)),
+        EvalResultTypes::Float(value) => Some(float_to_decimal(*value)),
+        _ => None,
+    }
+}
+
+/// Multiplies or divides an amount of money by a plain number
+fn scale_money(
+    money: &Money,
+    factor: &EvalResultTypes,
+    operation: fn(Decimal, Decimal) -> Option<Decimal>,
+) -> EvalResult {
+    let factor = to_decimal(factor)
+        .unwrap_or_else(|| Err(InvalidParameterTypeError::new("Expected number").into()))?;
+    Ok(EvalResultTypes::Money(
+        money.with_amount(operation(money.amount, factor))?,
+    ))
+}
+
+/// Applies a decimal operation when either operand is a decimal and the other is a number. Integers
+/// and floats are promoted to decimals, so `price * 1.1` stays exact when `price` is a decimal.
+/// Returns `None` when neither operand is a decimal.
+fn decimal_arithmetic(
+    verb: &str,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+    operation: fn(Decimal, Decimal) -> Option<Decimal>,
+) -> Option<EvalResult> {
+    let promote = |value: &EvalResultTypes| {
+        to_decimal(value).unwrap_or_else(|| Err(unsupported_operands(verb, lhs, rhs)))
+    };
+    let is_decimal = |value: &EvalResultTypes| matches!(value, EvalResultTypes::Decimal(_));
+    let is_money = |value: &EvalResultTypes| matches!(value, EvalResultTypes::Money(_));
+    // Money is scaled by decimals rather than combined with them
+    if !(is_decimal(lhs) || is_decimal(rhs)) || is_money(lhs) || is_money(rhs) {
+        return None;
+    }
+    Some(promote(lhs).and_then(|lhs| {
+        let rhs = promote(rhs)?;
+        operation(lhs, rhs)
+            .map(EvalResultTypes::Decimal)
+            .ok_or_else(|| EvaluationError::new("Decimal out of range"))
+    }))
+}
+
+/// A type that values can be tested for with `is`, e.g. `age is number`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+    Null,
+    Boolean,
+    /// Integers, floats and decimals
+    Number,
+    Integer,
+    Float,
+    Decimal,
+    String,
+    Bytes,
+    List,
+    Set,
+    Datetime,
+    Timedelta,
+    Money,
+    Mapping,
+    Ip,
+    Network,
+}
+impl ValueType {
+    pub fn matches(&self, value: &EvalResultTypes) -> bool {
         match self {
-            EqualityExpression::Equal(lhs, rhs) => self.compare_eval_results(
-                lhs.evaluate(ctx, thing)?,
-                rhs.evaluate(ctx, thing)?,
-                true,
-            ),
-            EqualityExpression::NotEqual(lhs, rhs) => self.compare_eval_results(
-                lhs.evaluate(ctx, thing)?,
-                rhs.evaluate(ctx, thing)?,
-                false,
-            ),
-            EqualityExpression::Comparison(comp) => comp.evaluate(ctx, thing),
+            ValueType::Number => is_number(value),
+            _ => value.type_name() == self.name(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::Null => "null",
+            ValueType::Boolean => "boolean",
+            ValueType::Number => "number",
+            ValueType::Integer => "integer",
+            ValueType::Float => "float",
+            ValueType::Decimal => "decimal",
+            ValueType::String => "string",
+            ValueType::Bytes => "bytes",
+            ValueType::List => "list",
+            ValueType::Set => "set",
+            ValueType::Datetime => "datetime",
+            ValueType::Timedelta => "timedelta",
+            ValueType::Money => "money",
+            ValueType::Mapping => "mapping",
+            ValueType::Ip => "ip",
+            ValueType::Network => "network",
+        }
+    }
+}
+impl FromStr for ValueType {
+    type Err = ParseError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "null" => Ok(ValueType::Null),
+            "boolean" => Ok(ValueType::Boolean),
+            "number" => Ok(ValueType::Number),
+            "integer" => Ok(ValueType::Integer),
+            "float" => Ok(ValueType::Float),
+            "decimal" => Ok(ValueType::Decimal),
+            "string" => Ok(ValueType::String),
+            "bytes" => Ok(ValueType::Bytes),
+            "list" => Ok(ValueType::List),
+            "set" => Ok(ValueType::Set),
+            "datetime" => Ok(ValueType::Datetime),
+            "timedelta" => Ok(ValueType::Timedelta),
+            "money" => Ok(ValueType::Money),
+            "mapping" => Ok(ValueType::Mapping),
+            "ip" => Ok(ValueType::Ip),
+            "network" => Ok(ValueType::Network),
+            _ => Err(ParseError::new(&format!("Unknown type {}", name))),
         }
     }
 }
 
 pub enum ComparisonExpression {
-    GreaterThan(Box<AdditiveExpression>, Box<AdditiveExpression>),
-    GreaterThanOrEqual(Box<AdditiveExpression>, Box<AdditiveExpression>),
-    LessThan(Box<AdditiveExpression>, Box<AdditiveExpression>),
-    LessThanOrEqual(Box<AdditiveExpression>, Box<AdditiveExpression>),
+    /// A chain such as `18 <= age < 65` holds when every adjacent pair satisfies its operator.
+    /// Like Python, each operand is evaluated at most once and evaluation stops at the first
+    /// comparison that fails.
+    Chain(
+        Box<AdditiveExpression>,
+        Vec<(ComparisonOperator, AdditiveExpression)>,
+    ),
+    /// `value is type`, or `value is not type` when negated
+    Is(Box<AdditiveExpression>, ValueType, bool),
     Additive(AdditiveExpression),
 }
 impl ComparisonExpression {
-    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
         match self {
-            ComparisonExpression::GreaterThan(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs > rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs as f64 > rhs))
-                    }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs > rhs as f64))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs > rhs))
-                    }
-                    _ => Err(EvaluationError::new("Cannot compare different types")),
-                }
-            }
-            ComparisonExpression::GreaterThanOrEqual(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs >= rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs as f64 >= rhs))
-                    }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs >= rhs as f64))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs >= rhs))
-                    }
-                    _ => Err(EvaluationError::new("Cannot compare different types")),
-                }
+            ComparisonExpression::Chain(first, rest) => {
+                first.walk(visit)?;
+                rest.iter().try_for_each(|(_, operand)| operand.walk(visit))
             }
-            ComparisonExpression::LessThan(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs < rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean((lhs as f64) < rhs))
-                    }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs < rhs as f64))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs < rhs))
+            ComparisonExpression::Is(additive, _, _) => additive.walk(visit),
+            ComparisonExpression::Additive(additive) => additive.walk(visit),
+        }
+    }
+
+    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+        match self {
+            ComparisonExpression::Chain(first, rest) => {
+                let mut lhs = first.evaluate(ctx, thing)?;
+                for (operator, operand) in rest {
+                    let rhs = operand.evaluate(ctx, thing)?;
+                    if !operator.apply(ctx, &lhs, &rhs)? {
+                        return Ok(EvalResultTypes::Boolean(false));
                     }
-                    _ => Err(EvaluationError::new("Cannot compare different types")),
+                    lhs = rhs;
                 }
+                Ok(EvalResultTypes::Boolean(true))
             }
-            ComparisonExpression::LessThanOrEqual(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs <= rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Boolean((lhs as f64) <= rhs))
+            ComparisonExpression::Is(additive, value_type, negated) => {
+                // An absent key is null, so that `discount is null` tests whether the input has it
+                let value = match additive.evaluate(ctx, thing) {
+                    Ok(value) => value,
+                    Err(_)
+                        if data_reference(additive)
+                            .is_some_and(|keys| ctx.is_missing(&keys, thing)) =>
+                    {
+                        EvalResultTypes::Null
                     }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs <= rhs as f64))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Boolean(lhs <= rhs))
-                    }
-                    _ => Err(EvaluationError::new("Cannot compare different types")),
-                }
+                    Err(err) => return Err(err),
+                };
+                Ok(EvalResultTypes::Boolean(
+                    value_type.matches(&value) != *negated,
+                ))
             }
             ComparisonExpression::Additive(additive) => additive.evaluate(ctx, thing),
         }
     }
 }
 
+/// The keys an expression reads from the input when it is nothing but a symbol or attribute
+fn data_reference(additive: &AdditiveExpression) -> Option<Vec<&str>> {
+    let AdditiveExpression::Factor(FactorExpression::Unary(UnaryExpression::Primary(primary))) =
+        additive
+    else {
+        return None;
+    };
+    match primary {
+        PrimaryExpression::Symbol(name) => Some(vec![name.as_str()]),
+        PrimaryExpression::Attribute(path) => Some(path.split('.').collect()),
+        _ => None,
+    }
+}
+
 pub enum AdditiveExpression {
     Add(FactorExpression, FactorExpression),
     Subtract(FactorExpression, FactorExpression),
+    /// `lhs | rhs`, the union of two sets
+    Union(FactorExpression, FactorExpression),
+    /// `lhs & rhs`, the intersection of two sets
+    Intersection(FactorExpression, FactorExpression),
+    /// `lhs ^ rhs`, the values in exactly one of two sets
+    SymmetricDifference(FactorExpression, FactorExpression),
     Factor(FactorExpression),
 }
 impl AdditiveExpression {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        match self {
+            AdditiveExpression::Add(lhs, rhs)
+            | AdditiveExpression::Subtract(lhs, rhs)
+            | AdditiveExpression::Union(lhs, rhs)
+            | AdditiveExpression::Intersection(lhs, rhs)
+            | AdditiveExpression::SymmetricDifference(lhs, rhs) => {
+                lhs.walk(visit)?;
+                rhs.walk(visit)
+            }
+            AdditiveExpression::Factor(factor) => factor.walk(visit),
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
             AdditiveExpression::Add(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs + rhs))
-                    }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs + (rhs as f64)))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float((lhs as f64) + rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Integer(lhs + rhs))
-                    }
-                    // TODO: Do we implement string/collection concatenation?
-                    // (EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => {
-                    //     Ok(EvalResultTypes::String(format!("{}{}", lhs, rhs)))
-                    // }
-                    _ => Err(EvaluationError::new("Cannot add different types")),
-                }
+                add(ctx, lhs.evaluate(ctx, thing)?, rhs.evaluate(ctx, thing)?)
             }
             AdditiveExpression::Subtract(lhs, rhs) => {
-                let lhs = lhs.evaluate(ctx, thing)?;
-                let rhs = rhs.evaluate(ctx, thing)?;
-                match (lhs, rhs) {
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs - rhs))
-                    }
-                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs - (rhs as f64)))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float((lhs as f64) - rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Integer(lhs - rhs))
-                    }
-                    _ => Err(EvaluationError::new("Cannot subtract different types")),
-                }
+                subtract(ctx, lhs.evaluate(ctx, thing)?, rhs.evaluate(ctx, thing)?)
+            }
+            AdditiveExpression::Union(lhs, rhs) => {
+                set_operation("take the union of", ctx, thing, lhs, rhs, ValueSet::union)
+            }
+            AdditiveExpression::Intersection(lhs, rhs) => {
+                set_operation("intersect", ctx, thing, lhs, rhs, ValueSet::intersection)
             }
+            AdditiveExpression::SymmetricDifference(lhs, rhs) => set_operation(
+                "take the symmetric difference of",
+                ctx,
+                thing,
+                lhs,
+                rhs,
+                ValueSet::symmetric_difference,
+            ),
             AdditiveExpression::Factor(factor) => factor.evaluate(ctx, thing),
         }
     }
 }
 
+// Kept out of `AdditiveExpression::evaluate` so that its stack frame, which every nested
+// expression and function call adds to, stays small
+pub fn add(ctx: &Context, lhs: EvalResultTypes, rhs: EvalResultTypes) -> EvalResult {
+    if let Some(result) = decimal_arithmetic("add", &lhs, &rhs, Decimal::checked_add) {
+        return result;
+    }
+    if let Some(result) =
+        integer_arithmetic(ctx, &lhs, &rhs, i64::checked_add, |lhs, rhs| lhs + rhs)
+    {
+        return result;
+    }
+    let (lhs, rhs) = widen_big_ints(lhs, rhs);
+    match (lhs, rhs) {
+        (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
+            Ok(EvalResultTypes::Float(lhs + rhs))
+        }
+        (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
+            Ok(EvalResultTypes::Float(lhs + (rhs as f64)))
+        }
+        (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
+            Ok(EvalResultTypes::Float((lhs as f64) + rhs))
+        }
+        (EvalResultTypes::String(lhs), EvalResultTypes::String(rhs)) => {
+            ctx.check_sequence_length(lhs.len() + rhs.len())?;
+            Ok(EvalResultTypes::String(lhs + &rhs))
+        }
+        (EvalResultTypes::Bytes(mut lhs), EvalResultTypes::Bytes(rhs)) => {
+            ctx.check_sequence_length(lhs.len() + rhs.len())?;
+            lhs.extend(rhs);
+            Ok(EvalResultTypes::Bytes(lhs))
+        }
+        (EvalResultTypes::List(mut lhs), EvalResultTypes::List(rhs)) => {
+            ctx.check_sequence_length(lhs.len() + rhs.len())?;
+            lhs.extend(rhs);
+            Ok(EvalResultTypes::List(lhs))
+        }
+        (EvalResultTypes::Datetime(lhs), EvalResultTypes::Timedelta(rhs))
+        | (EvalResultTypes::Timedelta(rhs), EvalResultTypes::Datetime(lhs)) => lhs
+            .checked_add_signed(rhs)
+            .map(EvalResultTypes::Datetime)
+            .ok_or_else(|| EvaluationError::new("Datetime out of range")),
+        (EvalResultTypes::Timedelta(lhs), EvalResultTypes::Timedelta(rhs)) => lhs
+            .checked_add(&rhs)
+            .map(EvalResultTypes::Timedelta)
+            .ok_or_else(|| EvaluationError::new("Timedelta out of range")),
+        (EvalResultTypes::Money(lhs), EvalResultTypes::Money(rhs)) => {
+            lhs.check_currency(&rhs, "add")?;
+            let amount = lhs.amount.checked_add(rhs.amount);
+            Ok(EvalResultTypes::Money(lhs.with_amount(amount)?))
+        }
+        (lhs, rhs) => Err(unsupported_operands("add", &lhs, &rhs)),
+    }
+}
+
+fn subtract(ctx: &Context, lhs: EvalResultTypes, rhs: EvalResultTypes) -> EvalResult {
+    if let Some(result) = decimal_arithmetic("subtract", &lhs, &rhs, Decimal::checked_sub) {
+        return result;
+    }
+    if let Some(result) =
+        integer_arithmetic(ctx, &lhs, &rhs, i64::checked_sub, |lhs, rhs| lhs - rhs)
+    {
+        return result;
+    }
+    let (lhs, rhs) = widen_big_ints(lhs, rhs);
+    match (lhs, rhs) {
+        (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
+            Ok(EvalResultTypes::Float(lhs - rhs))
+        }
+        (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
+            Ok(EvalResultTypes::Float(lhs - (rhs as f64)))
+        }
+        (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
+            Ok(EvalResultTypes::Float((lhs as f64) - rhs))
+        }
+        (EvalResultTypes::Datetime(lhs), EvalResultTypes::Datetime(rhs)) => {
+            Ok(EvalResultTypes::Timedelta(lhs.signed_duration_since(rhs)))
+        }
+        (EvalResultTypes::Datetime(lhs), EvalResultTypes::Timedelta(rhs)) => lhs
+            .checked_sub_signed(rhs)
+            .map(EvalResultTypes::Datetime)
+            .ok_or_else(|| EvaluationError::new("Datetime out of range")),
+        (EvalResultTypes::Timedelta(lhs), EvalResultTypes::Timedelta(rhs)) => lhs
+            .checked_sub(&rhs)
+            .map(EvalResultTypes::Timedelta)
+            .ok_or_else(|| EvaluationError::new("Timedelta out of range")),
+        (EvalResultTypes::Money(lhs), EvalResultTypes::Money(rhs)) => {
+            lhs.check_currency(&rhs, "subtract")?;
+            let amount = lhs.amount.checked_sub(rhs.amount);
+            Ok(EvalResultTypes::Money(lhs.with_amount(amount)?))
+        }
+        (EvalResultTypes::Set(lhs), EvalResultTypes::Set(rhs)) => {
+            Ok(EvalResultTypes::Set(lhs.difference(&rhs)))
+        }
+        (lhs, rhs) => Err(unsupported_operands("subtract", &lhs, &rhs)),
+    }
+}
+
+/// Evaluates both operands of a set operator, which is only defined for a pair of sets
+fn set_operation(
+    verb: &str,
+    ctx: &Context,
+    thing: Option<&PyDict>,
+    lhs: &FactorExpression,
+    rhs: &FactorExpression,
+    op: fn(&ValueSet, &ValueSet) -> ValueSet,
+) -> EvalResult {
+    match (lhs.evaluate(ctx, thing)?, rhs.evaluate(ctx, thing)?) {
+        (EvalResultTypes::Set(lhs), EvalResultTypes::Set(rhs)) => {
+            Ok(EvalResultTypes::Set(op(&lhs, &rhs)))
+        }
+        (lhs, rhs) => Err(unsupported_operands(verb, &lhs, &rhs)),
+    }
+}
+
 pub enum FactorExpression {
     Multiply(UnaryExpression, UnaryExpression),
     Divide(UnaryExpression, UnaryExpression),
+    FloorDivide(UnaryExpression, UnaryExpression),
     Modulo(UnaryExpression, UnaryExpression),
     Unary(UnaryExpression),
 }
 impl FactorExpression {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        match self {
+            FactorExpression::Multiply(lhs, rhs)
+            | FactorExpression::Divide(lhs, rhs)
+            | FactorExpression::FloorDivide(lhs, rhs)
+            | FactorExpression::Modulo(lhs, rhs) => {
+                lhs.walk(visit)?;
+                rhs.walk(visit)
+            }
+            FactorExpression::Unary(unary) => unary.walk(visit),
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
             FactorExpression::Multiply(lhs, rhs) => {
                 let lhs = lhs.evaluate(ctx, thing)?;
                 let rhs = rhs.evaluate(ctx, thing)?;
+                if let Some(result) =
+                    decimal_arithmetic("multiply", &lhs, &rhs, Decimal::checked_mul)
+                {
+                    return result;
+                }
+                if let Some(result) =
+                    integer_arithmetic(ctx, &lhs, &rhs, i64::checked_mul, |lhs, rhs| lhs * rhs)
+                {
+                    return result;
+                }
+                let (lhs, rhs) = widen_big_ints(lhs, rhs);
                 match (lhs, rhs) {
                     (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
                         Ok(EvalResultTypes::Float(lhs * rhs))
@@ -312,10 +1239,17 @@ impl FactorExpression {
                     (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
                         Ok(EvalResultTypes::Float((lhs as f64) * rhs))
                     }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Integer(lhs * rhs))
+                    (sequence @ (EvalResultTypes::String(_) | EvalResultTypes::List(_)), count)
+                    | (count, sequence @ (EvalResultTypes::String(_) | EvalResultTypes::List(_))) => {
+                        repeat(ctx, sequence, &count)
                     }
-                    _ => Err(EvaluationError::new("Cannot multiply different types")),
+                    (EvalResultTypes::Money(money), factor)
+                    | (factor, EvalResultTypes::Money(money))
+                        if is_number(&factor) =>
+                    {
+                        scale_money(&money, &factor, Decimal::checked_mul)
+                    }
+                    (lhs, rhs) => Err(unsupported_operands("multiply", &lhs, &rhs)),
                 }
             }
             FactorExpression::Divide(lhs, rhs) => {
@@ -324,6 +1258,11 @@ impl FactorExpression {
                 if rhs == EvalResultTypes::Integer(0) || rhs == EvalResultTypes::Float(0.0) {
                     return Err(EvaluationError::new("Cannot divide by zero"));
                 }
+                if let Some(result) = decimal_arithmetic("divide", &lhs, &rhs, Decimal::checked_div)
+                {
+                    return result;
+                }
+                let (lhs, rhs) = widen_big_ints(lhs, rhs);
                 match (lhs, rhs) {
                     (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
                         Ok(EvalResultTypes::Float(lhs / rhs))
@@ -338,7 +1277,54 @@ impl FactorExpression {
                         // Cast to float to preserve precision
                         Ok(EvalResultTypes::Float((lhs as f64) / (rhs as f64)))
                     }
-                    _ => Err(EvaluationError::new("Cannot divide different types")),
+                    (EvalResultTypes::Money(lhs), EvalResultTypes::Money(rhs)) => {
+                        lhs.check_currency(&rhs, "divide")?;
+                        if rhs.amount.is_zero() {
+                            return Err(EvaluationError::new("Cannot divide by zero"));
+                        }
+                        lhs.amount
+                            .checked_div(rhs.amount)
+                            .map(EvalResultTypes::Decimal)
+                            .ok_or_else(|| EvaluationError::new("Decimal out of range"))
+                    }
+                    (EvalResultTypes::Money(money), divisor) if is_number(&divisor) => {
+                        scale_money(&money, &divisor, Decimal::checked_div)
+                    }
+                    (lhs, rhs) => Err(unsupported_operands("divide", &lhs, &rhs)),
+                }
+            }
+            FactorExpression::FloorDivide(lhs, rhs) => {
+                let lhs = lhs.evaluate(ctx, thing)?;
+                let rhs = rhs.evaluate(ctx, thing)?;
+                if rhs == EvalResultTypes::Integer(0) || rhs == EvalResultTypes::Float(0.0) {
+                    return Err(EvaluationError::new("Cannot divide by zero"));
+                }
+                // Decimals floor like every other number rather than truncating like Python's
+                // `decimal.Decimal`
+                if let Some(result) = decimal_arithmetic("divide", &lhs, &rhs, |lhs, rhs| {
+                    lhs.checked_div(rhs).map(|quotient| quotient.floor())
+                }) {
+                    return result;
+                }
+                if let Some(result) =
+                    integer_arithmetic(ctx, &lhs, &rhs, floor_divide, |lhs, rhs| {
+                        lhs.div_floor(&rhs)
+                    })
+                {
+                    return result;
+                }
+                let (lhs, rhs) = widen_big_ints(lhs, rhs);
+                match (lhs, rhs) {
+                    (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
+                        Ok(EvalResultTypes::Float((lhs / rhs).floor()))
+                    }
+                    (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
+                        Ok(EvalResultTypes::Float((lhs / (rhs as f64)).floor()))
+                    }
+                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
+                        Ok(EvalResultTypes::Float(((lhs as f64) / rhs).floor()))
+                    }
+                    (lhs, rhs) => Err(unsupported_operands("divide", &lhs, &rhs)),
                 }
             }
             FactorExpression::Modulo(lhs, rhs) => {
@@ -347,20 +1333,39 @@ impl FactorExpression {
                 if rhs == EvalResultTypes::Integer(0) || rhs == EvalResultTypes::Float(0.0) {
                     return Err(EvaluationError::new("Cannot modulo by zero"));
                 }
+                // The remainder takes the sign of the divisor so that `a == (a // b) * b + a % b`
+                if let Some(result) = decimal_arithmetic("modulo", &lhs, &rhs, |lhs, rhs| {
+                    lhs.checked_rem(rhs).map(|remainder| {
+                        if !remainder.is_zero()
+                            && remainder.is_sign_negative() != rhs.is_sign_negative()
+                        {
+                            remainder + rhs
+                        } else {
+                            remainder
+                        }
+                    })
+                }) {
+                    return result;
+                }
+                if let Some(result) =
+                    integer_arithmetic(ctx, &lhs, &rhs, floor_modulo, |lhs, rhs| {
+                        lhs.mod_floor(&rhs)
+                    })
+                {
+                    return result;
+                }
+                let (lhs, rhs) = widen_big_ints(lhs, rhs);
                 match (lhs, rhs) {
                     (EvalResultTypes::Float(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs % rhs))
+                        Ok(EvalResultTypes::Float(float_modulo(lhs, rhs)))
                     }
                     (EvalResultTypes::Float(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Float(lhs % (rhs as f64)))
+                        Ok(EvalResultTypes::Float(float_modulo(lhs, rhs as f64)))
                     }
                     (EvalResultTypes::Integer(lhs), EvalResultTypes::Float(rhs)) => {
-                        Ok(EvalResultTypes::Float((lhs as f64) % rhs))
-                    }
-                    (EvalResultTypes::Integer(lhs), EvalResultTypes::Integer(rhs)) => {
-                        Ok(EvalResultTypes::Integer(lhs % rhs))
+                        Ok(EvalResultTypes::Float(float_modulo(lhs as f64, rhs)))
                     }
-                    _ => Err(EvaluationError::new("Cannot modulo different types")),
+                    (lhs, rhs) => Err(unsupported_operands("modulo", &lhs, &rhs)),
                 }
             }
             FactorExpression::Unary(unary) => unary.evaluate(ctx, thing),
@@ -368,12 +1373,89 @@ impl FactorExpression {
     }
 }
 
+/// Rounds towards negative infinity like Python rather than towards zero
+fn floor_divide(lhs: i64, rhs: i64) -> Option<i64> {
+    let quotient = lhs.checked_div(rhs)?;
+    if lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+        quotient.checked_sub(1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// The remainder of flooring division, which like Python's takes the sign of the divisor
+fn floor_modulo(lhs: i64, rhs: i64) -> Option<i64> {
+    let remainder = lhs.checked_rem(rhs)?;
+    if remainder != 0 && (remainder < 0) != (rhs < 0) {
+        remainder.checked_add(rhs)
+    } else {
+        Some(remainder)
+    }
+}
+
+/// Python's float `%`, whose result takes the sign of the divisor, even when it is zero
+fn float_modulo(lhs: f64, rhs: f64) -> f64 {
+    let remainder = lhs % rhs;
+    if remainder == 0.0 {
+        0.0_f64.copysign(rhs)
+    } else if (remainder < 0.0) != (rhs < 0.0) {
+        remainder + rhs
+    } else {
+        remainder
+    }
+}
+
+fn unsupported_operands(
+    verb: &str,
+    lhs: &EvalResultTypes,
+    rhs: &EvalResultTypes,
+) -> EvaluationError {
+    EvaluationError::new(&format!(
+        "Cannot {} {} and {}",
+        verb,
+        lhs.type_name(),
+        rhs.type_name()
+    ))
+}
+
+/// Repeats a string or list `count` times, like Python's `"ab" * 3`. Non-positive counts produce an
+/// empty sequence.
+fn repeat(ctx: &Context, sequence: EvalResultTypes, count: &EvalResultTypes) -> EvalResult {
+    let times = match count {
+        EvalResultTypes::Integer(count) => *count,
+        // A float that holds a whole number is accepted too, e.g. `3.0`
+        EvalResultTypes::Float(count) if count.fract() == 0.0 => *count as i64,
+        _ => return Err(unsupported_operands("multiply", &sequence, count)),
+    };
+    let times = usize::try_from(times).unwrap_or(0);
+    match sequence {
+        EvalResultTypes::String(value) => {
+            ctx.check_sequence_length(value.len().saturating_mul(times))?;
+            Ok(EvalResultTypes::String(value.repeat(times)))
+        }
+        EvalResultTypes::List(items) => {
+            ctx.check_sequence_length(items.len().saturating_mul(times))?;
+            let repeated = (0..times).flat_map(|_| items.iter().cloned()).collect();
+            Ok(EvalResultTypes::List(repeated))
+        }
+        other => Err(unsupported_operands("multiply", &other, count)),
+    }
+}
+
 pub enum UnaryExpression {
     Not(PrimaryExpression),
     Minus(PrimaryExpression),
     Primary(PrimaryExpression),
 }
 impl UnaryExpression {
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        match self {
+            UnaryExpression::Not(primary)
+            | UnaryExpression::Minus(primary)
+            | UnaryExpression::Primary(primary) => primary.walk(visit),
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
             UnaryExpression::Not(primary) => {
@@ -387,7 +1469,17 @@ impl UnaryExpression {
                 let primary = primary.evaluate(ctx, thing)?;
                 match primary {
                     EvalResultTypes::Float(value) => Ok(EvalResultTypes::Float(-value)),
-                    EvalResultTypes::Integer(value) => Ok(EvalResultTypes::Integer(-value)),
+                    EvalResultTypes::Integer(value) => match value.checked_neg() {
+                        Some(value) => Ok(EvalResultTypes::Integer(value)),
+                        None => integer_result(ctx, -BigInt::from(value)),
+                    },
+                    EvalResultTypes::BigInt(value) => integer_result(ctx, -value),
+                    EvalResultTypes::Timedelta(value) => Ok(EvalResultTypes::Timedelta(-value)),
+                    EvalResultTypes::Decimal(value) => Ok(EvalResultTypes::Decimal(-value)),
+                    EvalResultTypes::Money(value) => Ok(EvalResultTypes::Money(Money {
+                        amount: -value.amount,
+                        ..value
+                    })),
                     _ => Err(EvaluationError::new("Cannot negate non-numeric value")),
                 }
             }
@@ -397,30 +1489,103 @@ impl UnaryExpression {
 }
 
 pub enum PrimaryExpression {
-    Float(f64),
+    /// A whole-number literal, e.g. `42` or `0xff`
+    Integer(BigInt),
+    /// A numeric literal with a fraction or exponent. The decimal form is kept for contexts that
+    /// treat every literal as a decimal, and is `None` when the literal has no decimal equivalent.
+    Float(f64, Option<Decimal>),
+    /// A literal with an `m` suffix, e.g. `1.10m`
+    Decimal(Decimal),
+    /// A literal such as `m"12.50 USD"`
+    Money(Money),
+    Null,
     True,
     False,
     Symbol(String),
     Attribute(String),
     String(String),
+    /// A literal such as `b"\x89PNG"` or `x"deadbeef"`
+    Bytes(Vec<u8>),
     Grouping(Box<Expression>),
     List(Vec<Expression>),
+    /// A literal such as `{1, 2, 3}`. Duplicate values are dropped.
+    Set(Vec<Expression>),
+    Call(Box<FunctionCall>),
+    Datetime(DateTime<FixedOffset>),
+    Timedelta(TimeDelta),
+    /// A builtin method applied to the value of an arbitrary expression, e.g. `(end - start).days`
+    Member(Box<PrimaryExpression>, String),
+    MethodCall(Box<MethodCall>),
+    /// A value provided by the engine rather than the input, e.g. `$now`
+    Builtin(String),
+    /// A builtin called by name, e.g. `$min(a, b)`, which a definition can't shadow
+    BuiltinCall(Box<FunctionCall>),
 }
 impl PrimaryExpression {
+    /// Visits this expression and every primary expression nested within it
+    pub fn walk(&self, visit: &mut PrimaryVisitor<'_>) -> Result<(), ParseError> {
+        visit(self)?;
+        match self {
+            PrimaryExpression::Grouping(expr) => expr.walk(visit),
+            PrimaryExpression::List(exprs) | PrimaryExpression::Set(exprs) => {
+                exprs.iter().try_for_each(|expr| expr.walk(visit))
+            }
+            PrimaryExpression::Call(call) | PrimaryExpression::BuiltinCall(call) => {
+                call.args.iter().try_for_each(|expr| expr.walk(visit))
+            }
+            PrimaryExpression::Member(primary, _) => primary.walk(visit),
+            PrimaryExpression::MethodCall(call) => {
+                call.receiver.walk(visit)?;
+                call.args.iter().try_for_each(|expr| expr.walk(visit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The symbol a value is looked up by, e.g. `person` for both `person` and `person.name`
+    pub fn root_symbol(&self) -> Option<&str> {
+        match self {
+            PrimaryExpression::Symbol(name) => Some(name),
+            PrimaryExpression::Attribute(raw_attr) => raw_attr.split('.').next(),
+            _ => None,
+        }
+    }
+
     pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
         match self {
-            PrimaryExpression::Float(value) => Ok(EvalResultTypes::Float(*value)),
+            PrimaryExpression::Integer(value) if ctx.decimal_literals() => {
+                match big_int_to_decimal(value) {
+                    Ok(decimal) => Ok(EvalResultTypes::Decimal(decimal)),
+                    Err(_) => Err(EvaluationError::new(&format!(
+                        "Literal {} cannot be represented as a decimal",
+                        value
+                    ))),
+                }
+            }
+            PrimaryExpression::Integer(value) => integer_result(ctx, value.clone()),
+            PrimaryExpression::Float(value, decimal) => match (ctx.decimal_literals(), decimal) {
+                (false, _) => Ok(EvalResultTypes::Float(*value)),
+                (true, Some(decimal)) => Ok(EvalResultTypes::Decimal(*decimal)),
+                (true, None) => Err(EvaluationError::new(&format!(
+                    "Literal {} cannot be represented as a decimal",
+                    value
+                ))),
+            },
+            PrimaryExpression::Decimal(value) => Ok(EvalResultTypes::Decimal(*value)),
+            PrimaryExpression::Money(value) => Ok(EvalResultTypes::Money(value.clone())),
+            PrimaryExpression::Null => Ok(EvalResultTypes::Null),
             PrimaryExpression::True => Ok(EvalResultTypes::Boolean(true)),
             PrimaryExpression::False => Ok(EvalResultTypes::Boolean(false)),
-            PrimaryExpression::Symbol(str) => ctx
-                .resolve(str, thing)
-                .map_err(|err| EvaluationError::new(&err.to_string())),
+            PrimaryExpression::Symbol(str) => {
+                ctx.resolve(str, thing).map_err(EvaluationError::from)
+            }
             PrimaryExpression::Attribute(raw_attr) => {
                 let keys: Vec<&str> = raw_attr.split('.').collect();
                 ctx.resolve_attribute(&keys, thing)
-                    .map_err(|err| EvaluationError::new(&err.to_string()))
+                    .map_err(EvaluationError::from)
             }
             PrimaryExpression::String(str) => Ok(EvalResultTypes::String(str.clone())),
+            PrimaryExpression::Bytes(value) => Ok(EvalResultTypes::Bytes(value.clone())),
             PrimaryExpression::Grouping(expr) => expr.evaluate(ctx, thing),
             PrimaryExpression::List(exprs) => {
                 let mut result = Vec::new();
@@ -429,6 +1594,146 @@ impl PrimaryExpression {
                 }
                 Ok(EvalResultTypes::List(result))
             }
+            PrimaryExpression::Set(exprs) => {
+                let mut result = ValueSet::new();
+                for expr in exprs {
+                    result.insert(expr.evaluate(ctx, thing)?)?;
+                }
+                Ok(EvalResultTypes::Set(result))
+            }
+            PrimaryExpression::Call(call) => call.evaluate(ctx, thing),
+            PrimaryExpression::Datetime(value) => Ok(EvalResultTypes::Datetime(*value)),
+            PrimaryExpression::Timedelta(value) => Ok(EvalResultTypes::Timedelta(*value)),
+            PrimaryExpression::Member(primary, name) => {
+                if let Some(namespace) = builtin_namespace(primary, ctx.builtins()) {
+                    let qualified_name = format!("{}.{}", namespace, name);
+                    return match ctx.builtins().symbol(&qualified_name) {
+                        Some(symbol) => symbol.call(ctx, vec![]),
+                        None => Err(EvaluationError::new(&format!(
+                            "Unknown builtin symbol ${}",
+                            qualified_name
+                        ))),
+                    };
+                }
+                let value = primary.evaluate(ctx, thing)?;
+                // Like attributes, keys of a mapping take precedence over builtin properties
+                if let EvalResultTypes::Mapping(mapping) = &value {
+                    if let Some(item) = mapping.get(&EvalResultTypes::String(name.clone())) {
+                        return Ok(item.clone());
+                    }
+                }
+                match ctx.builtins().property(name) {
+                    Some(property) => property.call(ctx, vec![value]),
+                    None => Err(EvaluationError::new(&format!("Unknown property {}", name))),
+                }
+            }
+            PrimaryExpression::MethodCall(call) => call.evaluate(ctx, thing),
+            PrimaryExpression::Builtin(name) => match ctx.builtins().symbol(name) {
+                Some(symbol) => symbol.call(ctx, vec![]),
+                None if ctx.builtins().is_namespace(name) => Err(EvaluationError::new(&format!(
+                    "${} is a namespace, not a value",
+                    name
+                ))),
+                None => Err(EvaluationError::new(&format!(
+                    "Unknown builtin symbol ${}",
+                    name
+                ))),
+            },
+            PrimaryExpression::BuiltinCall(call) => match ctx.builtins().callable(&call.name) {
+                Some(builtin) => builtin.call(ctx, call.evaluate_args(ctx, thing)?),
+                None => Err(EvaluationError::new(&format!(
+                    "Unknown builtin function ${}",
+                    call.name
+                ))),
+            },
+        }
+    }
+}
+
+pub struct FunctionCall {
+    pub name: String,
+    pub args: Vec<Expression>,
+}
+impl FunctionCall {
+    pub fn new(name: String, args: Vec<Expression>) -> Self {
+        FunctionCall { name, args }
+    }
+
+    fn evaluate_args(
+        &self,
+        ctx: &Context,
+        thing: Option<&PyDict>,
+    ) -> Result<Vec<EvalResultTypes>, EvaluationError> {
+        let mut args = Vec::with_capacity(self.args.len());
+        for expr in &self.args {
+            args.push(expr.evaluate(ctx, thing)?);
+        }
+        Ok(args)
+    }
+
+    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+        let function = ctx.resolve_function(&self.name);
+        let builtin = ctx.builtins().callable(&self.name);
+        let args = self.evaluate_args(ctx, thing)?;
+        // Definitions shadow builtins of the same name
+        match (function, builtin) {
+            (Ok(function), _) => function.call(ctx, args),
+            (Err(_), Some(builtin)) => builtin.call(ctx, args),
+            (Err(err), None) => Err(err),
+        }
+    }
+}
+
+/// A builtin called with method syntax, e.g. `created_at.to_tz("America/Denver")`. The receiver is
+/// passed to the builtin as its first argument.
+pub struct MethodCall {
+    pub receiver: PrimaryExpression,
+    pub name: String,
+    pub args: Vec<Expression>,
+}
+impl MethodCall {
+    pub fn new(receiver: PrimaryExpression, name: String, args: Vec<Expression>) -> Self {
+        MethodCall {
+            receiver,
+            name,
+            args,
+        }
+    }
+
+    /// Splits an attribute such as `order.created_at.to_tz` into its receiver and method name
+    pub fn from_attribute(raw_attr: &str, args: Vec<Expression>) -> Self {
+        let (path, name) = raw_attr.rsplit_once('.').unwrap_or(("", raw_attr));
+        let receiver = if path.contains('.') {
+            PrimaryExpression::Attribute(path.to_string())
+        } else {
+            PrimaryExpression::Symbol(path.to_string())
+        };
+        MethodCall::new(receiver, name.to_string(), args)
+    }
+
+    /// The builtin called and whether the receiver is passed as its first argument, which it
+    /// isn't when the receiver is a namespace, e.g. `$math.round(x)`
+    fn target<'a>(&self, builtins: &'a BuiltinRegistry) -> (Option<&'a Builtin>, bool) {
+        match builtin_namespace(&self.receiver, builtins) {
+            Some(namespace) => (
+                builtins.callable(&format!("{}.{}", namespace, self.name)),
+                false,
+            ),
+            None => (builtins.callable(&self.name), true),
+        }
+    }
+
+    pub fn evaluate(&self, ctx: &Context, thing: Option<&PyDict>) -> EvalResult {
+        let (builtin, receiver) = self.target(ctx.builtins());
+        let builtin = builtin
+            .ok_or_else(|| EvaluationError::new(&format!("Unknown method {}", self.name)))?;
+        let mut args = Vec::with_capacity(self.args.len() + 1);
+        if receiver {
+            args.push(self.receiver.evaluate(ctx, thing)?);
+        }
+        for expr in &self.args {
+            args.push(expr.evaluate(ctx, thing)?);
         }
+        builtin.call(ctx, args)
     }
 }
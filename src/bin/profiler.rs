@@ -10,9 +10,9 @@ fn main() {
         let dict = pyo3::types::PyDict::new(py);
         populate_dict(dict, 1000);
         let sub_dict1 = pyo3::types::PyDict::new(py);
-        populate_dict(&sub_dict1, 1000);
+        populate_dict(sub_dict1, 1000);
         let sub_dict2 = pyo3::types::PyDict::new(py);
-        populate_dict(&sub_dict2, 1000);
+        populate_dict(sub_dict2, 1000);
         dict.set_item("dict1", sub_dict1).unwrap();
         dict.set_item("dict2", sub_dict2).unwrap();
         for _ in 0..100_000 {
@@ -23,6 +23,6 @@ fn main() {
 
 fn populate_dict(dict: &PyDict, count: usize) {
     for i in 0..count {
-        dict.set_item(&format!("num{}", i), i).unwrap();
+        dict.set_item(format!("num{}", i), i).unwrap();
     }
 }
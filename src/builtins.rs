@@ -1,8 +1,34 @@
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use chrono::{DateTime, Datelike, FixedOffset, TimeDelta, Timelike};
+use chrono_tz::Tz;
+use icu_casemap::CaseMapper;
+use icu_normalizer::{ComposingNormalizer, DecomposingNormalizer};
+use indexmap::IndexMap;
+use ipnet::IpNet;
+use num_bigint::{BigInt, Sign};
+use num_traits::FromPrimitive;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 use unic_langid::LanguageIdentifier;
 
-use crate::ast::EvalResultTypes;
-use crate::errors::{EvaluationError, InvalidParameterTypeError, SymbolResolutionError};
+use crate::ast::{
+    add, float_to_decimal, integer_result, order, to_float, EvalResult, EvalResultTypes, ValueType,
+};
+use crate::engine::{Context, Tolerance};
+use crate::errors::{
+    ContextError, EvaluationError, InvalidParameterTypeError, SymbolResolutionError,
+};
+use crate::locale::{self, parse_locale};
+use crate::mapping::ValueMap;
+use crate::money::Money;
+use crate::registry::{Builtin, BuiltinRegistry, Parameter};
+use crate::set::{SetKey, ValueSet};
+use crate::utils::parse_hex;
 
 fn as_lower(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
     match value {
@@ -11,13 +37,12 @@ fn as_lower(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError>
     }
 }
 
+/// The language of a locale such as `en-US`, or of a language's English name such as `English`
 fn language_code(eval_result: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
-    // TODO: Is there a way to map english -> en?
     match eval_result {
         EvalResultTypes::String(value) => {
-            let lang = LanguageIdentifier::from_str(&value).or_else(|_| {
-                LanguageIdentifier::from_str(value.split('-').next().unwrap_or_default())
-            });
+            let lang = parse_locale(&value)
+                .or_else(|_| parse_locale(value.split('-').next().unwrap_or_default()));
             match lang {
                 Ok(lang) => Ok(EvalResultTypes::String(lang.language.to_string())),
                 Err(_) => Err(SymbolResolutionError::new(&format!(
@@ -31,22 +56,2661 @@ fn language_code(eval_result: EvalResultTypes) -> Result<EvalResultTypes, Evalua
     }
 }
 
-type BuiltinFunc = fn(EvalResultTypes) -> Result<EvalResultTypes, EvaluationError>;
-pub fn resolve_builtin_methods(identifier: &str) -> Result<BuiltinFunc, SymbolResolutionError> {
-    match identifier {
-        "as_lower" => Ok(as_lower),
-        "language_code" => Ok(language_code),
-        _ => Err(SymbolResolutionError::new(&format!(
-            "Builtin method {} not found",
-            identifier
+fn expect_locale(value: EvalResultTypes) -> Result<LanguageIdentifier, EvaluationError> {
+    parse_locale(&expect_string(value)?)
+}
+
+/// A string, or null when there is none
+fn optional_string(value: Option<impl ToString>) -> EvalResultTypes {
+    value.map_or(EvalResultTypes::Null, |value| {
+        EvalResultTypes::String(value.to_string())
+    })
+}
+
+/// The region of a locale, e.g. `US` for `en-US`, or null when it has none
+fn region(value: EvalResultTypes) -> EvalResult {
+    Ok(optional_string(expect_locale(value)?.region))
+}
+
+/// The script of a locale, e.g. `Hant` for `zh-Hant-TW`, or null when it has none
+fn script(value: EvalResultTypes) -> EvalResult {
+    Ok(optional_string(expect_locale(value)?.script))
+}
+
+/// A locale in its canonical form, e.g. `en-US` for `EN_us`, `en_US.UTF-8` or
+/// `American English`
+fn canonicalize(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::String(expect_locale(value)?.to_string()))
+}
+
+/// The English name of a locale's language, e.g. `Portuguese` for `pt-BR`, or null when the
+/// language isn't one known by name
+fn language_name(value: EvalResultTypes) -> EvalResult {
+    Ok(optional_string(locale::language_name(&expect_locale(
+        value,
+    )?)))
+}
+
+/// Locales written as strings, keeping the text each was parsed from
+fn expect_locales(
+    value: EvalResultTypes,
+) -> Result<Vec<(String, LanguageIdentifier)>, EvaluationError> {
+    let values = match value {
+        EvalResultTypes::String(_) => vec![value],
+        value => expect_list(value)?,
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            let text = match value {
+                EvalResultTypes::String(text) => text,
+                value => return Err(expected("locale string", &value)),
+            };
+            let locale = parse_locale(&text)?;
+            Ok((text, locale))
+        })
+        .collect()
+}
+
+/// The available locale that best serves the requested ones, which are in order of preference,
+/// or null when none does, e.g. `$locale_matches(user.languages, ["en", "fr-CA"])`. A single
+/// requested locale may be given as a string.
+fn locale_matches(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [requested, available] = unpack(args)?;
+    let requested: Vec<LanguageIdentifier> = expect_locales(requested)?
+        .into_iter()
+        .map(|(_, locale)| locale)
+        .collect();
+    let (texts, available): (Vec<String>, Vec<LanguageIdentifier>) =
+        expect_locales(available)?.into_iter().unzip();
+    Ok(optional_string(
+        locale::negotiate(&requested, &available).map(|index| &texts[index]),
+    ))
+}
+
+fn expect_datetime(value: EvalResultTypes) -> Result<DateTime<FixedOffset>, EvaluationError> {
+    match value {
+        EvalResultTypes::Datetime(value) => Ok(value),
+        _ => Err(InvalidParameterTypeError::new("Expected datetime").into()),
+    }
+}
+
+fn year(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.year().into(),
+    ))
+}
+
+fn month(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.month().into(),
+    ))
+}
+
+fn day(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.day().into(),
+    ))
+}
+
+fn hour(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.hour().into(),
+    ))
+}
+
+fn minute(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.minute().into(),
+    ))
+}
+
+fn second(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::Integer(
+        expect_datetime(value)?.second().into(),
+    ))
+}
+
+/// Day of the week where Monday is 0 and Sunday is 6, matching Python's `datetime.weekday()`
+fn weekday(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let weekday = expect_datetime(value)?.weekday().num_days_from_monday();
+    Ok(EvalResultTypes::Integer(weekday.into()))
+}
+
+fn timedelta_microseconds(value: EvalResultTypes) -> Result<i64, EvaluationError> {
+    match value {
+        EvalResultTypes::Timedelta(value) => value
+            .num_microseconds()
+            .ok_or_else(|| EvaluationError::new("Timedelta out of range")),
+        _ => Err(InvalidParameterTypeError::new("Expected timedelta").into()),
+    }
+}
+
+const MICROSECONDS_PER_DAY: i64 = TimeDelta::days(1).num_microseconds().unwrap();
+
+/// Whole days, rounded down like Python's `timedelta.days`
+fn days(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let microseconds = timedelta_microseconds(value)?;
+    Ok(EvalResultTypes::Integer(
+        microseconds.div_euclid(MICROSECONDS_PER_DAY),
+    ))
+}
+
+/// Whole seconds remaining after `days`, like Python's `timedelta.seconds`
+fn seconds(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let microseconds = timedelta_microseconds(value)?;
+    Ok(EvalResultTypes::Integer(
+        microseconds.rem_euclid(MICROSECONDS_PER_DAY) / 1_000_000,
+    ))
+}
+
+fn total_seconds(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let microseconds = timedelta_microseconds(value)?;
+    Ok(EvalResultTypes::Float(microseconds as f64 / 1e6))
+}
+
+fn amount(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    match value {
+        EvalResultTypes::Money(value) => Ok(EvalResultTypes::Decimal(value.amount)),
+        _ => Err(InvalidParameterTypeError::new("Expected money").into()),
+    }
+}
+
+fn currency(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    match value {
+        EvalResultTypes::Money(value) => Ok(EvalResultTypes::String(value.currency)),
+        _ => Err(InvalidParameterTypeError::new("Expected money").into()),
+    }
+}
+
+/// Applies a float test to any number. Only floats can be NaN or infinite, so the test is false
+/// for every other number.
+fn float_test(value: EvalResultTypes, test: fn(f64) -> bool) -> EvalResult {
+    match value {
+        EvalResultTypes::Float(value) => Ok(EvalResultTypes::Boolean(test(value))),
+        value if to_float(&value).is_some() => Ok(EvalResultTypes::Boolean(false)),
+        _ => Err(InvalidParameterTypeError::new("Expected number").into()),
+    }
+}
+
+fn is_nan(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    float_test(value, f64::is_nan)
+}
+
+fn is_infinite(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    float_test(value, f64::is_infinite)
+}
+
+/// The number of bytes, characters, list items, set values or mapping entries
+fn length(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let length = match value {
+        EvalResultTypes::Bytes(value) => value.len(),
+        EvalResultTypes::String(value) => value.chars().count(),
+        EvalResultTypes::List(value) => value.len(),
+        EvalResultTypes::Set(value) => value.len(),
+        EvalResultTypes::Mapping(value) => value.len(),
+        _ => return Err(expected("bytes, string, list, set or mapping", &value)),
+    };
+    Ok(EvalResultTypes::Integer(length as i64))
+}
+
+fn expect_bytes(value: EvalResultTypes) -> Result<Vec<u8>, EvaluationError> {
+    match value {
+        EvalResultTypes::Bytes(value) => Ok(value),
+        _ => Err(InvalidParameterTypeError::new("Expected bytes").into()),
+    }
+}
+
+fn expect_string(value: EvalResultTypes) -> Result<String, EvaluationError> {
+    match value {
+        EvalResultTypes::String(value) => Ok(value),
+        _ => Err(InvalidParameterTypeError::new("Expected string").into()),
+    }
+}
+
+/// Lowercase hex digits, two per byte
+fn to_hex(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::String(hex::encode(expect_bytes(value)?)))
+}
+
+fn from_hex(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let bytes =
+        parse_hex(&expect_string(value)?).map_err(|err| EvaluationError::new(&err.to_string()))?;
+    Ok(EvalResultTypes::Bytes(bytes))
+}
+
+/// Standard base64 with padding, as used by most webhook signatures
+fn to_base64(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    Ok(EvalResultTypes::String(
+        BASE64_STANDARD.encode(expect_bytes(value)?),
+    ))
+}
+
+fn from_base64(value: EvalResultTypes) -> Result<EvalResultTypes, EvaluationError> {
+    let text = expect_string(value)?;
+    BASE64_STANDARD
+        .decode(&text)
+        .map(EvalResultTypes::Bytes)
+        .map_err(|_| EvaluationError::new(&format!("Invalid base64 '{}'", text)))
+}
+
+/// Unpacks arguments bound by `Signature::bind`, which guarantees how many there are
+fn unpack<const N: usize>(
+    args: Vec<EvalResultTypes>,
+) -> Result<[EvalResultTypes; N], EvaluationError> {
+    <[EvalResultTypes; N]>::try_from(args).map_err(|args| {
+        EvaluationError::new(&format!("Expected {} arguments, got {}", N, args.len()))
+    })
+}
+
+/// Converts a datetime to the same instant in the given IANA timezone, e.g. `America/Denver`
+fn to_tz(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Datetime(value), EvalResultTypes::String(name)] => {
+            let tz = Tz::from_str(&name)
+                .map_err(|_| EvaluationError::new(&format!("Unknown timezone '{}'", name)))?;
+            Ok(EvalResultTypes::Datetime(
+                value.with_timezone(&tz).fixed_offset(),
+            ))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected datetime and string").into()),
+    }
+}
+
+fn expect_whole_number(value: &EvalResultTypes) -> Result<i64, EvaluationError> {
+    match value {
+        EvalResultTypes::Integer(value) => Ok(*value),
+        // A float that holds a whole number is accepted too, e.g. `3.0`
+        EvalResultTypes::Float(value) if value.fract() == 0.0 => Ok(*value as i64),
+        _ => Err(InvalidParameterTypeError::new("Expected a whole number").into()),
+    }
+}
+
+/// Whether the datetime falls on a working day, e.g. `due.is_business_day("us")`
+fn is_business_day(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Datetime(value), EvalResultTypes::String(region)] => {
+            let calendar = ctx.calendar(&region)?;
+            Ok(EvalResultTypes::Boolean(
+                calendar.is_business_day(value.date_naive()),
+            ))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected datetime and string").into()),
+    }
+}
+
+/// Moves a datetime by a number of business days, keeping its time of day
+fn add_business_days(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Datetime(value), days, EvalResultTypes::String(region)] => {
+            let days = expect_whole_number(&days)?;
+            let calendar = ctx.calendar(&region)?;
+            let start = value.date_naive();
+            calendar
+                .add_business_days(start, days)
+                .and_then(|end| value.checked_add_signed(end - start))
+                .map(EvalResultTypes::Datetime)
+                .ok_or_else(|| EvaluationError::new("Datetime out of range"))
+        }
+        _ => {
+            Err(InvalidParameterTypeError::new("Expected datetime, whole number and string").into())
+        }
+    }
+}
+
+/// Counts the business days from the receiver up to, but not including, the end date
+fn business_days_between(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Datetime(start), EvalResultTypes::Datetime(end), EvalResultTypes::String(region)] =>
+        {
+            let calendar = ctx.calendar(&region)?;
+            Ok(EvalResultTypes::Integer(calendar.business_days_between(
+                start.date_naive(),
+                end.date_naive(),
+            )))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected two datetimes and a string").into()),
+    }
+}
+
+/// Maps the names of Python's `decimal` rounding modes, without the `ROUND_` prefix, e.g. `half_up`
+fn rounding_strategy(mode: &str) -> Result<RoundingStrategy, EvaluationError> {
+    match mode.to_ascii_lowercase().as_str() {
+        "half_even" | "bankers" => Ok(RoundingStrategy::MidpointNearestEven),
+        "half_up" => Ok(RoundingStrategy::MidpointAwayFromZero),
+        "half_down" => Ok(RoundingStrategy::MidpointTowardZero),
+        "up" => Ok(RoundingStrategy::AwayFromZero),
+        "down" => Ok(RoundingStrategy::ToZero),
+        "ceiling" => Ok(RoundingStrategy::ToPositiveInfinity),
+        "floor" => Ok(RoundingStrategy::ToNegativeInfinity),
+        _ => Err(EvaluationError::new(&format!(
+            "Unknown rounding mode '{}'",
+            mode
         ))),
     }
 }
 
+/// Rounds a number to a number of decimal places (0 by default) using a rounding mode ("half_even",
+/// also called "bankers", by default, like Python), e.g. `amount.round(2, "half_up")`. Floats are
+/// rounded as the shortest decimal that represents them, so `2.675.round(2, "half_up")` is `2.68`.
+fn round(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, places, mode] = unpack(args)?;
+    let places = u32::try_from(expect_whole_number(&places)?)
+        .map_err(|_| InvalidParameterTypeError::new("Expected a non-negative number of places"))?;
+    let strategy = match mode {
+        EvalResultTypes::String(mode) => rounding_strategy(&mode)?,
+        _ => return Err(InvalidParameterTypeError::new("Expected string").into()),
+    };
+    match value {
+        EvalResultTypes::Decimal(value) => Ok(EvalResultTypes::Decimal(
+            value.round_dp_with_strategy(places, strategy),
+        )),
+        EvalResultTypes::Float(value) if !value.is_finite() => Ok(EvalResultTypes::Float(value)),
+        EvalResultTypes::Float(value) => float_to_decimal(value)?
+            .round_dp_with_strategy(places, strategy)
+            .to_f64()
+            .map(EvalResultTypes::Float)
+            .ok_or_else(|| EvaluationError::new("Float out of range")),
+        EvalResultTypes::Integer(value) => Ok(EvalResultTypes::Integer(value)),
+        EvalResultTypes::BigInt(value) => Ok(EvalResultTypes::BigInt(value)),
+        EvalResultTypes::Money(value) => Ok(EvalResultTypes::Money(Money {
+            amount: value.amount.round_dp_with_strategy(places, strategy),
+            ..value
+        })),
+        _ => Err(InvalidParameterTypeError::new("Expected number").into()),
+    }
+}
+
+/// Converts money to another currency using the context's exchange rates, e.g.
+/// `price.convert("EUR")`. The amount is not rounded.
+fn convert(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Money(money), EvalResultTypes::String(currency)] => {
+            let rate = ctx.exchange_rate(&money.currency, &currency)?;
+            let amount = money
+                .amount
+                .checked_mul(rate)
+                .ok_or_else(|| EvaluationError::new("Money out of range"))?;
+            let converted = Money::new(amount, &currency)
+                .map_err(|err| EvaluationError::new(&err.to_string()))?;
+            Ok(EvalResultTypes::Money(converted))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected money and string").into()),
+    }
+}
+
+/// Like Python's set methods, the argument may be a list, which is treated as the set of its values
+fn set_relation(
+    args: Vec<EvalResultTypes>,
+    relation: fn(&ValueSet, &ValueSet) -> bool,
+) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Set(lhs), EvalResultTypes::Set(rhs)] => {
+            Ok(EvalResultTypes::Boolean(relation(&lhs, &rhs)))
+        }
+        [EvalResultTypes::Set(lhs), EvalResultTypes::List(rhs)] => Ok(EvalResultTypes::Boolean(
+            relation(&lhs, &ValueSet::from_values(rhs)?),
+        )),
+        _ => Err(InvalidParameterTypeError::new("Expected set and set or list").into()),
+    }
+}
+
+/// Whether every value of the receiver is in the argument, e.g. `user.roles.issubset(allowed)`
+fn issubset(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    set_relation(args, ValueSet::is_subset)
+}
+
+/// Whether every value of the argument is in the receiver
+fn issuperset(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    set_relation(args, ValueSet::is_superset)
+}
+
+/// Whether the receiver and the argument have no values in common
+fn isdisjoint(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    set_relation(args, ValueSet::is_disjoint)
+}
+
+/// Resolves Python-style slice bounds, where negative indices count from the end and indices past
+/// either end are clamped
+fn slice_bounds(length: usize, start: i64, end: Option<i64>) -> (usize, usize) {
+    let resolve = |index: i64| {
+        if index < 0 {
+            length.saturating_sub(index.unsigned_abs() as usize)
+        } else {
+            (index as usize).min(length)
+        }
+    };
+    let start = resolve(start);
+    let end = end.map_or(length, resolve);
+    (start, end.max(start))
+}
+
+/// The part of bytes, a string or a list from `start` up to, but not including, `end` (the end
+/// by default), e.g. `payload.slice(0, 4)`
+fn slice(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, start, end] = unpack(args)?;
+    let start = expect_whole_number(&start)?;
+    let end = match end {
+        EvalResultTypes::Null => None,
+        end => Some(expect_whole_number(&end)?),
+    };
+    match value {
+        EvalResultTypes::Bytes(value) => {
+            let (start, end) = slice_bounds(value.len(), start, end);
+            Ok(EvalResultTypes::Bytes(value[start..end].to_vec()))
+        }
+        EvalResultTypes::String(value) => {
+            let (start, end) = slice_bounds(value.chars().count(), start, end);
+            Ok(EvalResultTypes::String(
+                value.chars().skip(start).take(end - start).collect(),
+            ))
+        }
+        EvalResultTypes::List(value) => {
+            let (start, end) = slice_bounds(value.len(), start, end);
+            Ok(EvalResultTypes::List(value[start..end].to_vec()))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected bytes, string or list").into()),
+    }
+}
+
+/// Whether bytes or a string begin with the given prefix of the same type
+fn starts_with(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Bytes(value), EvalResultTypes::Bytes(prefix)] => {
+            Ok(EvalResultTypes::Boolean(value.starts_with(&prefix)))
+        }
+        [EvalResultTypes::String(value), EvalResultTypes::String(prefix)] => {
+            Ok(EvalResultTypes::Boolean(value.starts_with(&prefix)))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected two bytes or two strings").into()),
+    }
+}
+
+/// Whether bytes or a string end with the given suffix of the same type
+fn ends_with(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Bytes(value), EvalResultTypes::Bytes(suffix)] => {
+            Ok(EvalResultTypes::Boolean(value.ends_with(&suffix)))
+        }
+        [EvalResultTypes::String(value), EvalResultTypes::String(suffix)] => {
+            Ok(EvalResultTypes::Boolean(value.ends_with(&suffix)))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected two bytes or two strings").into()),
+    }
+}
+
+/// The position of the first occurrence of a needle, in bytes for bytes, characters for strings
+/// and items for lists, or `None` when there is none
+fn find(
+    value: &EvalResultTypes,
+    needle: &EvalResultTypes,
+) -> Result<Option<usize>, EvaluationError> {
+    match (value, needle) {
+        (EvalResultTypes::Bytes(value), EvalResultTypes::Bytes(needle)) => Ok(match needle.len() {
+            0 => Some(0),
+            length => value.windows(length).position(|window| window == needle),
+        }),
+        (EvalResultTypes::String(value), EvalResultTypes::String(needle)) => Ok(value
+            .find(needle.as_str())
+            .map(|index| value[..index].chars().count())),
+        (EvalResultTypes::List(values), needle) => {
+            Ok(values.iter().position(|value| value == needle))
+        }
+        // Bytes and strings are only searched for a needle of their own type
+        (EvalResultTypes::Bytes(_) | EvalResultTypes::String(_), needle) => {
+            Err(expected(value.type_name(), needle))
+        }
+        (value, _) => Err(expected("bytes, string or list", value)),
+    }
+}
+
+/// Whether bytes or a string contain the given needle of the same type
+fn contains(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, needle] = unpack(args)?;
+    Ok(EvalResultTypes::Boolean(find(&value, &needle)?.is_some()))
+}
+
+/// The position of the first occurrence of a needle, e.g. `"banana".index_of("n")` is 2 and
+/// `[1, 2].index_of(2)` is 1, or -1 when there is none
+fn index_of(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, needle] = unpack(args)?;
+    Ok(EvalResultTypes::Integer(
+        find(&value, &needle)?.map_or(-1, |index| index as i64),
+    ))
+}
+
+fn as_upper(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::String(
+        expect_string(value)?.to_uppercase(),
+    ))
+}
+
+/// Lowercases with Unicode's full case folding, so that e.g. `"STRASSE"` and `"Straße"` fold to
+/// the same string. Prefer this to `as_lower` when comparing text regardless of case.
+fn casefold(value: EvalResultTypes) -> EvalResult {
+    let text = expect_string(value)?;
+    Ok(EvalResultTypes::String(
+        CaseMapper::new().fold_string(&text),
+    ))
+}
+
+/// Uppercases the first letter of every word and lowercases the rest, where a word is a run of
+/// letters, like Python's `str.title`, e.g. `"o'neil-SMITH"` becomes `"O'Neil-Smith"`
+fn title(value: EvalResultTypes) -> EvalResult {
+    let text = expect_string(value)?;
+    let mut result = String::with_capacity(text.len());
+    let mut in_word = false;
+    for character in text.chars() {
+        match in_word {
+            true => result.extend(character.to_lowercase()),
+            false => result.extend(character.to_uppercase()),
+        }
+        in_word = character.is_alphabetic();
+    }
+    Ok(EvalResultTypes::String(result))
+}
+
+fn trim(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::String(
+        expect_string(value)?.trim().to_string(),
+    ))
+}
+
+fn ltrim(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::String(
+        expect_string(value)?.trim_start().to_string(),
+    ))
+}
+
+fn rtrim(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::String(
+        expect_string(value)?.trim_end().to_string(),
+    ))
+}
+
+/// Splits a string on a separator, or on runs of whitespace when the separator is null, into at
+/// most `limit` + 1 parts, like Python's `str.split`, e.g. `"a,b,c".split(",", 1)` is
+/// `["a", "b,c"]`
+fn split(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, separator, limit] = unpack(args)?;
+    let text = expect_string(value)?;
+    let limit = match limit {
+        EvalResultTypes::Null => usize::MAX,
+        limit => usize::try_from(expect_whole_number(&limit)?)
+            .map_err(|_| InvalidParameterTypeError::new("Expected a non-negative limit"))?
+            .saturating_add(1),
+    };
+    let parts: Vec<&str> = match separator {
+        EvalResultTypes::Null => {
+            // Like Python, the parts after the limit keep their whitespace except at the start
+            let mut parts = Vec::new();
+            let mut rest = text.trim_start();
+            while !rest.is_empty() {
+                if parts.len() + 1 == limit {
+                    parts.push(rest);
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                parts.push(&rest[..end]);
+                rest = rest[end..].trim_start();
+            }
+            parts
+        }
+        EvalResultTypes::String(separator) if separator.is_empty() => {
+            return Err(EvaluationError::new("Cannot split on an empty separator"))
+        }
+        EvalResultTypes::String(separator) => text.splitn(limit, separator.as_str()).collect(),
+        _ => return Err(InvalidParameterTypeError::new("Expected string").into()),
+    };
+    Ok(EvalResultTypes::List(
+        parts
+            .into_iter()
+            .map(|part| EvalResultTypes::String(part.to_string()))
+            .collect(),
+    ))
+}
+
+/// Joins a list of strings with a separator, e.g. `names.join(", ")`
+fn join(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::List(values), EvalResultTypes::String(separator)] => {
+            let mut parts = Vec::with_capacity(values.len());
+            for value in &values {
+                match value {
+                    EvalResultTypes::String(part) => parts.push(part.as_str()),
+                    _ => {
+                        return Err(InvalidParameterTypeError::new(&format!(
+                            "Cannot join a list containing {}, expected strings",
+                            value.type_name()
+                        ))
+                        .into())
+                    }
+                }
+            }
+            let joined = parts.join(&separator);
+            ctx.check_sequence_length(joined.len())?;
+            Ok(EvalResultTypes::String(joined))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected list and string").into()),
+    }
+}
+
+/// Replaces occurrences of a string, all of them unless a count is given, e.g.
+/// `phone.replace("-", "")`
+fn replace(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::String(value), EvalResultTypes::String(old), EvalResultTypes::String(new), count] =>
+        {
+            let replaced = match count {
+                EvalResultTypes::Null => value.replace(&old, &new),
+                count => {
+                    let count = usize::try_from(expect_whole_number(&count)?).map_err(|_| {
+                        InvalidParameterTypeError::new("Expected a non-negative count")
+                    })?;
+                    value.replacen(&old, &new, count)
+                }
+            };
+            ctx.check_sequence_length(replaced.len())?;
+            Ok(EvalResultTypes::String(replaced))
+        }
+        _ => Err(InvalidParameterTypeError::new("Expected three strings").into()),
+    }
+}
+
+/// The characters of a string from `start`, which counts from the end when negative, up to
+/// `length` characters long (the rest of the string by default), e.g.
+/// `"2024-06-30".substring(5, 2)`
+fn substring(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, start, length] = unpack(args)?;
+    let text = expect_string(value)?;
+    let count = text.chars().count();
+    let (start, _) = slice_bounds(count, expect_whole_number(&start)?, None);
+    let length = match length {
+        EvalResultTypes::Null => count,
+        length => usize::try_from(expect_whole_number(&length)?)
+            .map_err(|_| InvalidParameterTypeError::new("Expected a non-negative length"))?,
+    };
+    Ok(EvalResultTypes::String(
+        text.chars().skip(start).take(length).collect(),
+    ))
+}
+
+/// Pads the start of a string with a fill character until it is `width` characters long, e.g.
+/// `str(id).pad_left(6, "0")`. Strings that are already long enough are unchanged.
+fn pad_left(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, width, fill] = unpack(args)?;
+    let text = expect_string(value)?;
+    let fill = expect_string(fill)?;
+    let mut fill_characters = fill.chars();
+    let (Some(fill), None) = (fill_characters.next(), fill_characters.next()) else {
+        return Err(EvaluationError::new(&format!(
+            "Expected a single fill character, got '{}'",
+            fill
+        )));
+    };
+    let width = usize::try_from(expect_whole_number(&width)?).unwrap_or(0);
+    let padding = width.saturating_sub(text.chars().count());
+    ctx.check_sequence_length(text.len() + padding * fill.len_utf8())?;
+    let mut padded: String = std::iter::repeat_n(fill, padding).collect();
+    padded.push_str(&text);
+    Ok(EvalResultTypes::String(padded))
+}
+
+/// Parses a whole number written in a base from 2 to 36 (10 by default), optionally signed and
+/// surrounded by whitespace, e.g. `"ff".to_int(16)`
+fn parse_int(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, base] = unpack(args)?;
+    let text = expect_string(value)?;
+    let base = u32::try_from(expect_whole_number(&base)?)
+        .ok()
+        .filter(|base| (2..=36).contains(base))
+        .ok_or_else(|| InvalidParameterTypeError::new("Expected a base from 2 to 36"))?;
+    let trimmed = text.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let invalid = || {
+        EvaluationError::new(&format!(
+            "Cannot parse '{}' as an integer in base {}",
+            text, base
+        ))
+    };
+    // `parse_bytes` accepts underscores between digits, which rules shouldn't
+    if digits.is_empty() || !digits.chars().all(|digit| digit.is_digit(base)) {
+        return Err(invalid());
+    }
+    let number = BigInt::parse_bytes(digits.as_bytes(), base).ok_or_else(invalid)?;
+    integer_result(ctx, if negative { -number } else { number })
+}
+
+/// Parses a float, accepting exponents as well as `inf` and `nan`, e.g. `"1.5e3".to_float`
+fn parse_float(value: EvalResultTypes) -> EvalResult {
+    let text = expect_string(value)?;
+    text.trim()
+        .parse()
+        .map(EvalResultTypes::Float)
+        .map_err(|_| EvaluationError::new(&format!("Cannot parse '{}' as a float", text)))
+}
+
+/// Normalizes a string to one of Unicode's normalization forms (NFC by default), so that e.g. an
+/// `é` typed as one character equals one typed as `e` and a combining accent. The compatibility
+/// forms also fold variants such as `ﬁ` into `fi`.
+fn normalize(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, form] = unpack(args)?;
+    let text = expect_string(value)?;
+    let form = expect_string(form)?;
+    let normalized = match form.to_ascii_uppercase().as_str() {
+        "NFC" => ComposingNormalizer::new_nfc().normalize(&text),
+        "NFKC" => ComposingNormalizer::new_nfkc().normalize(&text),
+        "NFD" => DecomposingNormalizer::new_nfd().normalize(&text),
+        "NFKD" => DecomposingNormalizer::new_nfkd().normalize(&text),
+        _ => {
+            return Err(EvaluationError::new(&format!(
+                "Unknown normalization form '{}', expected NFC, NFKC, NFD or NFKD",
+                form
+            )))
+        }
+    };
+    Ok(EvalResultTypes::String(normalized))
+}
+
+/// Whether two numbers are equal within a tolerance, like Python's `math.isclose`, e.g.
+/// `isclose(total, 0.3, 1e-6, 0.001)`. The relative and absolute tolerances default to the
+/// context's, which are also used by `~=`.
+fn isclose(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [lhs, rhs, relative, absolute] = unpack(args)?;
+    let tolerance = |value: EvalResultTypes, default: f64| match value {
+        EvalResultTypes::Null => Ok(default),
+        value => match to_float(&value) {
+            Some(tolerance) if tolerance >= 0.0 => Ok(tolerance),
+            _ => Err(InvalidParameterTypeError::new(
+                "Expected a non-negative tolerance",
+            )),
+        },
+    };
+    let default = ctx.tolerance();
+    let tolerance = Tolerance {
+        relative: tolerance(relative, default.relative)?,
+        absolute: tolerance(absolute, default.absolute)?,
+    };
+    match (to_float(&lhs), to_float(&rhs)) {
+        (Some(lhs), Some(rhs)) => Ok(EvalResultTypes::Boolean(tolerance.is_close(lhs, rhs))),
+        _ => Err(InvalidParameterTypeError::new("Expected two numbers").into()),
+    }
+}
+
+/// Finds the value that orders `wanted` against every other, keeping the first of equal values. A
+/// single list or set argument is treated as the values to search.
+fn extreme(ctx: &Context, args: Vec<EvalResultTypes>, wanted: Ordering) -> EvalResult {
+    let values = match <[EvalResultTypes; 1]>::try_from(args) {
+        Ok([EvalResultTypes::List(values)]) => values,
+        Ok([EvalResultTypes::Set(values)]) => values.iter().cloned().collect(),
+        Ok([value]) => vec![value],
+        Err(args) => args,
+    };
+    let mut values = values.into_iter();
+    let mut best = values
+        .next()
+        .ok_or_else(|| EvaluationError::new("Expected at least one value"))?;
+    for value in values {
+        if order(ctx, &value, &best)? == wanted {
+            best = value;
+        }
+    }
+    Ok(best)
+}
+
+/// The smallest of the arguments, e.g. `$min(a, b)` or `$min(prices)`
+fn min(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    extreme(ctx, args, Ordering::Less)
+}
+
+/// The largest of the arguments, e.g. `$max(a, b)` or `$max(prices)`
+fn max(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    extreme(ctx, args, Ordering::Greater)
+}
+
+/// The absolute value of a number or amount of money, keeping its type
+fn abs(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::Integer(value)] => match value.checked_abs() {
+            Some(value) => Ok(EvalResultTypes::Integer(value)),
+            None => integer_result(ctx, BigInt::from(value).magnitude().clone().into()),
+        },
+        [EvalResultTypes::BigInt(value)] => integer_result(ctx, value.magnitude().clone().into()),
+        [EvalResultTypes::Float(value)] => Ok(EvalResultTypes::Float(value.abs())),
+        [EvalResultTypes::Decimal(value)] => Ok(EvalResultTypes::Decimal(value.abs())),
+        [EvalResultTypes::Money(value)] => Ok(EvalResultTypes::Money(Money {
+            amount: value.amount.abs(),
+            ..value
+        })),
+        _ => Err(InvalidParameterTypeError::new("Expected number or money").into()),
+    }
+}
+
+/// Rounds a number to a whole number in one direction. Integers are returned unchanged, while
+/// floats and decimals become integers, like Python's `math.floor` and `math.ceil`.
+fn whole_number(
+    ctx: &Context,
+    value: EvalResultTypes,
+    float: fn(f64) -> f64,
+    strategy: RoundingStrategy,
+) -> EvalResult {
+    match value {
+        EvalResultTypes::Integer(_) | EvalResultTypes::BigInt(_) => Ok(value),
+        EvalResultTypes::Float(number) => match BigInt::from_f64(float(number)) {
+            Some(whole) => integer_result(ctx, whole),
+            None => Err(EvaluationError::new(&format!(
+                "Cannot convert {} to an integer",
+                number
+            ))),
+        },
+        EvalResultTypes::Decimal(number) => integer_result(
+            ctx,
+            // A decimal's mantissa is only 96 bits, so its whole part always fits
+            BigInt::from(
+                number
+                    .round_dp_with_strategy(0, strategy)
+                    .to_i128()
+                    .unwrap_or_default(),
+            ),
+        ),
+        _ => Err(InvalidParameterTypeError::new("Expected number").into()),
+    }
+}
+
+/// The smallest whole number that is not less than a number, e.g. `2.1.ceil` is `3`
+fn ceil(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    whole_number(ctx, value, f64::ceil, RoundingStrategy::ToPositiveInfinity)
+}
+
+/// The largest whole number that is not greater than a number, e.g. `(-2.1).floor` is `-3`
+fn floor(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    whole_number(ctx, value, f64::floor, RoundingStrategy::ToNegativeInfinity)
+}
+
+/// -1, 0 or 1 depending on the sign of a number. NaN has no sign.
+fn sign(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    let sign = match &value {
+        EvalResultTypes::Integer(value) => value.signum(),
+        EvalResultTypes::BigInt(value) => match value.sign() {
+            Sign::Minus => -1,
+            Sign::NoSign => 0,
+            Sign::Plus => 1,
+        },
+        EvalResultTypes::Decimal(value) if value.is_zero() => 0,
+        EvalResultTypes::Decimal(value) if value.is_sign_negative() => -1,
+        EvalResultTypes::Decimal(_) => 1,
+        EvalResultTypes::Float(value) if value.is_nan() => {
+            return Err(EvaluationError::new("Cannot take the sign of NaN"))
+        }
+        EvalResultTypes::Float(value) if *value == 0.0 => 0,
+        EvalResultTypes::Float(value) => value.signum() as i64,
+        _ => return Err(InvalidParameterTypeError::new("Expected number").into()),
+    };
+    Ok(EvalResultTypes::Integer(sign))
+}
+
+fn expect_float(value: &EvalResultTypes) -> Result<f64, EvaluationError> {
+    to_float(value).ok_or_else(|| InvalidParameterTypeError::new("Expected number").into())
+}
+
+/// Checks that a float function did not overflow from finite arguments
+fn float_result(result: f64, arguments: &[f64]) -> EvalResult {
+    if result.is_infinite() && arguments.iter().all(|argument| argument.is_finite()) {
+        return Err(EvaluationError::new("Float out of range"));
+    }
+    Ok(EvalResultTypes::Float(result))
+}
+
+/// The square root of a non-negative number as a float
+fn sqrt(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    match expect_float(&value)? {
+        value if value < 0.0 => Err(EvaluationError::new(
+            "Cannot take the square root of a negative number",
+        )),
+        value => Ok(EvalResultTypes::Float(value.sqrt())),
+    }
+}
+
+/// The logarithm of a positive number as a float, e.g. `$log(x)` for the natural logarithm or
+/// `$log(x, 10)`
+fn log(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, base] = unpack(args)?;
+    let value = expect_float(&value)?;
+    if value.is_nan() || value <= 0.0 {
+        return Err(EvaluationError::new(&format!(
+            "Cannot take the logarithm of {}",
+            value
+        )));
+    }
+    match base {
+        EvalResultTypes::Null => Ok(EvalResultTypes::Float(value.ln())),
+        base => match expect_float(&base)? {
+            base if base.is_nan() || base <= 0.0 || base == 1.0 => Err(EvaluationError::new(
+                &format!("Invalid logarithm base {}", base),
+            )),
+            base => Ok(EvalResultTypes::Float(value.ln() / base.ln())),
+        },
+    }
+}
+
+/// e raised to a number, as a float
+fn exp(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    let value = expect_float(&value)?;
+    float_result(value.exp(), &[value])
+}
+
+/// The most bits a whole-number power may have, so that a rule cannot exhaust memory
+const MAX_POWER_BITS: u64 = 1 << 16;
+
+/// Raises a number to a power, e.g. `$pow(2, 10)`. Integers raised to non-negative integers stay
+/// integers, as do decimals raised to integers. Anything else is computed as a float.
+fn pow(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [base, exponent] = unpack(args)?;
+    let whole_base = match &base {
+        EvalResultTypes::Integer(base) => Some(BigInt::from(*base)),
+        EvalResultTypes::BigInt(base) => Some(base.clone()),
+        _ => None,
+    };
+    match (whole_base, &base, &exponent) {
+        (Some(whole), _, EvalResultTypes::Integer(n)) if *n >= 0 => {
+            if whole.bits().saturating_mul(n.unsigned_abs()) > MAX_POWER_BITS {
+                return Err(EvaluationError::new("Power too large"));
+            }
+            integer_result(ctx, whole.pow(*n as u32))
+        }
+        (_, EvalResultTypes::Decimal(base), EvalResultTypes::Integer(n)) => {
+            decimal_power(*base, *n).map(EvalResultTypes::Decimal)
+        }
+        _ => {
+            let (base, exponent) = (expect_float(&base)?, expect_float(&exponent)?);
+            if base == 0.0 && exponent < 0.0 {
+                return Err(EvaluationError::new(
+                    "Cannot raise zero to a negative power",
+                ));
+            }
+            if base < 0.0 && exponent.is_finite() && exponent.fract() != 0.0 {
+                return Err(EvaluationError::new(
+                    "Cannot raise a negative number to a fractional power",
+                ));
+            }
+            float_result(base.powf(exponent), &[base, exponent])
+        }
+    }
+}
+
+/// Raises a decimal to an integer by repeated squaring
+fn decimal_power(base: Decimal, exponent: i64) -> Result<Decimal, EvaluationError> {
+    let out_of_range = || EvaluationError::new("Decimal out of range");
+    let (mut result, mut square, mut remaining) = (Decimal::ONE, base, exponent.unsigned_abs());
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            result = result.checked_mul(square).ok_or_else(out_of_range)?;
+        }
+        remaining >>= 1;
+        if remaining > 0 {
+            square = square.checked_mul(square).ok_or_else(out_of_range)?;
+        }
+    }
+    if exponent >= 0 {
+        return Ok(result);
+    }
+    if result.is_zero() {
+        return Err(EvaluationError::new(
+            "Cannot raise zero to a negative power",
+        ));
+    }
+    Decimal::ONE.checked_div(result).ok_or_else(out_of_range)
+}
+
+/// Limits a value to a range, e.g. `$clamp(score, 0, 100)`. The value is returned unchanged when
+/// it is within the range, and otherwise the nearest bound is.
+fn clamp(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, low, high] = unpack(args)?;
+    if order(ctx, &low, &high)? == Ordering::Greater {
+        return Err(EvaluationError::new(
+            "The lower bound of clamp is greater than the upper bound",
+        ));
+    }
+    if order(ctx, &value, &low)? == Ordering::Less {
+        Ok(low)
+    } else if order(ctx, &value, &high)? == Ordering::Greater {
+        Ok(high)
+    } else {
+        Ok(value)
+    }
+}
+
+/// Whether a string, bytes, list, set or mapping has nothing in it
+fn is_empty(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    let empty = match &value {
+        EvalResultTypes::Bytes(value) => value.is_empty(),
+        EvalResultTypes::String(value) => value.is_empty(),
+        EvalResultTypes::List(value) => value.is_empty(),
+        EvalResultTypes::Set(value) => value.is_empty(),
+        EvalResultTypes::Mapping(value) => value.is_empty(),
+        _ => return Err(expected("bytes, string, list, set or mapping", &value)),
+    };
+    Ok(EvalResultTypes::Boolean(empty))
+}
+
+fn expected(types: &str, value: &EvalResultTypes) -> EvaluationError {
+    InvalidParameterTypeError::new(&format!("Expected {}, got {}", types, value.type_name())).into()
+}
+
+fn expect_list(value: EvalResultTypes) -> Result<Vec<EvalResultTypes>, EvaluationError> {
+    match value {
+        EvalResultTypes::List(values) => Ok(values),
+        EvalResultTypes::Set(values) => Ok(values.iter().cloned().collect()),
+        _ => Err(expected("list or set", &value)),
+    }
+}
+
+fn expect_mapping(value: EvalResultTypes) -> Result<ValueMap, EvaluationError> {
+    match value {
+        EvalResultTypes::Mapping(mapping) => Ok(mapping),
+        _ => Err(expected("mapping", &value)),
+    }
+}
+
+/// The first item of a non-empty list
+fn first(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    expect_list(values)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| EvaluationError::new("Cannot take the first item of an empty list"))
+}
+
+/// The last item of a non-empty list
+fn last(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    expect_list(values)?
+        .pop()
+        .ok_or_else(|| EvaluationError::new("Cannot take the last item of an empty list"))
+}
+
+/// Checks that every value is a number, money or a timedelta, so that `["a", "b"].sum` is an
+/// error rather than a concatenation
+fn check_summable(values: &[EvalResultTypes], verb: &str) -> Result<(), EvaluationError> {
+    let invalid = values.iter().find(|value| {
+        to_float(value).is_none()
+            && !matches!(
+                value,
+                EvalResultTypes::Money(_) | EvalResultTypes::Timedelta(_)
+            )
+    });
+    match invalid {
+        Some(value) => Err(InvalidParameterTypeError::new(&format!(
+            "Cannot {} a list containing {}, expected numbers, money or timedeltas",
+            verb,
+            value.type_name()
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+fn total(ctx: &Context, values: Vec<EvalResultTypes>, verb: &str) -> EvalResult {
+    check_summable(&values, verb)?;
+    let mut values = values.into_iter();
+    let Some(mut total) = values.next() else {
+        return Ok(EvalResultTypes::Integer(0));
+    };
+    for value in values {
+        total = add(ctx, total, value)?;
+    }
+    Ok(total)
+}
+
+/// The total of a list of numbers, money or timedeltas, e.g. `prices.sum`. An empty list sums
+/// to 0.
+fn sum(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    total(ctx, expect_list(values)?, "sum")
+}
+
+/// Divides a total by a count. Decimals, money and timedeltas stay exact while integers become
+/// floats, like Python's `/`.
+fn divide_total(total: EvalResultTypes, count: usize) -> EvalResult {
+    let out_of_range = || EvaluationError::new("Average out of range");
+    match total {
+        EvalResultTypes::Decimal(total) => total
+            .checked_div(Decimal::from(count))
+            .map(EvalResultTypes::Decimal)
+            .ok_or_else(out_of_range),
+        EvalResultTypes::Money(total) => Ok(EvalResultTypes::Money(
+            total.with_amount(total.amount.checked_div(Decimal::from(count)))?,
+        )),
+        EvalResultTypes::Timedelta(total) => i32::try_from(count)
+            .map(|count| EvalResultTypes::Timedelta(total / count))
+            .map_err(|_| out_of_range()),
+        total => to_float(&total)
+            .map(|total| EvalResultTypes::Float(total / count as f64))
+            .ok_or_else(|| expected("number", &total)),
+    }
+}
+
+/// The mean of a non-empty list of numbers, money or timedeltas
+fn avg(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    let values = expect_list(values)?;
+    if values.is_empty() {
+        return Err(EvaluationError::new("Cannot average an empty list"));
+    }
+    let count = values.len();
+    divide_total(total(ctx, values, "average")?, count)
+}
+
+/// Sorts values with the same ordering as `<`, failing on the first pair that can't be ordered
+fn sort_values(
+    ctx: &Context,
+    values: &mut [EvalResultTypes],
+    descending: bool,
+) -> Result<(), EvaluationError> {
+    let mut error = None;
+    values.sort_by(|lhs, rhs| match order(ctx, lhs, rhs) {
+        Ok(ordering) if descending => ordering.reverse(),
+        Ok(ordering) => ordering,
+        Err(err) => {
+            error.get_or_insert(err);
+            Ordering::Equal
+        }
+    });
+    error.map_or(Ok(()), Err)
+}
+
+/// The middle value of a non-empty list of numbers, money or timedeltas, or the mean of the two
+/// middle values when there is an even number of them, like Python's `statistics.median`
+fn median(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    let mut values = expect_list(values)?;
+    if values.is_empty() {
+        return Err(EvaluationError::new(
+            "Cannot take the median of an empty list",
+        ));
+    }
+    check_summable(&values, "take the median of")?;
+    sort_values(ctx, &mut values, false)?;
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        return Ok(values.swap_remove(middle));
+    }
+    let upper = values.swap_remove(middle);
+    let lower = values.swap_remove(middle - 1);
+    divide_total(add(ctx, lower, upper)?, 2)
+}
+
+/// The values of a list without repeats, keeping the first of equal values, e.g.
+/// `[1, 2, 1].distinct` is `[1, 2]`
+fn distinct(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    let mut seen = HashSet::new();
+    let mut result: Vec<EvalResultTypes> = Vec::new();
+    for value in expect_list(values)? {
+        let new = match SetKey::of(&value) {
+            Ok(key) => seen.insert(key),
+            // Lists and mappings can't be hashed, so they are compared with the values so far
+            Err(_) => !result.contains(&value),
+        };
+        if new {
+            result.push(value);
+        }
+    }
+    Ok(EvalResultTypes::List(result))
+}
+
+/// The values of a list in ascending order, or descending order when asked, e.g.
+/// `scores.sorted(true)`. Strings are ordered by the context's collation.
+fn sorted(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, descending] = unpack(args)?;
+    let mut values = expect_list(values)?;
+    sort_values(ctx, &mut values, descending.is_truthy())?;
+    Ok(EvalResultTypes::List(values))
+}
+
+/// A list in reverse order, or a string with its characters reversed
+fn reversed(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    match unpack(args)? {
+        [EvalResultTypes::String(text)] => {
+            Ok(EvalResultTypes::String(text.chars().rev().collect()))
+        }
+        [EvalResultTypes::List(mut values)] => {
+            values.reverse();
+            Ok(EvalResultTypes::List(values))
+        }
+        [value] => Err(expected("list or string", &value)),
+    }
+}
+
+/// Replaces the lists within a list with their items, one level deep, e.g. `[[1, 2], 3].flatten`
+/// is `[1, 2, 3]`
+fn flatten(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    let mut result = Vec::new();
+    for value in expect_list(values)? {
+        match value {
+            EvalResultTypes::List(values) => result.extend(values),
+            value => result.push(value),
+        }
+        ctx.check_sequence_length(result.len())?;
+    }
+    Ok(EvalResultTypes::List(result))
+}
+
+/// Pairs up the items of lists by position, stopping at the end of the shortest, e.g.
+/// `$zip(names, ages)`
+fn zip(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let lists = args
+        .into_iter()
+        .map(expect_list)
+        .collect::<Result<Vec<_>, _>>()?;
+    let length = lists.iter().map(Vec::len).min().unwrap_or_default();
+    let mut lists: Vec<_> = lists.into_iter().map(Vec::into_iter).collect();
+    let rows = (0..length)
+        .map(|_| {
+            // Every list has at least `length` items
+            EvalResultTypes::List(lists.iter_mut().filter_map(Iterator::next).collect())
+        })
+        .collect();
+    Ok(EvalResultTypes::List(rows))
+}
+
+fn contains_values(
+    args: Vec<EvalResultTypes>,
+    test: fn(&[EvalResultTypes], &[EvalResultTypes]) -> bool,
+) -> EvalResult {
+    let [values, wanted] = unpack(args)?;
+    let (values, wanted) = (expect_list(values)?, expect_list(wanted)?);
+    Ok(EvalResultTypes::Boolean(test(&values, &wanted)))
+}
+
+/// Whether a list or set contains every one of the given values
+fn contains_all(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    contains_values(args, |values, wanted| {
+        wanted.iter().all(|value| values.contains(value))
+    })
+}
+
+/// Whether a list or set contains at least one of the given values
+fn contains_any(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    contains_values(args, |values, wanted| {
+        wanted.iter().any(|value| values.contains(value))
+    })
+}
+
+/// The keys of a mapping in insertion order
+fn keys(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [mapping] = unpack(args)?;
+    let mapping = expect_mapping(mapping)?;
+    Ok(EvalResultTypes::List(mapping.keys().cloned().collect()))
+}
+
+/// The values of a mapping in insertion order
+fn values(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [mapping] = unpack(args)?;
+    let mapping = expect_mapping(mapping)?;
+    Ok(EvalResultTypes::List(mapping.values().cloned().collect()))
+}
+
+/// The entries of a mapping as `[key, value]` lists, in insertion order
+fn items(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [mapping] = unpack(args)?;
+    let items = expect_mapping(mapping)?
+        .iter()
+        .map(|(key, value)| EvalResultTypes::List(vec![key.clone(), value.clone()]))
+        .collect();
+    Ok(EvalResultTypes::List(items))
+}
+
+/// The value of a key, or the default (null unless given) when the mapping doesn't have it, e.g.
+/// `limits.get("daily", 100)`
+fn get(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [mapping, key, default] = unpack(args)?;
+    Ok(expect_mapping(mapping)?
+        .get(&key)
+        .cloned()
+        .unwrap_or(default))
+}
+
+/// The numbers of a list as floats, so that integers, floats and decimals can be mixed
+fn expect_numbers(values: EvalResultTypes, verb: &str) -> Result<Vec<f64>, EvaluationError> {
+    expect_list(values)?
+        .iter()
+        .map(|value| {
+            to_float(value).ok_or_else(|| {
+                InvalidParameterTypeError::new(&format!(
+                    "Cannot {} a list containing {}, expected numbers",
+                    verb,
+                    value.type_name()
+                ))
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// A running total that carries the rounding error of each addition, so that long lists and
+/// values of very different magnitudes sum accurately (Neumaier's variant of Kahan summation)
+#[derive(Default)]
+struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+impl CompensatedSum {
+    fn add(&mut self, value: f64) {
+        let sum = self.sum + value;
+        if self.sum.abs() >= value.abs() {
+            self.compensation += (self.sum - sum) + value;
+        } else {
+            self.compensation += (value - sum) + self.sum;
+        }
+        self.sum = sum;
+    }
+
+    fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+/// The mean and the sum of squared differences from it, computed in one pass with Welford's
+/// algorithm, which avoids the cancellation of subtracting the square of the mean. Values are
+/// divided by a power of two near the largest of them first, which is exact, so that the squares
+/// can't overflow. The sum of squares is in those units, and is returned with the power of two.
+fn mean_and_squares(values: &[f64]) -> (f64, f64, f64) {
+    let largest = values
+        .iter()
+        .map(|value| value.abs())
+        .filter(|value| value.is_finite())
+        .fold(0.0, f64::max);
+    let scale = if largest > 1.0 {
+        2f64.powi(largest.log2().floor().min(1023.0) as i32)
+    } else {
+        1.0
+    };
+    let (mut mean, mut squares) = (0.0, 0.0);
+    for (index, value) in values.iter().enumerate() {
+        let value = value / scale;
+        let delta = value - mean;
+        mean += delta / (index + 1) as f64;
+        squares += delta * (value - mean);
+    }
+    (mean * scale, squares, scale)
+}
+
+/// The variance of at least two values, or at least one when they are the whole population. It
+/// is in the units of `mean_and_squares` and is returned with their power of two, so that the
+/// standard deviation of huge values is finite even when their variance is not.
+fn variance_of(
+    values: &[f64],
+    population: bool,
+    name: &str,
+) -> Result<(f64, f64), EvaluationError> {
+    let (minimum, noun) = if population {
+        (1, "value")
+    } else {
+        (2, "values")
+    };
+    if values.len() < minimum {
+        return Err(EvaluationError::new(&format!(
+            "{} requires at least {} {}, got {}",
+            name,
+            minimum,
+            noun,
+            values.len()
+        )));
+    }
+    let (_, squares, scale) = mean_and_squares(values);
+    Ok((squares / (values.len() + 1 - minimum) as f64, scale))
+}
+
+/// The sample variance of a list of numbers, or the population variance when asked, like
+/// Python's `statistics.variance` and `statistics.pvariance`
+fn variance(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, population] = unpack(args)?;
+    let values = expect_numbers(values, "take the variance of")?;
+    let (variance, scale) = variance_of(&values, population.is_truthy(), "variance")?;
+    Ok(EvalResultTypes::Float(variance * scale * scale))
+}
+
+/// The sample standard deviation of a list of numbers, or the population one when asked
+fn stddev(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, population] = unpack(args)?;
+    let values = expect_numbers(values, "take the standard deviation of")?;
+    let (variance, scale) = variance_of(&values, population.is_truthy(), "stddev")?;
+    Ok(EvalResultTypes::Float(variance.sqrt() * scale))
+}
+
+/// The value below which the given percentage of a non-empty list of numbers falls, interpolating
+/// linearly between the closest values like NumPy's default, e.g. `$percentile(latencies, 95)`.
+/// Any NaN makes the result NaN.
+fn percentile(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, percent] = unpack(args)?;
+    let mut values = expect_numbers(values, "take a percentile of")?;
+    let percent = expect_float(&percent)?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(EvaluationError::new(&format!(
+            "Expected a percentile from 0 to 100, got {}",
+            percent
+        )));
+    }
+    if values.is_empty() {
+        return Err(EvaluationError::new(
+            "Cannot take a percentile of an empty list",
+        ));
+    }
+    if values.iter().any(|value| value.is_nan()) {
+        return Ok(EvalResultTypes::Float(f64::NAN));
+    }
+    values.sort_by(f64::total_cmp);
+    let position = percent / 100.0 * (values.len() - 1) as f64;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    let fraction = position - lower as f64;
+    Ok(EvalResultTypes::Float(
+        values[lower] + (values[upper] - values[lower]) * fraction,
+    ))
+}
+
+/// The most common value of a non-empty list, or the first of them to appear when several are
+/// equally common, like Python's `statistics.mode`. Values are counted like set elements, so `1`
+/// and `1.0` are the same value.
+fn mode(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values] = unpack(args)?;
+    let mut counts: IndexMap<SetKey, (EvalResultTypes, usize)> = IndexMap::new();
+    for value in expect_list(values)? {
+        let key = SetKey::of(&value).map_err(|_| {
+            InvalidParameterTypeError::new(&format!(
+                "Cannot take the mode of a list containing {}",
+                value.type_name()
+            ))
+        })?;
+        counts.entry(key).or_insert((value, 0)).1 += 1;
+    }
+    let mut best: Option<(EvalResultTypes, usize)> = None;
+    for (value, count) in counts.into_values() {
+        if best.as_ref().is_none_or(|(_, most)| count > *most) {
+            best = Some((value, count));
+        }
+    }
+    best.map(|(value, _)| value)
+        .ok_or_else(|| EvaluationError::new("Cannot take the mode of an empty list"))
+}
+
+/// How many sample standard deviations a number is from the mean of a list, e.g.
+/// `$zscore(amount, history) > 3`. The population standard deviation is used when asked.
+fn zscore(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value, values, population] = unpack(args)?;
+    let value = expect_float(&value)?;
+    let values = expect_numbers(values, "take a z-score against")?;
+    let (variance, scale) = variance_of(&values, population.is_truthy(), "zscore")?;
+    let deviation = variance.sqrt() * scale;
+    if deviation == 0.0 {
+        return Err(EvaluationError::new(
+            "Cannot take a z-score against values that are all equal",
+        ));
+    }
+    let (mean, _, _) = mean_and_squares(&values);
+    Ok(EvalResultTypes::Float((value - mean) / deviation))
+}
+
+/// The means of each run of `window` consecutive numbers, e.g. `[1, 2, 3, 4].moving_avg(2)` is
+/// `[1.5, 2.5, 3.5]`. A list shorter than the window has no averages.
+fn moving_avg(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, window] = unpack(args)?;
+    let values = expect_numbers(values, "take moving averages of")?;
+    let window = match usize::try_from(expect_whole_number(&window)?) {
+        Ok(window) if window > 0 => window,
+        _ => return Err(EvaluationError::new("Expected a window of at least 1")),
+    };
+    let mut sum = CompensatedSum::default();
+    let mut averages = Vec::new();
+    for (index, value) in values.iter().enumerate() {
+        sum.add(*value);
+        if index >= window {
+            sum.add(-values[index - window]);
+        }
+        if index + 1 >= window {
+            averages.push(EvalResultTypes::Float(sum.value() / window as f64));
+        }
+    }
+    Ok(EvalResultTypes::List(averages))
+}
+
+/// Exponentially weighted moving averages, where each average moves the given fraction of the way
+/// from the previous one to the next number, e.g. `prices.ewma(0.3)`. The first average is the
+/// first number.
+fn ewma(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [values, alpha] = unpack(args)?;
+    let values = expect_numbers(values, "take moving averages of")?;
+    let alpha = expect_float(&alpha)?;
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        return Err(EvaluationError::new(&format!(
+            "Expected a smoothing factor greater than 0 and at most 1, got {}",
+            alpha
+        )));
+    }
+    let mut average = None;
+    let averages = values
+        .iter()
+        .map(|value| {
+            let next = match average {
+                Some(previous) => previous + alpha * (value - previous),
+                None => *value,
+            };
+            average = Some(next);
+            EvalResultTypes::Float(next)
+        })
+        .collect();
+    Ok(EvalResultTypes::List(averages))
+}
+
+fn parse_ip(text: &str) -> Result<IpAddr, EvaluationError> {
+    text.trim()
+        .parse()
+        .map_err(|_| EvaluationError::new(&format!("Invalid IP address '{}'", text)))
+}
+
+fn expect_ip(value: EvalResultTypes) -> Result<IpAddr, EvaluationError> {
+    match value {
+        EvalResultTypes::IpAddress(address) => Ok(address),
+        EvalResultTypes::String(text) => parse_ip(&text),
+        _ => Err(expected("ip or string", &value)),
+    }
+}
+
+/// Parses an IPv4 or IPv6 address, e.g. `ip(request.remote_addr)`
+fn ip(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    Ok(EvalResultTypes::IpAddress(expect_ip(value)?))
+}
+
+/// Parses a network in CIDR notation, e.g. `cidr("10.0.0.0/8")`. A bare address is a network of
+/// just that address, and like Python's `ip_network`, an address with host bits set is an error
+/// rather than silently truncated.
+fn cidr(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let text = match unpack(args)? {
+        [EvalResultTypes::Network(network)] => return Ok(EvalResultTypes::Network(network)),
+        [EvalResultTypes::String(text)] => text,
+        [value] => return Err(expected("network or string", &value)),
+    };
+    let network = match text.trim().parse::<IpNet>() {
+        Ok(network) => network,
+        Err(_) => IpNet::from(parse_ip(&text).map_err(|_| {
+            EvaluationError::new(&format!(
+                "Invalid network '{}', expected an address and prefix length such as 10.0.0.0/8",
+                text
+            ))
+        })?),
+    };
+    if network.addr() != network.network() {
+        return Err(EvaluationError::new(&format!(
+            "Invalid network '{}', it has host bits set (did you mean {}?)",
+            text,
+            network.trunc()
+        )));
+    }
+    Ok(EvalResultTypes::Network(network))
+}
+
+/// Whether an address is reserved for private networks, i.e. in 10.0.0.0/8, 172.16.0.0/12 or
+/// 192.168.0.0/16 (RFC 1918) or fc00::/7 (RFC 4193). IPv4 addresses mapped to IPv6 are tested as
+/// IPv4.
+fn is_private(value: EvalResultTypes) -> EvalResult {
+    let private = match expect_ip(value)?.to_canonical() {
+        IpAddr::V4(address) => address.is_private(),
+        IpAddr::V6(address) => address.segments()[0] & 0xfe00 == 0xfc00,
+    };
+    Ok(EvalResultTypes::Boolean(private))
+}
+
+/// Whether an address is a loopback address, i.e. in 127.0.0.0/8 or `::1`
+fn is_loopback(value: EvalResultTypes) -> EvalResult {
+    Ok(EvalResultTypes::Boolean(
+        expect_ip(value)?.to_canonical().is_loopback(),
+    ))
+}
+
+/// 4 or 6, the version of the protocol an address belongs to
+fn ip_version(value: EvalResultTypes) -> EvalResult {
+    let version = match expect_ip(value)? {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6,
+    };
+    Ok(EvalResultTypes::Integer(version))
+}
+
+fn conversion_error(value: &EvalResultTypes, target: &str) -> EvaluationError {
+    match value {
+        EvalResultTypes::String(text) => {
+            EvaluationError::new(&format!("Cannot convert '{}' to {}", text, target))
+        }
+        _ => EvaluationError::new(&format!(
+            "Cannot convert {} to {}",
+            value.type_name(),
+            target
+        )),
+    }
+}
+
+/// Converts to an integer. Floats and decimals are truncated toward zero, booleans become 0 or 1
+/// and strings must hold a whole number in base 10, optionally signed and surrounded by whitespace,
+/// e.g. `int(" -42 ")`. Strings such as `"4.2"` are errors rather than truncated.
+fn int(ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    match &value {
+        EvalResultTypes::Integer(_) | EvalResultTypes::BigInt(_) => Ok(value),
+        EvalResultTypes::Boolean(flag) => Ok(EvalResultTypes::Integer(i64::from(*flag))),
+        EvalResultTypes::Float(number) => match BigInt::from_f64(number.trunc()) {
+            Some(whole) => integer_result(ctx, whole),
+            None => Err(conversion_error(&value, "integer")),
+        },
+        EvalResultTypes::Decimal(number) => {
+            // A decimal's mantissa is only 96 bits, so its whole part always fits
+            integer_result(
+                ctx,
+                BigInt::from(number.trunc().to_i128().unwrap_or_default()),
+            )
+        }
+        EvalResultTypes::String(text) => match BigInt::from_str(text.trim()) {
+            Ok(whole) => integer_result(ctx, whole),
+            Err(_) => Err(conversion_error(&value, "integer")),
+        },
+        _ => Err(conversion_error(&value, "integer")),
+    }
+}
+
+/// Converts to a float. Strings use Rust's float syntax after trimming whitespace, which accepts
+/// exponents as well as `inf` and `nan`, e.g. `float("1.5e3")`.
+fn float(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    let number = match &value {
+        EvalResultTypes::Boolean(flag) => Some(f64::from(u8::from(*flag))),
+        EvalResultTypes::String(text) => text.trim().parse().ok(),
+        _ => to_float(&value),
+    };
+    number
+        .map(EvalResultTypes::Float)
+        .ok_or_else(|| conversion_error(&value, "float"))
+}
+
+/// Converts to a string. Booleans and null are written as their literals, datetimes and
+/// timedeltas in ISO-8601, money as `12.50 USD`, addresses and networks in their usual notation
+/// and bytes are decoded as UTF-8. Lists, sets and mappings can't be converted.
+fn str(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    let text = match &value {
+        EvalResultTypes::String(text) => text.clone(),
+        EvalResultTypes::Null => "null".to_string(),
+        EvalResultTypes::Boolean(flag) => flag.to_string(),
+        EvalResultTypes::Integer(number) => number.to_string(),
+        EvalResultTypes::BigInt(number) => number.to_string(),
+        // Debug formatting keeps the fraction of whole floats, e.g. `1.0`
+        EvalResultTypes::Float(number) => format!("{:?}", number),
+        EvalResultTypes::Decimal(number) => number.to_string(),
+        EvalResultTypes::Money(money) => money.to_string(),
+        EvalResultTypes::Datetime(datetime) => datetime.to_rfc3339(),
+        EvalResultTypes::Timedelta(delta) => delta.to_string(),
+        EvalResultTypes::IpAddress(address) => address.to_string(),
+        EvalResultTypes::Network(network) => network.to_string(),
+        EvalResultTypes::Bytes(bytes) => String::from_utf8(bytes.clone()).map_err(|_| {
+            EvaluationError::new("Cannot convert bytes that aren't UTF-8 to string")
+        })?,
+        EvalResultTypes::List(_) | EvalResultTypes::Set(_) | EvalResultTypes::Mapping(_) => {
+            return Err(conversion_error(&value, "string"))
+        }
+    };
+    Ok(EvalResultTypes::String(text))
+}
+
+/// Converts to a boolean. Strings must be one of `true`/`false`, `yes`/`no` or `1`/`0`, ignoring
+/// case and surrounding whitespace, so that `bool("false")` is `false`. Any other value converts
+/// to its truthiness.
+fn bool(_ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+    let [value] = unpack(args)?;
+    match &value {
+        EvalResultTypes::String(text) => match text.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "1" => Ok(EvalResultTypes::Boolean(true)),
+            "false" | "no" | "0" => Ok(EvalResultTypes::Boolean(false)),
+            _ => Err(conversion_error(&value, "boolean")),
+        },
+        _ => Ok(EvalResultTypes::Boolean(value.is_truthy())),
+    }
+}
+
+fn now(ctx: &Context) -> EvalResult {
+    Ok(EvalResultTypes::Datetime(ctx.now()?))
+}
+
+fn today(ctx: &Context) -> EvalResult {
+    Ok(EvalResultTypes::Datetime(ctx.today()?))
+}
+
+const ANY: &[ValueType] = &[];
+const BOOLEAN: &[ValueType] = &[ValueType::Boolean];
+const NUMBER: &[ValueType] = &[ValueType::Number];
+const OPTIONAL_NUMBER: &[ValueType] = &[ValueType::Number, ValueType::Null];
+const STRING: &[ValueType] = &[ValueType::String];
+const OPTIONAL_STRING: &[ValueType] = &[ValueType::String, ValueType::Null];
+const BYTES: &[ValueType] = &[ValueType::Bytes];
+const DATETIME: &[ValueType] = &[ValueType::Datetime];
+const TIMEDELTA: &[ValueType] = &[ValueType::Timedelta];
+const MONEY: &[ValueType] = &[ValueType::Money];
+const SET: &[ValueType] = &[ValueType::Set];
+const SET_OR_LIST: &[ValueType] = &[ValueType::Set, ValueType::List];
+const MAPPING: &[ValueType] = &[ValueType::Mapping];
+const SIZED: &[ValueType] = &[
+    ValueType::Bytes,
+    ValueType::String,
+    ValueType::List,
+    ValueType::Set,
+    ValueType::Mapping,
+];
+const BYTES_OR_STRING: &[ValueType] = &[ValueType::Bytes, ValueType::String];
+
+fn register_time(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::symbol("time.now", now).with_doc("The current datetime in the context's timezone"),
+    )?;
+    registry.register(
+        Builtin::symbol("time.today", today)
+            .with_doc("Midnight of the current day in the context's timezone"),
+    )?;
+    for (name, property, doc) in [
+        (
+            "time.year",
+            year as BuiltinProperty,
+            "The year of a datetime",
+        ),
+        ("time.month", month, "The month of a datetime, from 1 to 12"),
+        ("time.day", day, "The day of the month of a datetime"),
+        ("time.hour", hour, "The hour of a datetime, from 0 to 23"),
+        ("time.minute", minute, "The minute of a datetime"),
+        ("time.second", second, "The second of a datetime"),
+        (
+            "time.weekday",
+            weekday,
+            "The day of the week of a datetime, where Monday is 0 and Sunday is 6",
+        ),
+    ] {
+        registry.register(Builtin::property(name, DATETIME, property).with_doc(doc))?;
+    }
+    for (name, property, doc) in [
+        (
+            "time.days",
+            days as BuiltinProperty,
+            "The whole days of a timedelta, rounded down",
+        ),
+        (
+            "time.seconds",
+            seconds,
+            "The whole seconds of a timedelta remaining after its days",
+        ),
+        (
+            "time.total_seconds",
+            total_seconds,
+            "The length of a timedelta in seconds",
+        ),
+    ] {
+        registry.register(Builtin::property(name, TIMEDELTA, property).with_doc(doc))?;
+    }
+    registry.register(
+        Builtin::function(
+            "time.to_tz",
+            vec![
+                Parameter::required("value", DATETIME),
+                Parameter::required("timezone", STRING),
+            ],
+            to_tz,
+        )
+        .with_doc("The same instant in an IANA timezone, e.g. `America/Denver`"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "time.is_business_day",
+            vec![
+                Parameter::required("value", DATETIME),
+                Parameter::required("region", STRING),
+            ],
+            is_business_day,
+        )
+        .with_doc("Whether a datetime falls on a working day of a region's calendar"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "time.add_business_days",
+            vec![
+                Parameter::required("value", DATETIME),
+                Parameter::required("days", NUMBER),
+                Parameter::required("region", STRING),
+            ],
+            add_business_days,
+        )
+        .with_doc("Moves a datetime by a number of business days, keeping its time of day"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "time.business_days_between",
+            vec![
+                Parameter::required("start", DATETIME),
+                Parameter::required("end", DATETIME),
+                Parameter::required("region", STRING),
+            ],
+            business_days_between,
+        )
+        .with_doc("The number of business days from the start up to, but not including, the end"),
+    )?;
+    Ok(())
+}
+
+fn register_math(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::function(
+            "math.round",
+            vec![
+                Parameter::required("value", &[ValueType::Number, ValueType::Money]),
+                Parameter::optional("places", NUMBER, EvalResultTypes::Integer(0)),
+                Parameter::optional(
+                    "mode",
+                    STRING,
+                    EvalResultTypes::String("half_even".to_string()),
+                ),
+            ],
+            round,
+        )
+        .with_doc("Rounds to a number of decimal places using one of Python's rounding modes"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "math.isclose",
+            vec![
+                Parameter::required("a", NUMBER),
+                Parameter::required("b", NUMBER),
+                Parameter::optional("rel_tol", OPTIONAL_NUMBER, EvalResultTypes::Null),
+                Parameter::optional("abs_tol", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            isclose,
+        )
+        .with_doc("Whether two numbers are equal within the given or the context's tolerances"),
+    )?;
+    registry.register(
+        Builtin::function("math.min", vec![Parameter::required("values", ANY)], min)
+            .variadic()
+            .with_doc("The smallest of the arguments, or of the values of a single list or set"),
+    )?;
+    registry.register(
+        Builtin::function("math.max", vec![Parameter::required("values", ANY)], max)
+            .variadic()
+            .with_doc("The largest of the arguments, or of the values of a single list or set"),
+    )?;
+    for (name, function, doc) in [
+        (
+            "math.ceil",
+            ceil as BuiltinFunctionPointer,
+            "The smallest whole number that is not less than a number",
+        ),
+        (
+            "math.floor",
+            floor,
+            "The largest whole number that is not greater than a number",
+        ),
+        (
+            "math.sign",
+            sign,
+            "-1, 0 or 1 depending on the sign of a number",
+        ),
+        (
+            "math.sqrt",
+            sqrt,
+            "The square root of a non-negative number",
+        ),
+        ("math.exp", exp, "e raised to a number"),
+    ] {
+        registry.register(
+            Builtin::function(name, vec![Parameter::required("value", NUMBER)], function)
+                .with_doc(doc)
+                .readable_as_attribute(),
+        )?;
+    }
+    registry.register(
+        Builtin::function(
+            "math.abs",
+            vec![Parameter::required(
+                "value",
+                &[ValueType::Number, ValueType::Money],
+            )],
+            abs,
+        )
+        .with_doc("The absolute value of a number or amount of money")
+        .readable_as_attribute(),
+    )?;
+    registry.register(
+        Builtin::function(
+            "math.log",
+            vec![
+                Parameter::required("value", NUMBER),
+                Parameter::optional("base", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            log,
+        )
+        .with_doc("The logarithm of a positive number, natural unless a base is given"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "math.pow",
+            vec![
+                Parameter::required("base", NUMBER),
+                Parameter::required("exponent", NUMBER),
+            ],
+            pow,
+        )
+        .with_doc("Raises a number to a power, keeping integers whole for non-negative exponents"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "math.clamp",
+            vec![
+                Parameter::required("value", ANY),
+                Parameter::required("low", ANY),
+                Parameter::required("high", ANY),
+            ],
+            clamp,
+        )
+        .with_doc("Limits a value to the range from low to high"),
+    )?;
+    registry.register(
+        Builtin::property("math.is_nan", NUMBER, is_nan).with_doc("Whether a number is NaN"),
+    )?;
+    registry.register(
+        Builtin::property("math.is_infinite", NUMBER, is_infinite)
+            .with_doc("Whether a number is positive or negative infinity"),
+    )?;
+    Ok(())
+}
+
+fn register_str(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, property, doc) in [
+        (
+            "str.as_lower",
+            as_lower as BuiltinProperty,
+            "A string in lowercase",
+        ),
+        ("str.as_upper", as_upper, "A string in uppercase"),
+        (
+            "str.casefold",
+            casefold,
+            "A string with Unicode case folding applied, for comparisons that ignore case",
+        ),
+        (
+            "str.title",
+            title,
+            "A string with the first letter of every word uppercased and the rest lowercased",
+        ),
+        (
+            "str.trim",
+            trim,
+            "A string without whitespace at its start and end",
+        ),
+        (
+            "str.ltrim",
+            ltrim,
+            "A string without whitespace at its start",
+        ),
+        ("str.rtrim", rtrim, "A string without whitespace at its end"),
+        (
+            "str.to_float",
+            parse_float,
+            "Parses a float, including exponents, `inf` and `nan`",
+        ),
+        (
+            "str.language_code",
+            language_code,
+            "The language of a locale such as `en-US` or a language name such as `English`",
+        ),
+    ] {
+        registry.register(Builtin::property(name, STRING, property).with_doc(doc))?;
+    }
+    for name in ["str.starts_with", "str.startswith"] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![
+                    Parameter::required("value", BYTES_OR_STRING),
+                    Parameter::required("prefix", BYTES_OR_STRING),
+                ],
+                starts_with,
+            )
+            .with_doc("Whether bytes or a string begin with a prefix of the same type"),
+        )?;
+    }
+    for (name, function, parameter, doc) in [
+        (
+            "str.ends_with",
+            ends_with as BuiltinFunctionPointer,
+            "suffix",
+            "Whether bytes or a string end with a suffix of the same type",
+        ),
+        (
+            "str.contains",
+            contains,
+            "needle",
+            "Whether bytes or a string contain a needle of the same type",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![
+                    Parameter::required("value", BYTES_OR_STRING),
+                    Parameter::required(parameter, BYTES_OR_STRING),
+                ],
+                function,
+            )
+            .with_doc(doc),
+        )?;
+    }
+    registry.register(
+        Builtin::function(
+            "str.split",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::optional("separator", OPTIONAL_STRING, EvalResultTypes::Null),
+                Parameter::optional("limit", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            split,
+        )
+        .with_doc("Splits a string on a separator, or on whitespace by default, like Python"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.join",
+            vec![
+                Parameter::required("values", &[ValueType::List]),
+                Parameter::optional("separator", STRING, EvalResultTypes::String(String::new())),
+            ],
+            join,
+        )
+        .with_doc("Joins a list of strings with a separator"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.replace",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::required("old", STRING),
+                Parameter::required("new", STRING),
+                Parameter::optional("count", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            replace,
+        )
+        .with_doc("Replaces every occurrence of a string, or only the first `count`"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.substring",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::required("start", NUMBER),
+                Parameter::optional("length", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            substring,
+        )
+        .with_doc("The characters of a string from a start position, up to a length"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.pad_left",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::required("width", NUMBER),
+                Parameter::optional("fill", STRING, EvalResultTypes::String(" ".to_string())),
+            ],
+            pad_left,
+        )
+        .with_doc("Pads the start of a string with a fill character up to a width"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.to_int",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::optional("base", NUMBER, EvalResultTypes::Integer(10)),
+            ],
+            parse_int,
+        )
+        .with_doc("Parses a whole number written in a base from 2 to 36")
+        .readable_as_attribute(),
+    )?;
+    registry.register(
+        Builtin::function(
+            "str.normalize",
+            vec![
+                Parameter::required("value", STRING),
+                Parameter::optional("form", STRING, EvalResultTypes::String("NFC".to_string())),
+            ],
+            normalize,
+        )
+        .with_doc("Normalizes a string to the NFC, NFKC, NFD or NFKD Unicode normalization form"),
+    )?;
+    Ok(())
+}
+
+fn register_bytes(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::property("bytes.to_hex", BYTES, to_hex)
+            .with_doc("Bytes as lowercase hex digits, two per byte"),
+    )?;
+    registry.register(
+        Builtin::property("bytes.from_hex", STRING, from_hex)
+            .with_doc("The bytes written as hex digits in a string"),
+    )?;
+    registry.register(
+        Builtin::property("bytes.to_base64", BYTES, to_base64)
+            .with_doc("Bytes as standard base64 with padding"),
+    )?;
+    registry.register(
+        Builtin::property("bytes.from_base64", STRING, from_base64)
+            .with_doc("The bytes written as standard base64 in a string"),
+    )?;
+    Ok(())
+}
+
+fn register_money(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::property("money.amount", MONEY, amount)
+            .with_doc("The amount of money as a decimal"),
+    )?;
+    registry.register(
+        Builtin::property("money.currency", MONEY, currency)
+            .with_doc("The ISO-4217 currency code of money"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "money.convert",
+            vec![
+                Parameter::required("value", MONEY),
+                Parameter::required("currency", STRING),
+            ],
+            convert,
+        )
+        .with_doc("Converts money to another currency using the context's exchange rates"),
+    )?;
+    Ok(())
+}
+
+fn register_set(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, function, doc) in [
+        (
+            "set.issubset",
+            issubset as BuiltinFunctionPointer,
+            "Whether every value of a set is in another set or list",
+        ),
+        (
+            "set.issuperset",
+            issuperset,
+            "Whether every value of another set or list is in a set",
+        ),
+        (
+            "set.isdisjoint",
+            isdisjoint,
+            "Whether a set and another set or list have no values in common",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![
+                    Parameter::required("value", SET),
+                    Parameter::required("other", SET_OR_LIST),
+                ],
+                function,
+            )
+            .with_doc(doc),
+        )?;
+    }
+    Ok(())
+}
+
+fn register_list(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, function, doc) in [
+        (
+            "list.first",
+            first as BuiltinFunctionPointer,
+            "The first item of a non-empty list",
+        ),
+        ("list.last", last, "The last item of a non-empty list"),
+        (
+            "list.sum",
+            sum,
+            "The total of a list of numbers, money or timedeltas, 0 when it is empty",
+        ),
+        (
+            "list.avg",
+            avg,
+            "The mean of a non-empty list of numbers, money or timedeltas",
+        ),
+        (
+            "list.median",
+            median,
+            "The middle value of a non-empty list of numbers, money or timedeltas",
+        ),
+        (
+            "list.distinct",
+            distinct,
+            "The values of a list without repeats, in the order they first appear",
+        ),
+        (
+            "list.flatten",
+            flatten,
+            "Replaces the lists within a list with their items, one level deep",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![Parameter::required("values", SET_OR_LIST)],
+                function,
+            )
+            .readable_as_attribute()
+            .with_doc(doc),
+        )?;
+    }
+    registry.register(
+        Builtin::function(
+            "list.sorted",
+            vec![
+                Parameter::required("values", SET_OR_LIST),
+                Parameter::optional("descending", BOOLEAN, EvalResultTypes::Boolean(false)),
+            ],
+            sorted,
+        )
+        .readable_as_attribute()
+        .with_doc("The values in ascending order, or descending order when asked"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "list.reversed",
+            vec![Parameter::required(
+                "values",
+                &[ValueType::List, ValueType::String],
+            )],
+            reversed,
+        )
+        .readable_as_attribute()
+        .with_doc("A list in reverse order, or a string with its characters reversed"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "list.zip",
+            vec![Parameter::required("lists", SET_OR_LIST)],
+            zip,
+        )
+        .variadic()
+        .with_doc("Pairs up the items of lists by position, stopping at the end of the shortest"),
+    )?;
+    for (name, function, doc) in [
+        (
+            "list.contains_all",
+            contains_all as BuiltinFunctionPointer,
+            "Whether a list or set contains every one of the given values",
+        ),
+        (
+            "list.contains_any",
+            contains_any,
+            "Whether a list or set contains at least one of the given values",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![
+                    Parameter::required("values", SET_OR_LIST),
+                    Parameter::required("wanted", SET_OR_LIST),
+                ],
+                function,
+            )
+            .with_doc(doc),
+        )?;
+    }
+    Ok(())
+}
+
+fn register_mapping(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, function, doc) in [
+        (
+            "mapping.keys",
+            keys as BuiltinFunctionPointer,
+            "The keys of a mapping in insertion order",
+        ),
+        (
+            "mapping.values",
+            values,
+            "The values of a mapping in insertion order",
+        ),
+        (
+            "mapping.items",
+            items,
+            "The entries of a mapping as [key, value] lists, in insertion order",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![Parameter::required("mapping", MAPPING)],
+                function,
+            )
+            .readable_as_attribute()
+            .with_doc(doc),
+        )?;
+    }
+    registry.register(
+        Builtin::function(
+            "mapping.get",
+            vec![
+                Parameter::required("mapping", MAPPING),
+                Parameter::required("key", ANY),
+                Parameter::optional("default", ANY, EvalResultTypes::Null),
+            ],
+            get,
+        )
+        .with_doc("The value of a key, or the default when the mapping doesn't have it"),
+    )?;
+    Ok(())
+}
+
+fn register_stats(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, function, doc) in [
+        (
+            "stats.variance",
+            variance as BuiltinFunctionPointer,
+            "The sample variance of a list of numbers, or the population variance when asked",
+        ),
+        (
+            "stats.stddev",
+            stddev,
+            "The sample standard deviation of a list of numbers, or the population one when asked",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(
+                name,
+                vec![
+                    Parameter::required("values", SET_OR_LIST),
+                    Parameter::optional("population", BOOLEAN, EvalResultTypes::Boolean(false)),
+                ],
+                function,
+            )
+            .readable_as_attribute()
+            .with_doc(doc),
+        )?;
+    }
+    registry.register(
+        Builtin::function(
+            "stats.percentile",
+            vec![
+                Parameter::required("values", SET_OR_LIST),
+                Parameter::required("percent", NUMBER),
+            ],
+            percentile,
+        )
+        .with_doc("The value below which a percentage of a list of numbers falls"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "stats.mode",
+            vec![Parameter::required("values", SET_OR_LIST)],
+            mode,
+        )
+        .readable_as_attribute()
+        .with_doc("The most common value of a list, the first to appear of equally common ones"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "stats.zscore",
+            vec![
+                Parameter::required("value", NUMBER),
+                Parameter::required("values", SET_OR_LIST),
+                Parameter::optional("population", BOOLEAN, EvalResultTypes::Boolean(false)),
+            ],
+            zscore,
+        )
+        .with_doc("How many standard deviations a number is from the mean of a list"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "stats.moving_avg",
+            vec![
+                Parameter::required("values", SET_OR_LIST),
+                Parameter::required("window", NUMBER),
+            ],
+            moving_avg,
+        )
+        .with_doc("The means of each run of a number of consecutive values"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "stats.ewma",
+            vec![
+                Parameter::required("values", SET_OR_LIST),
+                Parameter::required("alpha", NUMBER),
+            ],
+            ewma,
+        )
+        .with_doc("Exponentially weighted moving averages with the given smoothing factor"),
+    )?;
+    Ok(())
+}
+
+fn register_locale(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    for (name, property, doc) in [
+        (
+            "locale.region",
+            region as BuiltinProperty,
+            "The region of a locale, or null when it has none",
+        ),
+        (
+            "locale.script",
+            script,
+            "The script of a locale, or null when it has none",
+        ),
+        (
+            "locale.canonicalize",
+            canonicalize,
+            "A locale in its canonical BCP-47 form",
+        ),
+        (
+            "locale.language_name",
+            language_name,
+            "The English name of a locale's language, or null when it isn't known",
+        ),
+    ] {
+        registry.register(Builtin::property(name, STRING, property).with_doc(doc))?;
+    }
+    registry.register(
+        Builtin::function(
+            "locale.locale_matches",
+            vec![
+                Parameter::required("requested", &[ValueType::String, ValueType::List]),
+                Parameter::required("available", SET_OR_LIST),
+            ],
+            locale_matches,
+        )
+        .with_doc("The available locale that best serves the requested ones, or null"),
+    )?;
+    Ok(())
+}
+
+fn register_net(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::function(
+            "net.ip",
+            vec![Parameter::required(
+                "value",
+                &[ValueType::Ip, ValueType::String],
+            )],
+            ip,
+        )
+        .with_doc("Parses an IPv4 or IPv6 address"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "net.cidr",
+            vec![Parameter::required(
+                "value",
+                &[ValueType::Network, ValueType::String],
+            )],
+            cidr,
+        )
+        .with_doc("Parses a network in CIDR notation, e.g. 10.0.0.0/8"),
+    )?;
+    for (name, property, doc) in [
+        (
+            "net.is_private",
+            is_private as BuiltinProperty,
+            "Whether an address is reserved for private networks by RFC 1918 or RFC 4193",
+        ),
+        (
+            "net.is_loopback",
+            is_loopback,
+            "Whether an address is a loopback address",
+        ),
+        (
+            "net.ip_version",
+            ip_version,
+            "4 or 6, the version of the protocol an address belongs to",
+        ),
+    ] {
+        registry.register(
+            Builtin::property(name, &[ValueType::Ip, ValueType::String], property).with_doc(doc),
+        )?;
+    }
+    Ok(())
+}
+
+/// Builtins that apply to many types are registered without a namespace
+fn register_core(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    registry.register(
+        Builtin::property(
+            "length",
+            &[
+                ValueType::Bytes,
+                ValueType::String,
+                ValueType::List,
+                ValueType::Set,
+                ValueType::Mapping,
+            ],
+            length,
+        )
+        .with_doc("The number of bytes, characters, list items, set values or mapping entries"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "is_empty",
+            vec![Parameter::required("value", SIZED)],
+            is_empty,
+        )
+        .readable_as_attribute()
+        .with_doc("Whether bytes, a string, list, set or mapping has nothing in it"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "index_of",
+            vec![
+                Parameter::required(
+                    "value",
+                    &[ValueType::Bytes, ValueType::String, ValueType::List],
+                ),
+                Parameter::required("needle", ANY),
+            ],
+            index_of,
+        )
+        .with_doc("The position of the first occurrence of a needle, or -1 when there is none"),
+    )?;
+    registry.register(
+        Builtin::function(
+            "slice",
+            vec![
+                Parameter::required(
+                    "value",
+                    &[ValueType::Bytes, ValueType::String, ValueType::List],
+                ),
+                Parameter::required("start", NUMBER),
+                Parameter::optional("end", OPTIONAL_NUMBER, EvalResultTypes::Null),
+            ],
+            slice,
+        )
+        .with_doc(
+            "The part of bytes, a string or a list between two indices, like Python's slices",
+        ),
+    )?;
+    for (name, function, doc) in [
+        (
+            "int",
+            int as BuiltinFunctionPointer,
+            "Converts to an integer, truncating toward zero",
+        ),
+        ("float", float, "Converts to a float"),
+        ("str", str, "Converts to a string"),
+        (
+            "bool",
+            bool,
+            "Converts to a boolean, parsing strings such as `yes` and `false`",
+        ),
+    ] {
+        registry.register(
+            Builtin::function(name, vec![Parameter::required("value", ANY)], function)
+                .with_doc(doc),
+        )?;
+    }
+    Ok(())
+}
+
+type BuiltinProperty = fn(EvalResultTypes) -> EvalResult;
+type BuiltinFunctionPointer = fn(&Context, Vec<EvalResultTypes>) -> EvalResult;
+
+/// The builtins every context starts with. They are built once and shared until a context
+/// registers builtins of its own.
+fn register_standard(registry: &mut BuiltinRegistry) -> Result<(), ContextError> {
+    register_core(registry)?;
+    register_str(registry)?;
+    register_math(registry)?;
+    register_time(registry)?;
+    register_money(registry)?;
+    register_set(registry)?;
+    register_bytes(registry)?;
+    register_list(registry)?;
+    register_mapping(registry)?;
+    register_stats(registry)?;
+    register_locale(registry)?;
+    register_net(registry)
+}
+
+pub fn standard_builtins() -> Arc<BuiltinRegistry> {
+    static STANDARD: OnceLock<Arc<BuiltinRegistry>> = OnceLock::new();
+    STANDARD
+        .get_or_init(|| {
+            let mut registry = BuiltinRegistry::new();
+            register_standard(&mut registry).expect("standard builtins have distinct names");
+            Arc::new(registry)
+        })
+        .clone()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn call_builtin(ctx: &Context, name: &str, args: Vec<EvalResultTypes>) -> EvalResult {
+        ctx.builtins().resolve(name).unwrap().call(ctx, args)
+    }
+
     #[test]
     fn test_as_lower() {
         assert_eq!(
@@ -66,7 +2730,183 @@ mod tests {
             language_code(EvalResultTypes::String("en-US".to_string())).unwrap(),
             EvalResultTypes::String("en".to_string())
         );
+        for (name, code) in [
+            ("english", "en"),
+            ("English", "en"),
+            ("Brazilian Portuguese", "pt"),
+            ("Farsi", "fa"),
+        ] {
+            assert_eq!(
+                language_code(EvalResultTypes::String(name.to_string())).unwrap(),
+                EvalResultTypes::String(code.to_string()),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            language_code(EvalResultTypes::String("Notalanguage".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "Unable to find valid language code for 'Notalanguage'"
+        );
         assert!(language_code(EvalResultTypes::Integer(1)).is_err());
         assert!(language_code(EvalResultTypes::String("".to_string())).is_err());
     }
+
+    #[test]
+    fn test_datetime_components() {
+        let value = EvalResultTypes::Datetime(
+            DateTime::parse_from_rfc3339("2024-02-29T13:45:30-07:00").unwrap(),
+        );
+        assert_eq!(year(value.clone()).unwrap(), EvalResultTypes::Integer(2024));
+        assert_eq!(month(value.clone()).unwrap(), EvalResultTypes::Integer(2));
+        assert_eq!(day(value.clone()).unwrap(), EvalResultTypes::Integer(29));
+        assert_eq!(hour(value.clone()).unwrap(), EvalResultTypes::Integer(13));
+        assert_eq!(minute(value.clone()).unwrap(), EvalResultTypes::Integer(45));
+        assert_eq!(second(value.clone()).unwrap(), EvalResultTypes::Integer(30));
+        // 2024-02-29 was a Thursday
+        assert_eq!(weekday(value).unwrap(), EvalResultTypes::Integer(3));
+        assert!(year(EvalResultTypes::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_timedelta_components() {
+        let value = EvalResultTypes::Timedelta(TimeDelta::seconds(-90));
+        // Matches Python: timedelta(seconds=-90) == timedelta(days=-1, seconds=86310)
+        assert_eq!(days(value.clone()).unwrap(), EvalResultTypes::Integer(-1));
+        assert_eq!(
+            seconds(value.clone()).unwrap(),
+            EvalResultTypes::Integer(86310)
+        );
+        assert_eq!(total_seconds(value).unwrap(), EvalResultTypes::Float(-90.0));
+        assert!(days(EvalResultTypes::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_to_tz() {
+        let ctx = Context::default();
+        let value = EvalResultTypes::Datetime(
+            DateTime::parse_from_rfc3339("2024-07-01T03:00:00Z").unwrap(),
+        );
+        let converted = call_builtin(
+            &ctx,
+            "to_tz",
+            vec![
+                value.clone(),
+                EvalResultTypes::String("America/Denver".into()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            converted,
+            EvalResultTypes::Datetime(
+                DateTime::parse_from_rfc3339("2024-06-30T21:00:00-06:00").unwrap()
+            )
+        );
+        // Converting keeps the instant but moves the calendar day back to Sunday
+        assert_eq!(weekday(converted).unwrap(), EvalResultTypes::Integer(6));
+        assert!(call_builtin(
+            &ctx,
+            "to_tz",
+            vec![
+                value.clone(),
+                EvalResultTypes::String("Mars/Olympus".into())
+            ]
+        )
+        .is_err());
+        assert!(call_builtin(&ctx, "to_tz", vec![value]).is_err());
+    }
+
+    #[test]
+    fn test_round() {
+        let ctx = Context::default();
+        let decimal = |text: &str| EvalResultTypes::Decimal(text.parse().unwrap());
+        assert_eq!(
+            call_builtin(
+                &ctx,
+                "round",
+                vec![decimal("2.345"), EvalResultTypes::Integer(2)]
+            )
+            .unwrap(),
+            decimal("2.34")
+        );
+        assert_eq!(
+            round(
+                &ctx,
+                vec![
+                    decimal("2.345"),
+                    EvalResultTypes::Float(2.0),
+                    EvalResultTypes::String("half_up".into())
+                ]
+            )
+            .unwrap(),
+            decimal("2.35")
+        );
+        assert_eq!(
+            call_builtin(&ctx, "round", vec![decimal("-2.5")]).unwrap(),
+            decimal("-2")
+        );
+        assert_eq!(
+            round(
+                &ctx,
+                vec![
+                    EvalResultTypes::Float(2.675),
+                    EvalResultTypes::Integer(2),
+                    EvalResultTypes::String("HALF_UP".into())
+                ]
+            )
+            .unwrap(),
+            EvalResultTypes::Float(2.68)
+        );
+        assert!(call_builtin(
+            &ctx,
+            "round",
+            vec![
+                decimal("1"),
+                EvalResultTypes::Integer(0),
+                EvalResultTypes::String("sideways".into())
+            ]
+        )
+        .is_err());
+        assert!(call_builtin(
+            &ctx,
+            "round",
+            vec![decimal("1"), EvalResultTypes::Integer(-1)]
+        )
+        .is_err());
+        assert!(call_builtin(&ctx, "round", vec![EvalResultTypes::String("1".into())]).is_err());
+    }
+
+    #[test]
+    fn test_slice_bounds() {
+        assert_eq!(slice_bounds(5, 1, Some(3)), (1, 3));
+        assert_eq!(slice_bounds(5, -2, None), (3, 5));
+        assert_eq!(slice_bounds(5, 0, Some(-1)), (0, 4));
+        assert_eq!(slice_bounds(5, -10, Some(10)), (0, 5));
+        // An end before the start is an empty slice
+        assert_eq!(slice_bounds(5, 4, Some(2)), (4, 4));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let bytes = EvalResultTypes::Bytes(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            to_hex(bytes.clone()).unwrap(),
+            EvalResultTypes::String("deadbeef".to_string())
+        );
+        assert_eq!(
+            from_hex(EvalResultTypes::String("DEADBEEF".to_string())).unwrap(),
+            bytes
+        );
+        assert_eq!(
+            to_base64(bytes.clone()).unwrap(),
+            EvalResultTypes::String("3q2+7w==".to_string())
+        );
+        assert_eq!(
+            from_base64(EvalResultTypes::String("3q2+7w==".to_string())).unwrap(),
+            bytes
+        );
+        assert!(from_base64(EvalResultTypes::String("not base64".to_string())).is_err());
+        assert!(to_hex(EvalResultTypes::String("deadbeef".to_string())).is_err());
+    }
 }
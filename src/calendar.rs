@@ -0,0 +1,213 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::errors::ContextError;
+
+/// A region's non-working days, used by the business day builtins
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusinessCalendar {
+    weekend: HashSet<Weekday>,
+    holidays: HashSet<NaiveDate>,
+}
+
+/// The on-disk shape of a single region, e.g.
+/// `{"weekend": ["saturday", "sunday"], "holidays": ["2024-12-25"]}`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CalendarDefinition {
+    weekend: Option<Vec<String>>,
+    #[serde(default)]
+    holidays: Vec<String>,
+}
+
+impl BusinessCalendar {
+    pub fn new(weekend: HashSet<Weekday>, holidays: HashSet<NaiveDate>) -> Self {
+        BusinessCalendar { weekend, holidays }
+    }
+
+    fn from_definition(region: &str, definition: CalendarDefinition) -> Result<Self, ContextError> {
+        let invalid = |what: &str, value: &str| {
+            ContextError::new(&format!(
+                "Invalid {} '{}' in calendar '{}'",
+                what, value, region
+            ))
+        };
+        // Saturday and Sunday unless the region says otherwise
+        let weekend = match definition.weekend {
+            Some(days) => days
+                .iter()
+                .map(|day| day.parse().map_err(|_| invalid("weekday", day)))
+                .collect::<Result<_, _>>()?,
+            None => HashSet::from([Weekday::Sat, Weekday::Sun]),
+        };
+        if weekend.len() == 7 {
+            return Err(ContextError::new(&format!(
+                "Calendar '{}' has no business days",
+                region
+            )));
+        }
+        let holidays = definition
+            .holidays
+            .iter()
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid("holiday", date))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BusinessCalendar::new(weekend, holidays))
+    }
+
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        !self.weekend.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    /// Moves `days` business days away from `date`. With zero days, a non-business day rolls
+    /// forward to the next business day.
+    pub fn add_business_days(&self, date: NaiveDate, days: i64) -> Option<NaiveDate> {
+        let step = if days < 0 { -1 } else { 1 };
+        let mut current = date;
+        if days == 0 {
+            while !self.is_business_day(current) {
+                current = current.succ_opt()?;
+            }
+            return Some(current);
+        }
+        let mut remaining = days.unsigned_abs();
+        while remaining > 0 {
+            current = current.checked_add_signed(chrono::TimeDelta::days(step))?;
+            if self.is_business_day(current) {
+                remaining -= 1;
+            }
+        }
+        Some(current)
+    }
+
+    /// Counts the business days in `[start, end)`, negated when `end` is before `start`
+    pub fn business_days_between(&self, start: NaiveDate, end: NaiveDate) -> i64 {
+        let (from, to, sign) = if start <= end {
+            (start, end, 1)
+        } else {
+            (end, start, -1)
+        };
+        let count = from
+            .iter_days()
+            .take_while(|date| *date < to)
+            .filter(|date| self.is_business_day(*date))
+            .count();
+        sign * count as i64
+    }
+}
+
+/// Reads a JSON file mapping region names to calendar definitions
+pub fn load_calendars(path: &Path) -> Result<HashMap<String, BusinessCalendar>, ContextError> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        ContextError::new(&format!(
+            "Unable to read calendar file '{}': {}",
+            path.display(),
+            err
+        ))
+    })?;
+    let definitions: HashMap<String, CalendarDefinition> =
+        serde_json::from_str(&text).map_err(|err| {
+            ContextError::new(&format!(
+                "Invalid calendar file '{}': {}",
+                path.display(),
+                err
+            ))
+        })?;
+    definitions
+        .into_iter()
+        .map(|(region, definition)| {
+            let calendar = BusinessCalendar::from_definition(&region, definition)?;
+            Ok((region, calendar))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn us_calendar() -> BusinessCalendar {
+        BusinessCalendar::new(
+            HashSet::from([Weekday::Sat, Weekday::Sun]),
+            HashSet::from([date("2024-07-04")]),
+        )
+    }
+
+    #[test]
+    fn test_is_business_day() {
+        let calendar = us_calendar();
+        assert!(calendar.is_business_day(date("2024-07-03")));
+        assert!(!calendar.is_business_day(date("2024-07-04")));
+        assert!(!calendar.is_business_day(date("2024-07-06")));
+    }
+
+    #[test]
+    fn test_add_business_days() {
+        let calendar = us_calendar();
+        // Wednesday + 1 skips the Thursday holiday
+        assert_eq!(
+            calendar.add_business_days(date("2024-07-03"), 1),
+            Some(date("2024-07-05"))
+        );
+        // Friday + 1 skips the weekend
+        assert_eq!(
+            calendar.add_business_days(date("2024-07-05"), 1),
+            Some(date("2024-07-08"))
+        );
+        assert_eq!(
+            calendar.add_business_days(date("2024-07-08"), -2),
+            Some(date("2024-07-03"))
+        );
+        assert_eq!(
+            calendar.add_business_days(date("2024-07-06"), 0),
+            Some(date("2024-07-08"))
+        );
+    }
+
+    #[test]
+    fn test_business_days_between() {
+        let calendar = us_calendar();
+        assert_eq!(
+            calendar.business_days_between(date("2024-07-01"), date("2024-07-08")),
+            4
+        );
+        assert_eq!(
+            calendar.business_days_between(date("2024-07-08"), date("2024-07-01")),
+            -4
+        );
+        assert_eq!(
+            calendar.business_days_between(date("2024-07-01"), date("2024-07-01")),
+            0
+        );
+    }
+
+    #[test]
+    fn test_load_calendars() {
+        let path = std::env::temp_dir().join("rust_rule_engine_test_calendars.json");
+        fs::write(
+            &path,
+            r#"{
+                "us": {"holidays": ["2024-07-04"]},
+                "ae": {"weekend": ["saturday", "sunday"], "holidays": []},
+                "il": {"weekend": ["Fri", "Sat"]}
+            }"#,
+        )
+        .unwrap();
+        let calendars = load_calendars(&path).unwrap();
+        assert_eq!(calendars["us"], us_calendar());
+        assert!(calendars["il"].is_business_day(date("2024-07-07")));
+
+        fs::write(&path, r#"{"us": {"holidays": ["July 4th"]}}"#).unwrap();
+        assert!(load_calendars(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(load_calendars(&path).is_err());
+    }
+}
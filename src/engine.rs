@@ -1,23 +1,353 @@
-use crate::ast::{EvalResult, EvalResultTypes, Statement};
-use crate::builtins::resolve_builtin_methods;
-use crate::errors::{EvaluationError, SymbolResolutionError};
+use crate::ast::{EvalResult, EvalResultTypes, FunctionDefinition, PrimaryExpression, Program};
+use crate::builtins::standard_builtins;
+use crate::calendar::{load_calendars, BusinessCalendar};
+use crate::errors::{ContextError, EvaluationError, SymbolResolutionError};
 use crate::parser;
-use crate::utils::get_value_from_py_dict;
+use crate::registry::{Builtin, BuiltinRegistry};
+use crate::utils::{get_value_from_py_dict, py_datetime_to_datetime};
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use icu_collator::{Collator, CollatorOptions};
+use icu_locid::Locale;
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use std::any::Any;
+use pyo3::types::{PyDateTime, PyDict};
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The default limit on how deeply rule-defined functions may call one another
+const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+/// The default limit on the size of strings (in bytes) and lists (in elements) built by rules
+const DEFAULT_MAX_SEQUENCE_LENGTH: usize = 1_000_000;
+
+/// Where `$now` and `$today` get the current time from
+#[derive(Clone)]
+pub enum Clock {
+    System,
+    /// Pins the current time, e.g. so tests are deterministic
+    Fixed(DateTime<FixedOffset>),
+    /// A Python callable returning a `datetime`, called each time the clock is read
+    Python(Py<PyAny>),
+}
+impl Clock {
+    /// A fixed clock for a `datetime`, or a clock that calls the given callable
+    pub fn from_py(value: &PyAny) -> PyResult<Self> {
+        if value.is_callable() {
+            return Ok(Clock::Python(value.into()));
+        }
+        let value: &PyDateTime = value
+            .downcast()
+            .map_err(|_| ContextError::new("now must be a datetime or a callable returning one"))?;
+        Ok(Clock::Fixed(py_datetime_to_datetime(value)?))
+    }
+
+    fn now(&self) -> Result<DateTime<FixedOffset>, EvaluationError> {
+        match self {
+            Clock::System => Ok(Utc::now().fixed_offset()),
+            Clock::Fixed(value) => Ok(*value),
+            Clock::Python(callable) => Python::with_gil(|py| {
+                let value = callable
+                    .call0(py)
+                    .map_err(|err| EvaluationError::new(&format!("Clock failed: {}", err)))?;
+                let value: &PyDateTime = value
+                    .downcast(py)
+                    .map_err(|_| EvaluationError::new("Clock must return a datetime"))?;
+                py_datetime_to_datetime(value).map_err(|err| EvaluationError::new(&err.to_string()))
+            }),
+        }
+    }
+}
+
+/// What happens when integer arithmetic produces a value outside the range of an `i64`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntegerOverflow {
+    /// Continue with an arbitrary-precision integer, like Python
+    Promote,
+    /// Fail the evaluation with an `IntegerOverflowError`
+    Error,
+}
+
+/// How far apart two numbers may be and still be considered equal by `~=` and `isclose`. The
+/// defaults match Python's `math.isclose`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// The allowed difference relative to the larger magnitude
+    pub relative: f64,
+    /// The allowed difference regardless of magnitude, needed for comparisons near zero
+    pub absolute: f64,
+}
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            relative: 1e-9,
+            absolute: 0.0,
+        }
+    }
+}
+impl Tolerance {
+    pub fn new(relative: f64, absolute: f64) -> Result<Self, ContextError> {
+        if !(relative >= 0.0 && absolute >= 0.0) {
+            return Err(ContextError::new("Tolerances must be non-negative"));
+        }
+        Ok(Tolerance { relative, absolute })
+    }
+
+    /// NaN is close to nothing, including itself, and an infinity is only close to itself
+    pub fn is_close(&self, lhs: f64, rhs: f64) -> bool {
+        if lhs == rhs {
+            return true;
+        }
+        if !lhs.is_finite() || !rhs.is_finite() {
+            return false;
+        }
+        let difference = (lhs - rhs).abs();
+        difference <= (self.relative * lhs.abs().max(rhs.abs())).max(self.absolute)
+    }
+}
+
+/// The parts of a context that stay the same while a rule is evaluated, shared between a context
+/// and the scopes created from it rather than copied into each
+#[derive(Clone)]
+struct Settings {
+    max_call_depth: usize,
+    /// Locale-aware ordering for strings. Strings are ordered by code point when this is unset.
+    collator: Option<Arc<Collator>>,
+    max_sequence_length: usize,
+    clock: Clock,
+    /// The timezone `$now` and `$today` are expressed in
+    timezone: Tz,
+    /// Business calendars by region, used by builtins such as `add_business_days`
+    calendars: HashMap<String, Arc<BusinessCalendar>>,
+    /// Whether numeric literals evaluate to decimals rather than floats
+    decimal_literals: bool,
+    integer_overflow: IntegerOverflow,
+    /// Exchange rates keyed by `(from, to)` currency codes, where one unit of `from` is worth the
+    /// rate in `to`
+    exchange_rates: HashMap<(String, String), Decimal>,
+    tolerance: Tolerance,
+    builtins: Arc<BuiltinRegistry>,
+}
 
 #[pyclass]
+#[derive(Clone)]
 pub struct Context {
-    // TODO: Is this even needed? We don't have statements so idk how assignments would work
+    /// Symbols bound by the rule itself. Programs evaluate against a copy of the caller's context,
+    /// so bindings never outlive a single evaluation.
     assignments: HashMap<String, EvalResultTypes>,
+    /// Functions defined by the rule being evaluated
+    functions: Arc<HashMap<String, Arc<FunctionDefinition>>>,
+    /// The number of function calls currently being evaluated
+    call_depth: usize,
+    settings: Arc<Settings>,
+}
+impl Default for Context {
+    fn default() -> Self {
+        Context::new(None)
+    }
 }
 impl Context {
     fn new(assignments: Option<HashMap<String, EvalResultTypes>>) -> Self {
         Context {
-            assignments: assignments.unwrap_or(HashMap::new()),
+            assignments: assignments.unwrap_or_default(),
+            functions: Arc::new(HashMap::new()),
+            call_depth: 0,
+            settings: Arc::new(Settings {
+                max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+                collator: None,
+                max_sequence_length: DEFAULT_MAX_SEQUENCE_LENGTH,
+                clock: Clock::System,
+                timezone: Tz::UTC,
+                calendars: HashMap::new(),
+                decimal_literals: false,
+                integer_overflow: IntegerOverflow::Promote,
+                exchange_rates: HashMap::new(),
+                tolerance: Tolerance::default(),
+                builtins: standard_builtins(),
+            }),
+        }
+    }
+
+    /// The settings of this context alone, copied first if they are shared with another
+    fn settings_mut(&mut self) -> &mut Settings {
+        Arc::make_mut(&mut self.settings)
+    }
+
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        &self.settings.builtins
+    }
+
+    /// Makes a builtin available to rules evaluated with this context, replacing any registered
+    /// under the same qualified name. Fails when a builtin in another namespace has the same name.
+    pub fn register_builtin(&mut self, builtin: Builtin) -> Result<(), ContextError> {
+        Arc::make_mut(&mut self.settings_mut().builtins).register(builtin)
+    }
+
+    pub fn set_exchange_rate(&mut self, from: &str, to: &str, rate: Decimal) {
+        self.settings_mut()
+            .exchange_rates
+            .insert((from.to_string(), to.to_string()), rate);
+    }
+
+    /// The rate to convert `from` into `to`. A rate registered in the opposite direction is
+    /// inverted when there is no direct one.
+    pub fn exchange_rate(&self, from: &str, to: &str) -> Result<Decimal, EvaluationError> {
+        if from == to {
+            return Ok(Decimal::ONE);
+        }
+        let key = |from: &str, to: &str| (from.to_string(), to.to_string());
+        if let Some(rate) = self.settings.exchange_rates.get(&key(from, to)) {
+            return Ok(*rate);
+        }
+        self.settings
+            .exchange_rates
+            .get(&key(to, from))
+            .and_then(|rate| Decimal::ONE.checked_div(*rate))
+            .ok_or_else(|| {
+                EvaluationError::new(&format!("No exchange rate from {} to {}", from, to))
+            })
+    }
+
+    pub fn tolerance(&self) -> Tolerance {
+        self.settings.tolerance
+    }
+
+    pub fn set_tolerance(&mut self, tolerance: Tolerance) {
+        self.settings_mut().tolerance = tolerance;
+    }
+
+    pub fn integer_overflow(&self) -> IntegerOverflow {
+        self.settings.integer_overflow
+    }
+
+    pub fn set_integer_overflow(&mut self, mode: IntegerOverflow) {
+        self.settings_mut().integer_overflow = mode;
+    }
+
+    pub fn decimal_literals(&self) -> bool {
+        self.settings.decimal_literals
+    }
+
+    pub fn set_decimal_literals(&mut self, enabled: bool) {
+        self.settings_mut().decimal_literals = enabled;
+    }
+
+    pub fn register_calendar(&mut self, region: &str, calendar: BusinessCalendar) {
+        self.settings_mut()
+            .calendars
+            .insert(region.to_string(), Arc::new(calendar));
+    }
+
+    pub fn calendar(&self, region: &str) -> Result<&BusinessCalendar, EvaluationError> {
+        self.settings
+            .calendars
+            .get(region)
+            .map(|calendar| calendar.as_ref())
+            .ok_or_else(|| {
+                EvaluationError::new(&format!("No business calendar for region '{}'", region))
+            })
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.settings_mut().clock = clock;
+    }
+
+    /// Sets the IANA timezone, e.g. `America/Denver`, used for `$now` and `$today`
+    pub fn set_timezone(&mut self, name: &str) -> Result<(), ContextError> {
+        self.settings_mut().timezone = name
+            .parse()
+            .map_err(|_| ContextError::new(&format!("Unknown timezone '{}'", name)))?;
+        Ok(())
+    }
+
+    pub fn now(&self) -> Result<DateTime<FixedOffset>, EvaluationError> {
+        Ok(self
+            .settings
+            .clock
+            .now()?
+            .with_timezone(&self.settings.timezone)
+            .fixed_offset())
+    }
+
+    /// Midnight at the start of the current day in the context's timezone
+    pub fn today(&self) -> Result<DateTime<FixedOffset>, EvaluationError> {
+        let midnight = self.now()?.date_naive().and_time(NaiveTime::MIN);
+        // Midnight may be skipped by a DST transition, in which case the day starts an hour later
+        let start = self
+            .settings
+            .timezone
+            .from_local_datetime(&midnight)
+            .earliest()
+            .or_else(|| {
+                self.settings
+                    .timezone
+                    .from_local_datetime(&(midnight + chrono::TimeDelta::hours(1)))
+                    .earliest()
+            })
+            .ok_or_else(|| EvaluationError::new("Unable to determine the start of today"))?;
+        Ok(start.fixed_offset())
+    }
+
+    /// Guards against rules such as `"a" * 1e12` exhausting memory
+    pub fn check_sequence_length(&self, length: usize) -> Result<(), EvaluationError> {
+        if length > self.settings.max_sequence_length {
+            return Err(EvaluationError::new(&format!(
+                "Result of length {} exceeds the maximum of {}",
+                length, self.settings.max_sequence_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Orders strings using the collation rules of the given BCP-47 locale, e.g. `sv` or `de-u-co-phonebk`
+    pub fn set_collation(&mut self, locale: &str) -> Result<(), ContextError> {
+        let unsupported =
+            || ContextError::new(&format!("Unsupported collation locale '{}'", locale));
+        let parsed: Locale = locale.parse().map_err(|_| unsupported())?;
+        let collator = Collator::try_new(&(&parsed).into(), CollatorOptions::new())
+            .map_err(|_| unsupported())?;
+        self.settings_mut().collator = Some(Arc::new(collator));
+        Ok(())
+    }
+
+    pub fn collate(&self, lhs: &str, rhs: &str) -> Ordering {
+        match &self.settings.collator {
+            Some(collator) => collator.compare(lhs, rhs),
+            None => lhs.cmp(rhs),
+        }
+    }
+
+    pub fn bind(&mut self, name: &str, value: EvalResultTypes) {
+        self.assignments.insert(name.to_string(), value);
+    }
+
+    pub fn define_function(&mut self, function: Arc<FunctionDefinition>) {
+        Arc::make_mut(&mut self.functions).insert(function.name.clone(), function);
+    }
+
+    pub fn resolve_function(&self, name: &str) -> Result<Arc<FunctionDefinition>, EvaluationError> {
+        self.functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvaluationError::new(&format!("Function {} is not defined", name)))
+    }
+
+    /// Creates the scope a function body is evaluated in. The scope shares the caller's functions
+    /// but none of its bindings.
+    pub fn call_frame(&self) -> Result<Context, EvaluationError> {
+        if self.call_depth >= self.settings.max_call_depth {
+            return Err(EvaluationError::new(&format!(
+                "Maximum call depth of {} exceeded",
+                self.settings.max_call_depth
+            )));
         }
+        Ok(Context {
+            assignments: HashMap::new(),
+            functions: Arc::clone(&self.functions),
+            call_depth: self.call_depth + 1,
+            settings: Arc::clone(&self.settings),
+        })
     }
 
     pub fn resolve(
@@ -32,7 +362,12 @@ impl Context {
         if let Some(dict) = thing {
             match get_value_from_py_dict(dict, &[name]) {
                 Ok(Some(value)) => return Ok(value),
-                Err(_) => return Err(SymbolResolutionError::new("Failed to get value")),
+                Err(err) => {
+                    return Err(SymbolResolutionError::with_cause(
+                        "Failed to get value",
+                        err,
+                    ))
+                }
                 _ => {}
             }
         }
@@ -42,25 +377,68 @@ impl Context {
         )))
     }
 
+    /// Whether the keys of a symbol or attribute are absent from the input, rather than present
+    /// with a value that couldn't be read, e.g. `discount` or `order.discount` when `order` has no
+    /// `discount` key
+    pub fn is_missing(&self, keys: &[&str], thing: Option<&PyDict>) -> bool {
+        if keys.is_empty() || self.assignments.contains_key(keys[0]) {
+            return false;
+        }
+        let Some(dict) = thing else {
+            return true;
+        };
+        let mut current: &PyAny = dict.as_ref();
+        for key in keys {
+            let Ok(mapping) = current.downcast::<PyDict>() else {
+                return false;
+            };
+            match mapping.get_item(key) {
+                Some(value) => current = value,
+                None => return true,
+            }
+        }
+        false
+    }
+
     pub fn resolve_attribute(
         &self,
         keys: &[&str],
         thing: Option<&PyDict>,
     ) -> Result<EvalResultTypes, SymbolResolutionError> {
-        // If the last key is a builtin method, we need to resolve the value of the attribute and then call the method
-        if let Ok(builtin_method) = resolve_builtin_methods(keys[keys.len() - 1]) {
-            let value = self.resolve_attribute(&keys[..keys.len() - 1], thing)?;
-            return builtin_method(value)
-                .map_err(|err| SymbolResolutionError::new(&err.to_string()));
+        if let [name] = keys {
+            return self.resolve(&name.to_string(), thing);
         }
-        // Fetch attribute's value from original python object
+        // Fetch attribute's value from original python object. Data is preferred to builtin
+        // properties so that keys named like one (e.g. `language_code`) can still be read, while
+        // `value.language_code()` always calls the builtin.
         if let Some(dict) = thing {
             match get_value_from_py_dict(dict, keys) {
                 Ok(Some(value)) => return Ok(value),
-                Err(_) => return Err(SymbolResolutionError::new("Failed to get value")),
+                Err(err) => {
+                    return Err(SymbolResolutionError::with_cause(
+                        "Failed to get value",
+                        err,
+                    ))
+                }
                 _ => {}
             }
         }
+        // Otherwise the last key is looked up on the value of the others, which may be a mapping
+        // produced along the way (e.g. the `sku` of `lines.first.sku`) or a builtin property's
+        // receiver
+        let name = keys[keys.len() - 1];
+        if let Ok(value) = self.resolve_attribute(&keys[..keys.len() - 1], thing) {
+            if let EvalResultTypes::Mapping(mapping) = &value {
+                if let Some(found) = mapping.get(&EvalResultTypes::String(name.to_string())) {
+                    return Ok(found.clone());
+                }
+            }
+            if let Some(property) = self.settings.builtins.property(name) {
+                return property
+                    .call(self, vec![value])
+                    .map_err(SymbolResolutionError::from);
+            }
+        }
         Err(SymbolResolutionError::new(&format!(
             "Symbol {} not found",
             keys.join("."),
@@ -68,20 +446,152 @@ impl Context {
     }
 }
 
+#[pymethods]
+impl Context {
+    #[new]
+    #[pyo3(signature = (
+        *,
+        collation = None,
+        max_sequence_length = DEFAULT_MAX_SEQUENCE_LENGTH,
+        timezone = "UTC",
+        now = None,
+        decimal_literals = false,
+        integer_overflow = "promote",
+        exchange_rates = None,
+        rel_tol = 1e-9,
+        abs_tol = 0.0,
+    ))]
+    // Each keyword argument of the Python constructor is a parameter
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        collation: Option<&str>,
+        max_sequence_length: usize,
+        timezone: &str,
+        now: Option<&PyAny>,
+        decimal_literals: bool,
+        integer_overflow: &str,
+        exchange_rates: Option<HashMap<String, HashMap<String, Decimal>>>,
+        rel_tol: f64,
+        abs_tol: f64,
+    ) -> PyResult<Self> {
+        let mut ctx = Context::new(None);
+        ctx.set_tolerance(Tolerance::new(rel_tol, abs_tol)?);
+        ctx.set_decimal_literals(decimal_literals);
+        for (from, rates) in exchange_rates.unwrap_or_default() {
+            for (to, rate) in rates {
+                ctx.set_exchange_rate(&from, &to, rate);
+            }
+        }
+        ctx.set_integer_overflow(match integer_overflow {
+            "promote" => IntegerOverflow::Promote,
+            "error" => IntegerOverflow::Error,
+            _ => {
+                return Err(ContextError::new(&format!(
+                    "Unknown integer overflow mode '{}', expected 'promote' or 'error'",
+                    integer_overflow
+                ))
+                .into())
+            }
+        });
+        if let Some(locale) = collation {
+            ctx.set_collation(locale)?;
+        }
+        ctx.settings_mut().max_sequence_length = max_sequence_length;
+        ctx.set_timezone(timezone)?;
+        if let Some(now) = now {
+            ctx.set_clock(Clock::from_py(now)?);
+        }
+        Ok(ctx)
+    }
+
+    /// Lets rules call a Python function, e.g. `is_vip(customer)`, optionally within a namespace
+    /// such as `crm.is_vip`. Rules that call it must be given this context when they are created.
+    /// Exceptions raised by the function become evaluation errors caused by the exception.
+    #[pyo3(signature = (name, function, *, arg_types = None, return_type = None, pure = false, doc = None))]
+    // Each keyword argument of the Python method is a parameter
+    #[allow(clippy::too_many_arguments)]
+    pub fn register_function(
+        &mut self,
+        py: Python,
+        name: &str,
+        function: PyObject,
+        arg_types: Option<Vec<Option<String>>>,
+        return_type: Option<String>,
+        pure: bool,
+        doc: Option<&str>,
+    ) -> PyResult<()> {
+        let mut builtin = Builtin::from_python(py, name, function, arg_types, return_type, pure)?;
+        if let Some(doc) = doc {
+            builtin = builtin.with_doc(doc);
+        }
+        self.register_builtin(builtin)?;
+        Ok(())
+    }
+
+    /// Describes every builtin available to rules, e.g. for editor completion or generating
+    /// documentation
+    #[pyo3(name = "builtins")]
+    pub fn describe_builtins(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let mut descriptions = Vec::new();
+        for builtin in self.settings.builtins.iter() {
+            let mut parameters = Vec::new();
+            for parameter in &builtin.signature.parameters {
+                let description = PyDict::new(py);
+                description.set_item("name", &parameter.name)?;
+                let types: Vec<&str> = parameter.types.iter().map(|t| t.name()).collect();
+                description.set_item("types", types)?;
+                if let Some(default) = &parameter.default {
+                    description.set_item("default", default.clone().into_py(py))?;
+                }
+                parameters.push(description);
+            }
+            let description = PyDict::new(py);
+            description.set_item("name", builtin.qualified_name())?;
+            description.set_item("namespace", &builtin.namespace)?;
+            description.set_item("kind", builtin.kind.name())?;
+            description.set_item("signature", builtin.signature.to_string())?;
+            description.set_item("parameters", parameters)?;
+            description.set_item("variadic", builtin.signature.variadic)?;
+            description.set_item("doc", &builtin.doc)?;
+            descriptions.push(description.into());
+        }
+        Ok(descriptions)
+    }
+
+    /// Registers every region defined in a JSON calendar file, replacing regions of the same name
+    pub fn load_calendars(&mut self, path: &str) -> PyResult<()> {
+        for (region, calendar) in load_calendars(Path::new(path))? {
+            self.register_calendar(&region, calendar);
+        }
+        Ok(())
+    }
+}
+
 #[pyclass]
 pub struct Rule {
-    parser: parser::Parser,
-    statement: Statement,
+    program: Program,
+}
+
+impl Rule {
+    pub fn new(text: String) -> PyResult<Self> {
+        Rule::py_new(text, None)
+    }
 }
 
 /// Test docstring for the Rule class
 #[pymethods]
 impl Rule {
+    /// Parses a rule. Rules that call builtins registered with a context must be given that
+    /// context so the calls can be checked.
     #[new]
-    pub fn new(text: String) -> PyResult<Self> {
+    #[pyo3(signature = (text, ctx = None))]
+    pub fn py_new(text: String, ctx: Option<&Context>) -> PyResult<Self> {
         let parser = parser::Parser::new();
-        let statement = parser.parse_internal(text)?;
-        Ok(Rule { parser, statement })
+        let program = match ctx {
+            Some(ctx) => parser.parse_with_builtins(text, ctx.builtins())?,
+            None => parser.parse_internal(text)?,
+        };
+        Ok(Rule { program })
     }
 
     /// Test whether or not the rule is syntactically correct. This verifies the grammar is well structured and that
@@ -92,18 +602,43 @@ impl Rule {
     /// * text - The text to parse
     /// * context - The context used for specifying symbol type information.
     #[staticmethod]
-    pub fn is_valid(text: String, _ctx: Option<&Context>) -> PyResult<bool> {
-        let cls_parser = parser::Parser::new();
-        let statement = cls_parser.parse_internal(text);
-        match statement {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
-        }
+    pub fn is_valid(text: String, ctx: Option<&Context>) -> PyResult<bool> {
+        Ok(Rule::py_new(text, ctx).is_ok())
     }
 
     pub fn evaluate(&self, thing: Option<&PyDict>, ctx: Option<&Context>) -> EvalResult {
-        self.statement
-            .evaluate(&ctx.unwrap_or(&Context::new(None)), thing)
+        self.program
+            .evaluate(ctx.unwrap_or(&Context::new(None)), thing)
+    }
+
+    /// Warns about attributes whose last key names a builtin property but that read a key of the
+    /// input instead, e.g. `person.language_code` when `person` has a `language_code` key.
+    #[pyo3(signature = (thing, ctx = None))]
+    pub fn lint(&self, thing: &PyDict, ctx: Option<&Context>) -> Vec<String> {
+        let default = Context::new(None);
+        let builtins = ctx.unwrap_or(&default).builtins();
+        let mut warnings = Vec::new();
+        let _ = self.program.walk(&mut |primary| {
+            let PrimaryExpression::Attribute(path) = primary else {
+                return Ok(());
+            };
+            let keys: Vec<&str> = path.split('.').collect();
+            let (name, receiver) = keys.split_last().unwrap_or((&"", &[]));
+            let shadowed = builtins.property(name).is_some()
+                && !matches!(get_value_from_py_dict(thing, &keys), Ok(None));
+            let warning = format!(
+                "Key {} of {} shadows the builtin {}, write {}() to call the builtin",
+                name,
+                receiver.join("."),
+                name,
+                path
+            );
+            if shadowed && !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+            Ok(())
+        });
+        warnings
     }
 
     pub fn matches(&self, thing: Option<&PyDict>) -> bool {
@@ -118,6 +653,7 @@ impl Rule {
 /// Adds the objects within the engine to the module.
 /// The module is the engine module created in lib.rs
 pub fn engine(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Context>()?;
     m.add_class::<Rule>()?;
     Ok(())
 }
@@ -125,6 +661,12 @@ pub fn engine(_py: Python, m: &PyModule) -> PyResult<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::ValueType;
+    use crate::errors::ErrorKind;
+    use crate::registry::Parameter;
+    use crate::utils::parse_datetime;
+    use chrono::TimeDelta;
+    use num_bigint::BigInt;
 
     #[test]
     fn test_rule_is_valid() {
@@ -142,7 +684,7 @@ mod tests {
         let invalid_statements = vec!["1abc == 1", "true =="];
         for statement in invalid_statements {
             println!("Testing invalid statement: {}", statement);
-            assert_eq!(Rule::is_valid(statement.into(), None).unwrap(), false);
+            assert!(!Rule::is_valid(statement.into(), None).unwrap());
         }
     }
 
@@ -160,7 +702,6 @@ mod tests {
 
     #[test]
     fn test_evaluate_with_multisymbol_resolution() {
-        let attrs = &["hi", "im", "hank", "in", "utah"];
         pyo3::prepare_freethreaded_python();
         let rule = Rule::new("age >= required_age".into()).unwrap();
         let _ = &Python::with_gil(|py| {
@@ -210,7 +751,7 @@ mod tests {
     fn test_evaluate_unary_not() {
         pyo3::prepare_freethreaded_python();
         let rule = Rule::new("not true".into()).unwrap();
-        let _ = &Python::with_gil(|py| {
+        let _ = &Python::with_gil(|_py| {
             let result = rule.evaluate(None, None).unwrap();
             assert_eq!(result, EvalResultTypes::Boolean(false));
         });
@@ -221,7 +762,7 @@ mod tests {
         pyo3::prepare_freethreaded_python();
         let true_rule = Rule::new("1 > -1".into()).unwrap();
         let false_rule = Rule::new("-1 < -2".into()).unwrap();
-        let _ = &Python::with_gil(|py| {
+        let _ = &Python::with_gil(|_py| {
             assert_eq!(
                 true_rule.evaluate(None, None).unwrap(),
                 EvalResultTypes::Boolean(true),
@@ -253,10 +794,2134 @@ mod tests {
     }
 
     #[test]
-    fn test_addition() {
+    fn test_evaluate_program_with_bindings() {
         pyo3::prepare_freethreaded_python();
-        let rule = Rule::new("1.0 + 1".into()).unwrap();
-        let result = rule.evaluate(None, None).unwrap();
-        assert_eq!(result, EvalResultTypes::Integer(2));
+        let rule = Rule::new(
+            "let subtotal = price * quantity; let tax = subtotal * 0.08; subtotal + tax > 100"
+                .into(),
+        )
+        .unwrap();
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("price", 25).unwrap();
+            dict.set_item("quantity", 4).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(true));
+
+            dict.set_item("quantity", 3).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(false));
+        });
+    }
+
+    #[test]
+    fn test_bindings_shadow_input() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("let name = \"hank\"; name == \"hank\"".into()).unwrap();
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("name", "HANK").unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(true));
+        });
+    }
+
+    #[test]
+    fn test_quoted_keys() {
+        pyo3::prepare_freethreaded_python();
+        assert!(!Rule::is_valid("let > 4".into(), None).unwrap());
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("let", 5).unwrap();
+            dict.set_item("first name", "Hank").unwrap();
+            let options = PyDict::new(py);
+            options.set_item("is", 2).unwrap();
+            options.set_item("length", 7).unwrap();
+            dict.set_item("in", options).unwrap();
+            for text in [
+                "`let` > 4",
+                "let limit = `let`; limit == 5",
+                "`first name` == \"Hank\"",
+                "`in`.is == 2 and in.is == 2",
+                "`in`.length == 7",
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                let result = rule.evaluate(Some(dict), None).unwrap();
+                assert_eq!(result, EvalResultTypes::Boolean(true), "{}", text);
+            }
+        });
+    }
+
+    #[test]
+    fn test_program_is_valid() {
+        let valid_programs = vec!["let a = 1; a + 1", "let a = 1; let b = a; b == a"];
+        for program in valid_programs {
+            assert!(Rule::is_valid(program.into(), None).unwrap());
+        }
+        let invalid_programs = vec![
+            "let a = b; let b = 1; a",
+            "let a = a + 1; a",
+            "let a = 1; let a = 2; a",
+            "let a = 1;",
+            "let a = 1 a",
+        ];
+        for program in invalid_programs {
+            println!("Testing invalid program: {}", program);
+            assert!(!Rule::is_valid(program.into(), None).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_evaluate_user_defined_function() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new(
+            "fn age(days) = days // 365; fn adult(days) = age(days) >= 18; adult(born)".into(),
+        )
+        .unwrap();
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("born", 365 * 20).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(true));
+
+            dict.set_item("born", 365 * 17 + 364).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(false));
+        });
+    }
+
+    #[test]
+    fn test_function_recursion_is_rejected() {
+        pyo3::prepare_freethreaded_python();
+        let Err(err) = Rule::new("fn forever(x) = forever(x + 1); forever(0)".into()) else {
+            panic!("Expected recursion to be rejected");
+        };
+        assert!(err
+            .to_string()
+            .contains("Function forever cannot call itself"));
+        // Nor can a function be reached again through one defined after it
+        assert!(Rule::new("fn a(x) = b(x); fn b(x) = a(x); a(1)".into()).is_err());
+    }
+
+    #[test]
+    fn test_function_is_valid() {
+        let valid_programs = vec![
+            "fn zero() = 0; zero()",
+            "fn add(a, b) = a + b; let c = add(1, 2); add(c, 3)",
+        ];
+        for program in valid_programs {
+            assert!(Rule::is_valid(program.into(), None).unwrap());
+        }
+        let invalid_programs = vec![
+            // Arity mismatch
+            "fn add(a, b) = a + b; add(1)",
+            // Called before definition
+            "let a = f(1); fn f(x) = x; a",
+            // Unknown function
+            "f(1)",
+            // Bodies may only reference their parameters
+            "let a = 1; fn f(x) = x + a; f(1)",
+            "fn f(x) = x + age; f(1)",
+            // Duplicate parameter names
+            "fn f(x, x) = x; f(1, 2)",
+            // Bindings aren't callable
+            "let a = 1; a(1)",
+        ];
+        for program in invalid_programs {
+            println!("Testing invalid program: {}", program);
+            assert!(!Rule::is_valid(program.into(), None).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_floor_division() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("7 // 2".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Float(3.0)
+        );
+        let rule = Rule::new("-7 // 2".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Float(-4.0)
+        );
+        assert!(Rule::new("1 // 0".into())
+            .unwrap()
+            .evaluate(None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_modulo_floors() {
+        pyo3::prepare_freethreaded_python();
+        for (a, b, quotient, remainder) in [(-7, 3, -3, 2), (7, -3, -3, -2), (-7, -3, 2, -1)] {
+            let mut ctx = Context::new(None);
+            ctx.bind("a", EvalResultTypes::Integer(a));
+            ctx.bind("b", EvalResultTypes::Integer(b));
+            for (text, expected) in [("a // b", quotient), ("a % b", remainder)] {
+                let result = Rule::new(text.into())
+                    .unwrap()
+                    .evaluate(None, Some(&ctx))
+                    .unwrap();
+                assert_eq!(result.type_name(), "integer", "{} with {}, {}", text, a, b);
+                assert_eq!(result, EvalResultTypes::Integer(expected), "{}", text);
+            }
+            let rule = Rule::new("a == (a // b) * b + a % b".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+        }
+        for (text, expected) in [
+            ("-7.0 % 3", EvalResultTypes::Float(2.0)),
+            ("7.5 % -2", EvalResultTypes::Float(-0.5)),
+            ("-7m % 3", EvalResultTypes::Decimal(2.into())),
+            ("7m % -3", EvalResultTypes::Decimal((-2).into())),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let result = rule.evaluate(None, None).unwrap();
+            assert_eq!(result.type_name(), expected.type_name(), "{}", text);
+            assert_eq!(result, expected, "{}", text);
+        }
+        let rule = Rule::new("-6.0 % 3".into()).unwrap();
+        let EvalResultTypes::Float(zero) = rule.evaluate(None, None).unwrap() else {
+            panic!("Expected a float");
+        };
+        assert!(zero == 0.0 && zero.is_sign_positive());
+    }
+
+    #[test]
+    fn test_evaluate_chained_comparison() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("18 <= age < 65".into()).unwrap();
+        let _ = &Python::with_gil(|py| {
+            for (age, expected) in [(17, false), (18, true), (64, true), (65, false)] {
+                let dict = PyDict::new(py);
+                dict.set_item("age", age).unwrap();
+                let result = rule.evaluate(Some(dict), None).unwrap();
+                assert_eq!(result, EvalResultTypes::Boolean(expected));
+            }
+        });
+    }
+
+    #[test]
+    fn test_evaluate_chained_comparison_with_equality() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            ("1 < 2 == 2", true),
+            ("1 < 2 != 2", false),
+            ("1 == 1 == 1.0", true),
+            ("3 > 2 > 1 > 0", true),
+            ("3 > 2 < 1", false),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, None).unwrap(),
+                EvalResultTypes::Boolean(expected),
+            );
+        }
+    }
+
+    #[test]
+    fn test_chained_comparison_short_circuits() {
+        pyo3::prepare_freethreaded_python();
+        // `missing` would fail to resolve if the chain kept evaluating after `2 < 1`
+        let rule = Rule::new("2 < 1 < missing".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Boolean(false)
+        );
+        let rule = Rule::new("1 < 2 < missing".into()).unwrap();
+        assert!(rule.evaluate(None, None).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_ordering_of_non_numeric_types() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            ("\"b\" > \"a\"", true),
+            ("\"apple\" < \"apricot\"", true),
+            ("\"2024-01-31T00:00:00Z\" < \"2024-02-01T00:00:00Z\"", true),
+            ("[1, 2] < [1, 3]", true),
+            ("[1, 2] < [1, 2, 0]", true),
+            ("[1, \"b\"] >= [1, \"a\"]", true),
+            ("false < true", true),
+            ("true <= false", false),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, None).unwrap(),
+                EvalResultTypes::Boolean(expected),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_ordering_incomparable_types_fails() {
+        pyo3::prepare_freethreaded_python();
+        let err = Rule::new("1 < \"a\"".into())
+            .unwrap()
+            .evaluate(None, None)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Cannot compare integer with string");
+        let err = Rule::new("[1] < [\"a\"]".into())
+            .unwrap()
+            .evaluate(None, None)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Cannot compare integer with string");
+        assert_eq!(err.kind(), ErrorKind::Type);
+        let err = Rule::new("1 == \"a\"".into())
+            .unwrap()
+            .evaluate(None, None)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Type);
+        Python::with_gil(|py| {
+            assert!(PyErr::from(err).is_instance_of::<pyo3::exceptions::PyTypeError>(py))
+        });
+    }
+
+    #[test]
+    fn test_equality_follows_comparison() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            ("[1, null] == [1, null]", true),
+            ("[1, \"a\"] == [1, 2]", false),
+            ("{1, 2} == {2, 1}", true),
+            ("\"a\" != \"b\"", true),
+            ("null == 1", false),
+            ("[1, 2] == [1, 2, 3]", false),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, None).unwrap(),
+                EvalResultTypes::Boolean(expected),
+                "{}",
+                text
+            );
+        }
+        assert_ne!(
+            EvalResultTypes::String("1".into()),
+            EvalResultTypes::Float(1.0)
+        );
+        assert_eq!(EvalResultTypes::Integer(2), EvalResultTypes::Float(2.0));
+    }
+
+    #[test]
+    fn test_ordering_strings_with_collation() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("\"ä\" < \"z\"".into()).unwrap();
+        let mut ctx = Context::new(None);
+        ctx.set_collation("de").unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        ctx.set_collation("sv").unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(false)
+        );
+        assert!(ctx.set_collation("not a locale").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_concatenation() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("(first + \" \") + last".into()).unwrap();
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("first", "Hank").unwrap();
+            dict.set_item("last", "Breck").unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::String("Hank Breck".into()));
+        });
+        let rule = Rule::new("[1, 2] + [3]".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::List(vec![
+                EvalResultTypes::Integer(1),
+                EvalResultTypes::Integer(2),
+                EvalResultTypes::Integer(3),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_evaluate_repetition() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            ("\"ab\" * 3", EvalResultTypes::String("ababab".into())),
+            ("2 * \"ab\"", EvalResultTypes::String("abab".into())),
+            ("\"ab\" * -1", EvalResultTypes::String("".into())),
+            (
+                "[0] * 2",
+                EvalResultTypes::List(vec![EvalResultTypes::Integer(0); 2]),
+            ),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        let err = Rule::new("\"ab\" * 1.5".into())
+            .unwrap()
+            .evaluate(None, None)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Cannot multiply string and float");
+    }
+
+    #[test]
+    fn test_repetition_respects_maximum_length() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("\"abc\" * 4".into()).unwrap();
+        let mut ctx = Context::new(None);
+        ctx.settings_mut().max_sequence_length = 12;
+        assert!(rule.evaluate(None, Some(&ctx)).is_ok());
+        ctx.settings_mut().max_sequence_length = 11;
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+        assert!(Rule::new("\"a\" * 1e12".into())
+            .unwrap()
+            .evaluate(None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_errors_name_operand_types() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            ("1 + \"a\"", "Cannot add integer and string"),
+            ("[1] - [1]", "Cannot subtract list and list"),
+            ("true / 2", "Cannot divide boolean and integer"),
+        ];
+        for (text, expected) in cases {
+            let err = Rule::new(text.into())
+                .unwrap()
+                .evaluate(None, None)
+                .unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_datetime_arithmetic() {
+        pyo3::prepare_freethreaded_python();
+        let cases = [
+            (
+                "d\"2024-01-01T00:00:00Z\" + t\"P1DT2H\"",
+                EvalResultTypes::Datetime(parse_datetime("2024-01-02T02:00:00Z").unwrap()),
+            ),
+            (
+                "d\"2024-01-01\" - t\"P1W\"",
+                EvalResultTypes::Datetime(parse_datetime("2023-12-25").unwrap()),
+            ),
+            (
+                "d\"2024-03-01\" - d\"2024-02-01\"",
+                EvalResultTypes::Timedelta(TimeDelta::days(29)),
+            ),
+            (
+                "(d\"2024-03-01\" - d\"2023-03-01\").days // 7",
+                EvalResultTypes::Integer(52),
+            ),
+            (
+                "d\"2024-03-01T12:00:00Z\".weekday",
+                EvalResultTypes::Integer(4),
+            ),
+            (
+                "(d\"2024-03-01T12:00:00Z\" + t\"P1D\").to_tz(\"UTC\").weekday",
+                EvalResultTypes::Integer(5),
+            ),
+            ("(\"HANK\").as_lower.length", EvalResultTypes::Integer(4)),
+            (
+                "\"ab\".as_upper.as_lower.length",
+                EvalResultTypes::Integer(2),
+            ),
+            (
+                "d\"2024-01-01T00:00:00+05:00\" == d\"2023-12-31T19:00:00Z\"",
+                EvalResultTypes::Boolean(true),
+            ),
+            (
+                "t\"PT1H\" < t\"PT61M\" <= t\"P1D\"",
+                EvalResultTypes::Boolean(true),
+            ),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        assert!(!Rule::is_valid("d\"not a date\" == 1".into(), None).unwrap());
+        assert!(!Rule::is_valid("t\"P1Y\" == 1".into(), None).unwrap());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_datetimes() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new(
+            "created_at > d\"2024-01-01\" and created_at - birthday > t\"P6570D\"".into(),
+        )
+        .unwrap();
+        let _ = &Python::with_gil(|py| {
+            let datetime = py.import("datetime").unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("datetime", datetime).unwrap();
+            let dict: &PyDict = py
+                .eval(
+                    "{'created_at': datetime.datetime(2024, 6, 1, 12, tzinfo=datetime.timezone.utc), \
+                      'birthday': datetime.date(2000, 1, 1)}",
+                    None,
+                    Some(locals),
+                )
+                .unwrap()
+                .downcast()
+                .unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(true));
+
+            let rule = Rule::new("created_at - birthday".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py
+                .eval(
+                    "datetime.timedelta(days=8918, hours=12)",
+                    None,
+                    Some(locals),
+                )
+                .unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_builtin_named_attribute_reads_data() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("birth.year == 1990".into()).unwrap();
+        let _ = &Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            let birth = PyDict::new(py);
+            birth.set_item("year", 1990).unwrap();
+            dict.set_item("birth", birth).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Boolean(true));
+        });
+    }
+
+    #[test]
+    fn test_addition() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("1.0 + 1".into()).unwrap();
+        let result = rule.evaluate(None, None).unwrap();
+        assert_eq!(result, EvalResultTypes::Integer(2));
+    }
+
+    #[test]
+    fn test_fixed_clock_and_timezone() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.set_clock(Clock::Fixed(
+            parse_datetime("2024-07-01T03:30:00Z").unwrap(),
+        ));
+        ctx.set_timezone("America/Denver").unwrap();
+        let rule = Rule::new("$now".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Datetime(parse_datetime("2024-06-30T21:30:00-06:00").unwrap())
+        );
+        let rule = Rule::new("$today == d\"2024-06-30T00:00:00-06:00\"".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        assert!(ctx.set_timezone("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_to_tz_method() {
+        pyo3::prepare_freethreaded_python();
+        // A Monday in UTC, but still Sunday evening in Denver
+        let rule = Rule::new(
+            "let created_at = d\"2024-07-01T03:00:00Z\"; \
+             created_at.to_tz(\"America/Denver\").weekday() < 5"
+                .into(),
+        )
+        .unwrap();
+        let result = rule.evaluate(None, None).unwrap();
+        assert_eq!(result, EvalResultTypes::Boolean(false));
+        let rule = Rule::new("d\"2024-07-01\".to_tz(\"Nowhere/Special\")".into()).unwrap();
+        assert!(rule.evaluate(None, None).is_err());
+    }
+
+    #[test]
+    fn test_unknown_builtins_are_rejected() {
+        pyo3::prepare_freethreaded_python();
+        assert!(Rule::new("$yesterday".into()).is_err());
+        assert!(Rule::new("name.frobnicate(1)".into()).is_err());
+        assert!(Rule::new("fn f(x) = x + $now; f(t\"PT1H\")".into()).is_ok());
+    }
+
+    #[test]
+    fn test_python_clock() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "import datetime\nclock = lambda: datetime.datetime(2024, 1, 2, 3, 4, 5)",
+                Some(locals),
+                None,
+            )
+            .unwrap();
+            let clock = locals.get_item("clock").unwrap();
+            let mut ctx = Context::new(None);
+            ctx.set_clock(Clock::from_py(clock).unwrap());
+            let rule = Rule::new("$now.hour".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                EvalResultTypes::Integer(3)
+            );
+            let bad = py.eval("42", None, None).unwrap();
+            assert!(Clock::from_py(bad).is_err());
+        });
+    }
+
+    #[test]
+    fn test_business_day_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let path = std::env::temp_dir().join("rust_rule_engine_engine_calendars.json");
+        std::fs::write(&path, r#"{"us": {"holidays": ["2024-07-04"]}}"#).unwrap();
+        let mut ctx = Context::new(None);
+        ctx.load_calendars(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rule = Rule::new(
+            "let opened = d\"2024-07-03T16:00:00-04:00\"; \
+             opened.add_business_days(1, \"us\")"
+                .into(),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Datetime(parse_datetime("2024-07-05T16:00:00-04:00").unwrap())
+        );
+        let rule = Rule::new(
+            "d\"2024-07-01\".business_days_between(d\"2024-07-08\", \"us\") == 4 \
+             and not d\"2024-07-04\".is_business_day(\"us\")"
+                .into(),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        let rule = Rule::new("d\"2024-07-04\".is_business_day(\"uk\")".into()).unwrap();
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+    }
+
+    #[test]
+    fn test_decimal_arithmetic() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("0.1m + 0.2m == 0.3m".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        let rule = Rule::new("0.1 + 0.2 == 0.3".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Boolean(false)
+        );
+        let mut ctx = Context::new(None);
+        ctx.set_decimal_literals(true);
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+
+        // Integers and floats are promoted when combined with a decimal
+        let rule = Rule::new("1.10m * 3".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Decimal("3.30".parse().unwrap())
+        );
+        let rule = Rule::new("(10m / 4).round(0, \"half_up\") > 2.5".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        let rule = Rule::new("-7m // 2".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Decimal((-4).into())
+        );
+        let rule = Rule::new("1m / 0".into()).unwrap();
+        assert!(rule.evaluate(None, None).is_err());
+        let rule = Rule::new("79228162514264337593543950335m * 2".into()).unwrap();
+        assert!(rule.evaluate(None, None).is_err());
+        let rule = Rule::new("1m + \"1\"".into()).unwrap();
+        assert!(rule.evaluate(None, None).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_decimals() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "import decimal\nthing = {'price': decimal.Decimal('19.99')}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            let rule = Rule::new("price * 2".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert_eq!(result, EvalResultTypes::Decimal("39.98".parse().unwrap()));
+            let expected = py
+                .eval("decimal.Decimal('39.98')", None, Some(locals))
+                .unwrap();
+            assert!(result.into_py(py).as_ref(py).eq(expected).unwrap());
+
+            py.run(
+                "thing['price'] = decimal.Decimal('NaN')",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            assert!(rule.evaluate(Some(dict), None).is_err());
+        });
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("big * big - big".into()).unwrap();
+        let mut ctx = Context::new(None);
+        ctx.bind("big", EvalResultTypes::Integer(i64::MAX));
+        let expected: BigInt = BigInt::from(i64::MAX) * i64::MAX - i64::MAX;
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::BigInt(expected)
+        );
+        // Results that fit are narrowed back to an integer
+        let rule = Rule::new("(big * big) // big".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Integer(i64::MAX)
+        );
+
+        ctx.set_integer_overflow(IntegerOverflow::Error);
+        let rule = Rule::new("big + big".into()).unwrap();
+        let err = rule.evaluate(None, Some(&ctx)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow");
+        assert_eq!(err.kind(), ErrorKind::Overflow);
+        Python::with_gil(|py| {
+            assert!(PyErr::from(err).is_instance_of::<pyo3::exceptions::PyOverflowError>(py))
+        });
+        let rule = Rule::new("9223372036854775807 + 1".into()).unwrap();
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+        ctx.bind("big", EvalResultTypes::Integer(i64::MIN));
+        ctx.bind("minus_one", EvalResultTypes::Integer(-1));
+        let rule = Rule::new("-big".into()).unwrap();
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+        let rule = Rule::new("big // minus_one".into()).unwrap();
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_big_ints() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("id", py.eval("2 ** 80", None, None).unwrap())
+                .unwrap();
+            let rule = Rule::new("id + id".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py.eval("2 ** 81", None, None).unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+
+            let rule = Rule::new("1e24 < id < 1.3e24 and id != 2.5".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            let rule = Rule::new("id == 1208925819614629174706176".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            let rule = Rule::new("id / 2".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Float(2f64.powi(79))
+            );
+
+            // Whole-number literals keep integer arithmetic exact
+            dict.set_item("big", py.eval("2 ** 70", None, None).unwrap())
+                .unwrap();
+            let rule = Rule::new("big + 1 != big and big - 1 < big".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            let rule = Rule::new("big * 3 + 1".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert!(matches!(result, EvalResultTypes::BigInt(_)));
+            let expected = py.eval("2 ** 70 * 3 + 1", None, None).unwrap();
+            assert!(result.into_py(py).as_ref(py).eq(expected).unwrap());
+            let rule = Rule::new("big + 0.5".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap().type_name(),
+                "float"
+            );
+
+            // A whole float from the input stays a float, as it would in Python
+            dict.set_item("f", 2.0).unwrap();
+            dict.set_item("i", 7).unwrap();
+            let rule = Rule::new("f + i".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            assert!(matches!(result, EvalResultTypes::Float(value) if value == 9.0));
+        });
+        for (text, expected) in [
+            ("$int(\"42\") + 1", EvalResultTypes::Integer(43)),
+            ("$int(\"7\") // 2", EvalResultTypes::Integer(3)),
+            ("$int(\"7\") * 1.5", EvalResultTypes::Float(10.5)),
+            ("7 // 2", EvalResultTypes::Integer(3)),
+            ("7.0 // 2", EvalResultTypes::Float(3.0)),
+            ("(0x10 + 0b11) + 0o7", EvalResultTypes::Integer(26)),
+            (
+                "9223372036854775807 + 1 == 9223372036854775808",
+                EvalResultTypes::Boolean(true),
+            ),
+        ] {
+            let result = Rule::new(text.into())
+                .unwrap()
+                .evaluate(None, None)
+                .unwrap();
+            assert_eq!(result.type_name(), expected.type_name(), "{}", text);
+            assert_eq!(result, expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_money() {
+        pyo3::prepare_freethreaded_python();
+        let rule = Rule::new("m\"12.50 USD\" * 2 + m\"0.10 USD\"".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Money("25.10 USD".parse().unwrap())
+        );
+        let rule = Rule::new("m\"10 USD\" / m\"4 USD\"".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Decimal("2.5".parse().unwrap())
+        );
+        for text in [
+            "m\"1 USD\" + m\"1 EUR\"",
+            "m\"1 USD\" < m\"1 EUR\"",
+            "m\"1 USD\" == m\"1 EUR\"",
+            "m\"1 USD\" + 1",
+            "m\"1 USD\" == 1",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert!(rule.evaluate(None, None).is_err(), "{}", text);
+        }
+        assert!(Rule::new("m\"1 dollar\"".into()).is_err());
+    }
+
+    #[test]
+    fn test_money_conversion() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.set_exchange_rate("USD", "EUR", "0.8".parse().unwrap());
+        let rule = Rule::new("m\"10.00 USD\".convert(\"EUR\")".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Money("8.000 EUR".parse().unwrap())
+        );
+        // The inverse of a registered rate is used when there is no direct one
+        let rule = Rule::new("m\"8 EUR\".convert(\"USD\") == m\"10 USD\"".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        let rule = Rule::new("m\"1 USD\".convert(\"GBP\")".into()).unwrap();
+        assert!(rule.evaluate(None, Some(&ctx)).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_money() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "import decimal\n\
+                 thing = {'price': {'amount': decimal.Decimal('19.99'), 'currency': 'USD'}, \
+                          'note': {'amount': 'abc', 'currency': 'usd'}}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            let rule = Rule::new("price.amount > 10 and price.currency == \"USD\"".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            let rule = Rule::new("(price * 3).round(1, \"half_up\")".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py
+                .eval(
+                    "{'amount': decimal.Decimal('60.0'), 'currency': 'USD'}",
+                    None,
+                    Some(locals),
+                )
+                .unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+
+            // Mappings that merely share the shape of money stay mappings
+            for text in [
+                "price is money",
+                "note is mapping",
+                "note.currency == \"usd\"",
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                assert_eq!(
+                    rule.evaluate(Some(dict), None).unwrap(),
+                    EvalResultTypes::Boolean(true),
+                    "{}",
+                    text
+                );
+            }
+        });
+        assert!(Rule::new("m\"12.50 XYZ\"".into()).is_err());
+    }
+
+    #[test]
+    fn test_sets() {
+        pyo3::prepare_freethreaded_python();
+        for text in [
+            "{1, 1.0, 1.00m} == {1}",
+            "{1, 2} | {3} == {1, 2, 3}",
+            "{1, 2, 3} & {2, 3, 4} == {2, 3}",
+            "{1, 2, 3} - {2} == {1, 3}",
+            "{1, 2} ^ {2, 3} == {1, 3}",
+            "{\"admin\"}.issubset({\"admin\", \"editor\"})",
+            "{\"admin\", \"editor\"}.issuperset([\"admin\"])",
+            "{1, 2}.isdisjoint({3})",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, None).unwrap(),
+                EvalResultTypes::Boolean(true),
+                "{}",
+                text
+            );
+        }
+        for text in ["{[1]}", "{1} | [1]", "{1} < {2}", "{1}.issubset(1)"] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert!(rule.evaluate(None, None).is_err(), "{}", text);
+        }
+        assert!(Rule::new("{}".into()).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_sets() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "thing = {'roles': {'admin', 'editor'}, 'required': frozenset(['admin'])}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            let rule = Rule::new("roles & required".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py.eval("{'admin'}", None, None).unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+            let rule = Rule::new("required.issubset(roles)".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+        });
+    }
+
+    #[test]
+    fn test_bytes() {
+        pyo3::prepare_freethreaded_python();
+        for text in [
+            "b\"\\x89PNG\" == x\"89504e47\"",
+            "b\"ab\" + b\"c\" == b\"abc\"",
+            "b\"abc\".length == 3",
+            "b\"abcdef\".slice(1, -1) == b\"bcde\"",
+            "b\"abcdef\".slice(4) == b\"ef\"",
+            "x\"89504e470d0a\".starts_with(b\"\\x89PNG\")",
+            "b\"abc\".startswith(b\"ab\")",
+            "b\"hi\".to_base64() == \"aGk=\"",
+            "\"6869\".from_hex() == b\"hi\"",
+            "b\"a\" < b\"b\"",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, None).unwrap(),
+                EvalResultTypes::Boolean(true),
+                "{}",
+                text
+            );
+        }
+        // Bytes and strings never mix
+        for text in [
+            "b\"a\" == \"a\"",
+            "b\"a\" + \"a\"",
+            "b\"a\".starts_with(\"a\")",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert!(rule.evaluate(None, None).is_err(), "{}", text);
+        }
+        assert!(Rule::new("x\"abc\"".into()).is_err());
+        assert!(Rule::new("b\"\\xzz\"".into()).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_python_bytes() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "thing = {'payload': b'\\x89PNG\\r\\n', 'buffer': bytearray(b'ab')}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            let rule = Rule::new("payload.slice(1, 4)".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py.eval("b'PNG'", None, None).unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+            let rule = Rule::new("buffer == b\"ab\"".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+        });
+    }
+
+    #[test]
+    fn test_type_tests() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.bind("count", EvalResultTypes::Integer(3));
+        for text in [
+            "\"3\" is string",
+            "count is number and count is integer",
+            "1.5 is number",
+            "1.5m is decimal",
+            "null is null",
+            "[1] is list",
+            "{1} is set",
+            "b\"a\" is bytes",
+            "\"3\" is not number",
+            "count is not null",
+            "m\"1 USD\" is money",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                EvalResultTypes::Boolean(true),
+                "{}",
+                text
+            );
+        }
+        assert!(Rule::new("1 is widget".into()).is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        pyo3::prepare_freethreaded_python();
+        for (text, expected) in [
+            ("int(\" -42 \")", EvalResultTypes::Integer(-42)),
+            ("int(-3.7)", EvalResultTypes::Integer(-3)),
+            ("int(true)", EvalResultTypes::Integer(1)),
+            ("float(\"1.5e3\")", EvalResultTypes::Float(1500.0)),
+            ("float(2.5m)", EvalResultTypes::Float(2.5)),
+            ("str(1.0)", EvalResultTypes::String("1.0".into())),
+            ("str(int(\"7\"))", EvalResultTypes::String("7".into())),
+            ("str(null)", EvalResultTypes::String("null".into())),
+            ("str(b\"hi\")", EvalResultTypes::String("hi".into())),
+            ("bool(\" False \")", EvalResultTypes::Boolean(false)),
+            ("bool(\"yes\")", EvalResultTypes::Boolean(true)),
+            ("bool(0)", EvalResultTypes::Boolean(false)),
+            ("\"12\".int() + 1", EvalResultTypes::Integer(13)),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        assert_eq!(
+            Rule::new("int(\"99999999999999999999\")".into())
+                .unwrap()
+                .evaluate(None, None)
+                .unwrap(),
+            EvalResultTypes::BigInt("99999999999999999999".parse().unwrap())
+        );
+        for text in [
+            "int(\"4.2\")",
+            "int(\"abc\")",
+            "float(\"abc\")",
+            "str([1])",
+            "bool(\"maybe\")",
+            "int(1.0 / 0)",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert!(rule.evaluate(None, None).is_err(), "{}", text);
+        }
+        assert!(Rule::new("int(1, 2)".into()).is_err());
+        // Definitions shadow builtins
+        let rule = Rule::new("fn int(x) = x * 2; int(2)".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, None).unwrap(),
+            EvalResultTypes::Float(4.0)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_with_python_none() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("discount", py.None()).unwrap();
+            dict.set_item("age", "42").unwrap();
+            let rule = Rule::new("discount is null and age is string".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            let rule = Rule::new("int(age) >= 18".into()).unwrap();
+            assert_eq!(
+                rule.evaluate(Some(dict), None).unwrap(),
+                EvalResultTypes::Boolean(true)
+            );
+            // Absent keys are null to a type test, but still an error anywhere else
+            let order = PyDict::new(py);
+            order.set_item("id", 7).unwrap();
+            dict.set_item("order", order).unwrap();
+            for (text, expected) in [
+                ("coupon is null", true),
+                ("coupon is not null", false),
+                ("coupon is string", false),
+                ("order.coupon is null", true),
+                ("order.id is null", false),
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                assert_eq!(
+                    rule.evaluate(Some(dict), None).unwrap(),
+                    EvalResultTypes::Boolean(expected),
+                    "{}",
+                    text
+                );
+            }
+            for text in [
+                "coupon == null",
+                "order.id.coupon is null",
+                "age.language_code is null",
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                assert!(rule.evaluate(Some(dict), None).is_err(), "{}", text);
+            }
+            let rule = Rule::new("discount".into()).unwrap();
+            assert!(rule
+                .evaluate(Some(dict), None)
+                .unwrap()
+                .into_py(py)
+                .is_none(py));
+        });
+    }
+
+    #[test]
+    fn test_approximate_equality() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.bind("nan", EvalResultTypes::Float(f64::NAN));
+        ctx.bind("inf", EvalResultTypes::Float(f64::INFINITY));
+        for (text, expected) in [
+            ("0.1 + 0.2 == 0.3", false),
+            ("0.1 + 0.2 ~= 0.3", true),
+            ("0.1 + 0.2 ~= 0.3m", true),
+            ("1 ~= 1.001", false),
+            // Relative tolerance alone never matches zero
+            ("0 ~= 1e-12", false),
+            ("isclose(0, 1e-12, 1e-9, 1e-9)", true),
+            ("isclose(1, 1.001, 0.01)", true),
+            ("nan ~= nan", false),
+            ("nan == nan", false),
+            ("nan != nan", true),
+            ("inf ~= inf", true),
+            ("inf ~= 1e308", false),
+            ("inf > 1e308", true),
+            ("nan.is_nan and inf.is_infinite", true),
+            ("(1).is_nan", false),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                EvalResultTypes::Boolean(expected),
+                "{}",
+                text
+            );
+        }
+        for text in [
+            "nan < 1",
+            "1 >= nan",
+            "[nan] < [1]",
+            "\"a\" ~= \"a\"",
+            "isclose(1, 1, -1)",
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert!(rule.evaluate(None, Some(&ctx)).is_err(), "{}", text);
+        }
+        ctx.set_tolerance(Tolerance::new(0.01, 0.0).unwrap());
+        let rule = Rule::new("1 ~= 1.001".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        assert!(Tolerance::new(-1.0, 0.0).is_err());
+        assert!(Tolerance::new(f64::NAN, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_builtin_calls() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.bind("a", EvalResultTypes::Integer(3));
+        ctx.bind("b", EvalResultTypes::Integer(5));
+        for (text, expected) in [
+            ("$min(a, b)", EvalResultTypes::Integer(3)),
+            ("$max(a, b, 4)", EvalResultTypes::Integer(5)),
+            ("$max([2, 7, 1])", EvalResultTypes::Float(7.0)),
+            (
+                "$round(2.345m, 2)",
+                EvalResultTypes::Decimal(Decimal::new(234, 2)),
+            ),
+            ("$round(2.5m)", EvalResultTypes::Decimal(Decimal::new(2, 0))),
+            (
+                "$slice(\"hello\", 1)",
+                EvalResultTypes::String("ello".into()),
+            ),
+            ("$as_lower(\"ABC\")", EvalResultTypes::String("abc".into())),
+            // Unlike plain calls, `$` always calls the builtin
+            ("fn min(x, y) = 0; $min(a, b)", EvalResultTypes::Integer(3)),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                expected,
+                "{}",
+                text
+            );
+        }
+        for (text, message) in [
+            (
+                "$round(\"1\")",
+                "Argument 'value' of round must be number or money, got string",
+            ),
+            (
+                "$round(1, \"2\")",
+                "Argument 'places' of round must be number, got string",
+            ),
+            ("$min([])", "Expected at least one value"),
+            ("$max(1, \"a\")", "Cannot compare"),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, Some(&ctx)).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+        for (text, message) in [
+            ("$round()", "Missing argument 'value' for round"),
+            ("$min()", "Missing argument 'values' for min"),
+            (
+                "$round(1, 2, \"up\", 4)",
+                "round expects at most 3 arguments, got 4",
+            ),
+            ("a.to_tz()", "Missing argument 'timezone' for to_tz"),
+            (
+                "a.as_lower(1)",
+                "as_lower expects at most 1 arguments, got 2",
+            ),
+            ("$frobnicate(1)", "Unknown builtin function $frobnicate"),
+        ] {
+            let err = Rule::new(text.into()).err().unwrap().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_python_functions() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::from_code(
+                py,
+                r#"
+calls = []
+
+def risk_score(amount, country="US"):
+    """How risky an order is"""
+    calls.append(amount)
+    return amount if country == "US" else amount * 2
+
+def fail(value):
+    raise KeyError("boom")
+
+def wrong(value):
+    return "high"
+
+def total(*values):
+    return sum(values)
+
+def keyword(value, *, scale):
+    return value
+
+def kind(value):
+    return type(value).__name__
+"#,
+                "helpers.py",
+                "helpers",
+            )
+            .unwrap();
+            let function = |name: &str| module.getattr(name).unwrap().into();
+            let types =
+                |names: &[&str]| Some(names.iter().map(|name| Some(name.to_string())).collect());
+            let mut ctx = Context::new(None);
+            ctx.register_function(
+                py,
+                "risk.risk_score",
+                function("risk_score"),
+                types(&["number", "string"]),
+                Some("number".into()),
+                true,
+                None,
+            )
+            .unwrap();
+            for name in ["fail", "wrong", "total"] {
+                ctx.register_function(
+                    py,
+                    name,
+                    function(name),
+                    None,
+                    Some("number".into()),
+                    false,
+                    None,
+                )
+                .unwrap();
+            }
+            ctx.register_function(py, "kind", function("kind"), None, None, true, None)
+                .unwrap();
+            ctx.register_builtin(
+                Builtin::from_python(py, "fails", function("fail"), None, None, false)
+                    .unwrap()
+                    .readable_as_attribute(),
+            )
+            .unwrap();
+            ctx.bind("amount", EvalResultTypes::Integer(5));
+            let evaluate = |text: &str| {
+                Rule::py_new(text.into(), Some(&ctx))
+                    .unwrap()
+                    .evaluate(None, Some(&ctx))
+            };
+            assert_eq!(
+                evaluate(
+                    "(risk_score(amount) + amount.risk_score()) + $risk.risk_score(amount, \"FR\")"
+                )
+                .unwrap(),
+                EvalResultTypes::Integer(20)
+            );
+            // The pure function was only called once for each distinct set of arguments
+            assert_eq!(module.getattr("calls").unwrap().len().unwrap(), 2);
+            assert_eq!(
+                evaluate("total(1, 2, 3)").unwrap(),
+                EvalResultTypes::Integer(6)
+            );
+            assert_eq!(evaluate("total()").unwrap(), EvalResultTypes::Integer(0));
+            // Equal arguments of different types aren't answered from each other's cache entry
+            assert_eq!(
+                evaluate("kind(amount // 5) + kind(1.0)").unwrap(),
+                EvalResultTypes::String("intfloat".into())
+            );
+            assert_eq!(
+                evaluate("risk_score(\"5\")").unwrap_err().to_string(),
+                "Argument 'amount' of risk_score must be number, got string"
+            );
+            assert_eq!(
+                evaluate("wrong(1)").unwrap_err().to_string(),
+                "wrong returned string, expected number"
+            );
+            let err = evaluate("fail(1)").unwrap_err();
+            assert_eq!(err.to_string(), "fail raised KeyError: 'boom'");
+            let err: PyErr = err.into();
+            assert!(err
+                .cause(py)
+                .unwrap()
+                .is_instance_of::<pyo3::exceptions::PyKeyError>(py));
+            // The exception is still the cause when the function is read as an attribute
+            let err: PyErr = evaluate("amount.fails").unwrap_err().into();
+            assert!(err
+                .cause(py)
+                .unwrap()
+                .is_instance_of::<pyo3::exceptions::PyKeyError>(py));
+            let description = ctx.describe_builtins(py).unwrap();
+            let risk_score = description
+                .iter()
+                .map(|description| description.as_ref(py))
+                .find(|description| {
+                    description.get_item("name").unwrap().to_string() == "risk.risk_score"
+                })
+                .unwrap();
+            assert_eq!(
+                risk_score.get_item("doc").unwrap().to_string(),
+                "How risky an order is"
+            );
+            assert_eq!(
+                risk_score.get_item("signature").unwrap().to_string(),
+                "risk_score(amount: number, country: string = \"US\")"
+            );
+            let total = ctx.builtins().callable("total").unwrap();
+            assert_eq!(total.signature.to_string(), "total([values...])");
+            // Rules can only pass arguments by position
+            assert!(ctx
+                .register_function(py, "keyword", function("keyword"), None, None, false, None)
+                .is_err());
+            assert!(ctx
+                .register_function(
+                    py,
+                    "total",
+                    function("total"),
+                    types(&["number", "number"]),
+                    None,
+                    false,
+                    None
+                )
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_data_shadows_builtin_properties() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let person = PyDict::new(py);
+            person.set_item("language_code", "fr-CA").unwrap();
+            person.set_item("locale", "en-US").unwrap();
+            let dict = PyDict::new(py);
+            dict.set_item("person", person).unwrap();
+            for (text, expected) in [
+                ("person.language_code", "fr-CA"),
+                ("person.locale.language_code", "en"),
+                ("person.locale.language_code()", "en"),
+                ("person.language_code.language_code", "fr"),
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                assert_eq!(
+                    rule.evaluate(Some(dict), None).unwrap(),
+                    EvalResultTypes::String(expected.into()),
+                    "{}",
+                    text
+                );
+            }
+            let rule =
+                Rule::new("person.language_code == person.locale.language_code".into()).unwrap();
+            assert_eq!(
+                rule.lint(dict, None),
+                vec!["Key language_code of person shadows the builtin language_code, write person.language_code() to call the builtin"]
+            );
+            assert!(Rule::new("person.locale.language_code".into())
+                .unwrap()
+                .lint(dict, None)
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let string = |text: &str| EvalResultTypes::String(text.into());
+        let strings =
+            |texts: &[&str]| EvalResultTypes::List(texts.iter().map(|text| string(text)).collect());
+        for (text, expected) in [
+            ("\"Ünïcode\".as_upper", string("ÜNÏCODE")),
+            ("\"  padded \".trim", string("padded")),
+            ("\"  padded \".ltrim", string("padded ")),
+            ("\"  padded \".rtrim", string("  padded")),
+            ("\"o'neil-SMITH jr\".title", string("O'Neil-Smith Jr")),
+            ("\"a,b,,c\".split(\",\")", strings(&["a", "b", "", "c"])),
+            ("\"a,b,c\".split(\",\", 1)", strings(&["a", "b,c"])),
+            (
+                "\"  one  two three \".split()",
+                strings(&["one", "two", "three"]),
+            ),
+            (
+                "\" one  two three \".split(null, 1)",
+                strings(&["one", "two three "]),
+            ),
+            ("[\"a\", \"b\"].join(\", \")", string("a, b")),
+            ("$join([\"a\", \"b\"])", string("ab")),
+            ("\"555-0100-99\".replace(\"-\", \"\")", string("555010099")),
+            (
+                "\"555-0100-99\".replace(\"-\", \".\", 1)",
+                string("555.0100-99"),
+            ),
+            ("\"2024-06-30\".substring(5, 2)", string("06")),
+            ("\"2024-06-30\".substring(-2)", string("30")),
+            ("\"42\".pad_left(5, \"0\")", string("00042")),
+            ("\"123456\".pad_left(3)", string("123456")),
+            ("\"héllo\".index_of(\"l\")", EvalResultTypes::Integer(2)),
+            ("\"hello\".index_of(\"z\")", EvalResultTypes::Integer(-1)),
+            (
+                "\"hello\".contains(\"ell\")",
+                EvalResultTypes::Boolean(true),
+            ),
+            (
+                "b\"hello\".contains(b\"lo\")",
+                EvalResultTypes::Boolean(true),
+            ),
+            (
+                "\"report.pdf\".ends_with(\".pdf\")",
+                EvalResultTypes::Boolean(true),
+            ),
+            ("\" -42 \".to_int()", EvalResultTypes::Integer(-42)),
+            ("\"42\".to_int", EvalResultTypes::Integer(42)),
+            ("\"42\".to_int()", EvalResultTypes::Integer(42)),
+            ("\"4.5\".to_float()", EvalResultTypes::Float(4.5)),
+            ("\"ff\".to_int(16)", EvalResultTypes::Integer(255)),
+            ("\"1.5e3\".to_float", EvalResultTypes::Float(1500.0)),
+            (
+                "\"Straße\".casefold == \"STRASSE\".casefold",
+                EvalResultTypes::Boolean(true),
+            ),
+            (
+                "\"Straße\".as_lower == \"STRASSE\".as_lower",
+                EvalResultTypes::Boolean(false),
+            ),
+            // `é` as one code point and as `e` followed by a combining accent
+            (
+                "\"caf\u{e9}\" == \"cafe\u{301}\"",
+                EvalResultTypes::Boolean(false),
+            ),
+            (
+                "\"caf\u{e9}\" == \"cafe\u{301}\".normalize()",
+                EvalResultTypes::Boolean(true),
+            ),
+            ("\"\u{fb01}le\".normalize(\"nfkc\")", string("file")),
+            (
+                "\"caf\u{e9}\".normalize(\"NFD\").length",
+                EvalResultTypes::Integer(5),
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        for (text, message) in [
+            ("\"a\".split(\"\")", "Cannot split on an empty separator"),
+            (
+                "[\"a\", 1].join()",
+                "Cannot join a list containing integer, expected strings",
+            ),
+            (
+                "\"4.2\".to_int()",
+                "Cannot parse '4.2' as an integer in base 10",
+            ),
+            (
+                "\"1_000\".to_int()",
+                "Cannot parse '1_000' as an integer in base 10",
+            ),
+            ("\"1\".to_int(1)", "Expected a base from 2 to 36"),
+            ("\"abc\".to_float", "Cannot parse 'abc' as a float"),
+            (
+                "\"1\".pad_left(3, \"ab\")",
+                "Expected a single fill character, got 'ab'",
+            ),
+            (
+                "\"a\".normalize(\"NFX\")",
+                "Unknown normalization form 'NFX'",
+            ),
+            ("\"a\".contains(b\"a\")", "Expected string, got bytes"),
+            ("b\"a\".index_of(\"a\")", "Expected bytes, got string"),
+            (
+                "$as_upper(1)",
+                "Argument 'value' of as_upper must be string, got integer",
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, None).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_math_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.bind("two", EvalResultTypes::Integer(2));
+        ctx.bind("ten", EvalResultTypes::Integer(10));
+        ctx.bind("minus_seven", EvalResultTypes::Integer(-7));
+        ctx.bind("lowest", EvalResultTypes::Integer(i64::MIN));
+        ctx.bind("price", EvalResultTypes::Decimal("-2.50".parse().unwrap()));
+        for (text, expected) in [
+            ("minus_seven.abs()", EvalResultTypes::Integer(7)),
+            ("$abs(-1.5)", EvalResultTypes::Float(1.5)),
+            (
+                "price.abs()",
+                EvalResultTypes::Decimal("2.50".parse().unwrap()),
+            ),
+            (
+                "lowest.abs()",
+                EvalResultTypes::BigInt(BigInt::from(i64::MIN).magnitude().clone().into()),
+            ),
+            ("2.1.ceil()", EvalResultTypes::Integer(3)),
+            ("2.1.ceil", EvalResultTypes::Integer(3)),
+            ("3.7.floor", EvalResultTypes::Integer(3)),
+            ("(-2.1).floor", EvalResultTypes::Integer(-3)),
+            // Unary minus applies to the whole of `2.1.floor`
+            ("-2.1.floor", EvalResultTypes::Integer(-2)),
+            ("minus_seven.abs", EvalResultTypes::Integer(7)),
+            ("16.0.sqrt.sign", EvalResultTypes::Integer(1)),
+            ("$floor(-2.1)", EvalResultTypes::Integer(-3)),
+            ("price.floor()", EvalResultTypes::Integer(-3)),
+            ("price.ceil()", EvalResultTypes::Integer(-2)),
+            ("minus_seven.floor()", EvalResultTypes::Integer(-7)),
+            ("2.5.round()", EvalResultTypes::Float(2.0)),
+            ("2.5.round(0, \"half_up\")", EvalResultTypes::Float(3.0)),
+            ("2.345.round(2, \"bankers\")", EvalResultTypes::Float(2.34)),
+            ("$min(minus_seven, two)", EvalResultTypes::Integer(-7)),
+            ("$max([1, two, 3])", EvalResultTypes::Integer(3)),
+            ("$sqrt(16)", EvalResultTypes::Float(4.0)),
+            ("$log(1)", EvalResultTypes::Float(0.0)),
+            ("$log(100, 10)", EvalResultTypes::Float(2.0)),
+            ("$exp(0)", EvalResultTypes::Float(1.0)),
+            ("$pow(two, ten)", EvalResultTypes::Integer(1024)),
+            ("$pow(ten, two)", EvalResultTypes::Integer(100)),
+            ("$pow(two, -1)", EvalResultTypes::Float(0.5)),
+            ("$pow(4, 0.5)", EvalResultTypes::Float(2.0)),
+            ("pow(minus_seven, 2)", EvalResultTypes::Integer(49)),
+            ("$pow(3, 3)", EvalResultTypes::Integer(27)),
+            ("$pow(3.0, 2)", EvalResultTypes::Float(9.0)),
+            (
+                "$pow(price, two)",
+                EvalResultTypes::Decimal("6.25".parse().unwrap()),
+            ),
+            (
+                "$pow(two, $pow(ten, two))",
+                EvalResultTypes::BigInt(BigInt::from(2).pow(100)),
+            ),
+            ("$clamp(minus_seven, 0, 10)", EvalResultTypes::Integer(0)),
+            ("$clamp(two, 0, 10)", EvalResultTypes::Integer(2)),
+            (
+                "$clamp(\"m\", \"a\", \"k\")",
+                EvalResultTypes::String("k".into()),
+            ),
+            ("minus_seven.sign()", EvalResultTypes::Integer(-1)),
+            ("$sign(0.0)", EvalResultTypes::Integer(0)),
+            ("price.sign()", EvalResultTypes::Integer(-1)),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let result = rule.evaluate(None, Some(&ctx)).unwrap();
+            // Numbers of different types compare equal, so the types are checked separately
+            assert_eq!(result.type_name(), expected.type_name(), "{}", text);
+            assert_eq!(result, expected, "{}", text);
+        }
+        for (text, message) in [
+            (
+                "$sqrt(-1)",
+                "Cannot take the square root of a negative number",
+            ),
+            ("$log(0)", "Cannot take the logarithm of 0"),
+            ("$log(8, 1)", "Invalid logarithm base 1"),
+            ("$exp(1000)", "Float out of range"),
+            ("$pow(0, -1)", "Cannot raise zero to a negative power"),
+            (
+                "$pow(-8, 0.5)",
+                "Cannot raise a negative number to a fractional power",
+            ),
+            ("$pow(two, $pow(ten, ten))", "Power too large"),
+            (
+                "$clamp(1, 10, 0)",
+                "The lower bound of clamp is greater than the upper bound",
+            ),
+            ("$sign(float(\"nan\"))", "Cannot take the sign of NaN"),
+            ("$ceil(float(\"inf\"))", "Cannot convert inf to an integer"),
+            (
+                "$sqrt(\"4\")",
+                "Argument 'value' of sqrt must be number, got string",
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, Some(&ctx)).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+        ctx.set_integer_overflow(IntegerOverflow::Error);
+        let rule = Rule::new("lowest.abs()".into()).unwrap();
+        let err = rule.evaluate(None, Some(&ctx)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow");
+    }
+
+    #[test]
+    fn test_collection_builtins() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "import decimal\nthing = {'order': {'lines': [{'sku': 'a', 'qty': 2}, {'sku': 'b', 'qty': 1}], 'limits': {'daily': 5, 1: 'one'}, 'items': 'data'}, 'prices': [decimal.Decimal('1.50'), decimal.Decimal('2.25')], 'tags': ('x', 'y')}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("thing").unwrap().downcast().unwrap();
+            let mut ctx = Context::new(None);
+            ctx.bind("three", EvalResultTypes::Integer(3));
+            let integers = |values: &[i64]| {
+                EvalResultTypes::List(
+                    values
+                        .iter()
+                        .map(|value| EvalResultTypes::Integer(*value))
+                        .collect(),
+                )
+            };
+            let string = |text: &str| EvalResultTypes::String(text.into());
+            for (text, expected) in [
+                ("[].is_empty", EvalResultTypes::Boolean(true)),
+                ("$is_empty(\"a\")", EvalResultTypes::Boolean(false)),
+                ("[1, 2].first", EvalResultTypes::Integer(1)),
+                ("$last([1, 2])", EvalResultTypes::Integer(2)),
+                ("[three, three].sum", EvalResultTypes::Integer(6)),
+                ("[].sum", EvalResultTypes::Integer(0)),
+                (
+                    "prices.sum",
+                    EvalResultTypes::Decimal("3.75".parse().unwrap()),
+                ),
+                ("[1, 2].avg", EvalResultTypes::Float(1.5)),
+                ("[three, 1, 2].median", EvalResultTypes::Integer(2)),
+                ("[three, three, 1, 2].median()", EvalResultTypes::Float(2.5)),
+                ("$median([three])", EvalResultTypes::Integer(3)),
+                (
+                    "[1, 2, 1.0, [1], [1]].distinct",
+                    EvalResultTypes::List(vec![
+                        EvalResultTypes::Integer(1),
+                        EvalResultTypes::Integer(2),
+                        integers(&[1]),
+                    ]),
+                ),
+                ("[2, 3, 1].sorted", integers(&[1, 2, 3])),
+                ("[2, 3, 1].sorted(true)", integers(&[3, 2, 1])),
+                ("[1, 2].reversed", integers(&[2, 1])),
+                ("\"abc\".reversed", string("cba")),
+                ("[[1, 2], [3], 4].flatten", integers(&[1, 2, 3, 4])),
+                (
+                    "$zip([1, 2, 3], [\"a\", \"b\"])",
+                    EvalResultTypes::List(vec![
+                        EvalResultTypes::List(vec![EvalResultTypes::Integer(1), string("a")]),
+                        EvalResultTypes::List(vec![EvalResultTypes::Integer(2), string("b")]),
+                    ]),
+                ),
+                (
+                    "tags.contains_all([\"y\", \"x\"])",
+                    EvalResultTypes::Boolean(true),
+                ),
+                (
+                    "tags.contains_all([\"x\", \"z\"])",
+                    EvalResultTypes::Boolean(false),
+                ),
+                (
+                    "tags.contains_any([\"z\", \"x\"])",
+                    EvalResultTypes::Boolean(true),
+                ),
+                ("tags.index_of(\"y\")", EvalResultTypes::Integer(1)),
+                ("[1, 2].index_of(3)", EvalResultTypes::Integer(-1)),
+                (
+                    "order.limits.keys",
+                    EvalResultTypes::List(vec![string("daily"), EvalResultTypes::Integer(1)]),
+                ),
+                (
+                    "$values(order.limits)",
+                    EvalResultTypes::List(vec![EvalResultTypes::Integer(5), string("one")]),
+                ),
+                ("order.limits.get(\"daily\")", EvalResultTypes::Integer(5)),
+                ("order.limits.get(1.0)", string("one")),
+                (
+                    "order.limits.get(\"weekly\", 10)",
+                    EvalResultTypes::Integer(10),
+                ),
+                ("order.limits.get(\"weekly\")", EvalResultTypes::Null),
+                ("order.limits.get([1])", EvalResultTypes::Null),
+                ("order.limits.length", EvalResultTypes::Integer(2)),
+                // Data is preferred to builtin properties, while calls always reach the builtin
+                ("order.items", string("data")),
+                ("order.items().length", EvalResultTypes::Integer(3)),
+                ("order.lines.first.sku", string("a")),
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                let result = rule.evaluate(Some(dict), Some(&ctx)).unwrap();
+                assert_eq!(result.type_name(), expected.type_name(), "{}", text);
+                assert_eq!(result, expected, "{}", text);
+            }
+            for (text, message) in [
+                ("[].first", "Cannot take the first item of an empty list"),
+                ("[].avg", "Cannot average an empty list"),
+                (
+                    "[\"a\", \"b\"].sum",
+                    "Cannot sum a list containing string, expected numbers, money or timedeltas",
+                ),
+                (
+                    "$sum(\"ab\")",
+                    "Argument 'values' of sum must be set or list, got string",
+                ),
+                (
+                    "tags.keys",
+                    "Argument 'mapping' of keys must be mapping, got list",
+                ),
+                ("[1, \"a\"].sorted", "Cannot compare string with integer"),
+            ] {
+                let rule = Rule::new(text.into()).unwrap();
+                let err = rule
+                    .evaluate(Some(dict), Some(&ctx))
+                    .unwrap_err()
+                    .to_string();
+                assert!(err.contains(message), "{}: {}", text, err);
+            }
+            // Mappings convert back to dicts
+            let rule = Rule::new("order.limits".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap().into_py(py);
+            let expected = py.eval("{'daily': 5, 1: 'one'}", None, None).unwrap();
+            assert!(result.as_ref(py).eq(expected).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_statistics_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        let integers = |values: &[i64]| {
+            EvalResultTypes::List(
+                values
+                    .iter()
+                    .map(|value| EvalResultTypes::Integer(*value))
+                    .collect(),
+            )
+        };
+        let floats = |values: &[f64]| {
+            EvalResultTypes::List(
+                values
+                    .iter()
+                    .map(|value| EvalResultTypes::Float(*value))
+                    .collect(),
+            )
+        };
+        // Large values with a small spread, where subtracting the square of the mean loses every
+        // significant digit
+        ctx.bind(
+            "offset",
+            integers(&[1_000_000_004, 1_000_000_007, 1_000_000_013, 1_000_000_016]),
+        );
+        ctx.bind(
+            "mixed",
+            EvalResultTypes::List(vec![
+                EvalResultTypes::Integer(2),
+                EvalResultTypes::Float(4.0),
+                EvalResultTypes::Decimal("4".parse().unwrap()),
+                EvalResultTypes::Integer(4),
+                EvalResultTypes::Float(5.0),
+                EvalResultTypes::Integer(5),
+                EvalResultTypes::Decimal("7".parse().unwrap()),
+                EvalResultTypes::Integer(9),
+            ]),
+        );
+        for (text, expected) in [
+            ("offset.variance", EvalResultTypes::Float(30.0)),
+            ("offset.variance(true)", EvalResultTypes::Float(22.5)),
+            ("mixed.stddev(true)", EvalResultTypes::Float(2.0)),
+            ("$stddev([1, 1])", EvalResultTypes::Float(0.0)),
+            ("$percentile(mixed, 50)", EvalResultTypes::Float(4.5)),
+            (
+                "$percentile([1, 2, 3, 4], 95)",
+                EvalResultTypes::Float(3.85),
+            ),
+            ("$percentile([5], 0)", EvalResultTypes::Float(5.0)),
+            ("mixed.mode", EvalResultTypes::Float(4.0)),
+            (
+                "[\"b\", \"a\", \"a\", \"b\"].mode",
+                EvalResultTypes::String("b".into()),
+            ),
+            ("$zscore(9, mixed, true)", EvalResultTypes::Float(2.0)),
+            ("$zscore(1000000010, offset)", EvalResultTypes::Float(0.0)),
+            ("[1, 2, 3, 4].moving_avg(2)", floats(&[1.5, 2.5, 3.5])),
+            ("[1, 2].moving_avg(3)", floats(&[])),
+            (
+                "[1e16, 1, -1e16, 1].moving_avg(2)",
+                floats(&[5e15, -5e15, -5e15]),
+            ),
+            ("[10, 20, 20].ewma(0.5)", floats(&[10.0, 15.0, 17.5])),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let result = rule.evaluate(None, Some(&ctx)).unwrap();
+            assert_eq!(result.type_name(), expected.type_name(), "{}", text);
+            match (&result, &expected) {
+                (EvalResultTypes::Float(result), EvalResultTypes::Float(expected)) => {
+                    assert!((result - expected).abs() < 1e-9, "{}: {}", text, result)
+                }
+                _ => assert_eq!(result, expected, "{}", text),
+            }
+        }
+        // Huge values don't overflow on the way to a deviation that is itself in range
+        for (text, expected) in [
+            ("$stddev([1e308, -1e308])", 1e308 * 2f64.sqrt()),
+            ("$stddev([1e308, -1e308], true)", 1e308),
+            ("$zscore(1e308, [1e308, -1e308], true)", 1.0),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let EvalResultTypes::Float(result) = rule.evaluate(None, Some(&ctx)).unwrap() else {
+                panic!("Expected a float for {}", text);
+            };
+            assert!(
+                ((result - expected) / expected).abs() < 1e-15,
+                "{}: {}",
+                text,
+                result
+            );
+        }
+        let rule = Rule::new("$percentile([1, float(\"nan\")], 50).is_nan".into()).unwrap();
+        assert_eq!(
+            rule.evaluate(None, Some(&ctx)).unwrap(),
+            EvalResultTypes::Boolean(true)
+        );
+        for (text, message) in [
+            ("[1].variance", "variance requires at least 2 values, got 1"),
+            ("[].stddev(true)", "stddev requires at least 1 value, got 0"),
+            (
+                "$percentile([], 50)",
+                "Cannot take a percentile of an empty list",
+            ),
+            (
+                "$percentile([1], 101)",
+                "Expected a percentile from 0 to 100, got 101",
+            ),
+            ("[].mode", "Cannot take the mode of an empty list"),
+            (
+                "[[1]].mode",
+                "Cannot take the mode of a list containing list",
+            ),
+            (
+                "$zscore(1, [2, 2])",
+                "Cannot take a z-score against values that are all equal",
+            ),
+            (
+                "[1, \"2\"].variance",
+                "Cannot take the variance of a list containing string, expected numbers",
+            ),
+            ("[1].moving_avg(0)", "Expected a window of at least 1"),
+            (
+                "[1].ewma(0)",
+                "Expected a smoothing factor greater than 0 and at most 1, got 0",
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, Some(&ctx)).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_locale_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let string = |text: &str| EvalResultTypes::String(text.into());
+        for (text, expected) in [
+            ("\"zh_hant_tw\".canonicalize", string("zh-Hant-TW")),
+            ("\"zh-Hant-TW\".region", string("TW")),
+            ("\"zh-Hant-TW\".script", string("Hant")),
+            ("\"en\".region", EvalResultTypes::Null),
+            ("\"pt-BR\".language_name", string("Portuguese")),
+            ("\"Brazilian Portuguese\".region", string("BR")),
+            ("\"iw\".language_name", string("Hebrew")),
+            ("\"tlh\".language_name", EvalResultTypes::Null),
+            ("\"english\".language_code", string("en")),
+            (
+                "$locale_matches([\"de-AT\", \"en-US\"], [\"fr\", \"en-GB\", \"en\"])",
+                string("en"),
+            ),
+            (
+                "$locale_matches(\"zh-TW\", [\"zh-Hans\", \"zh-Hant\"])",
+                string("zh-Hant"),
+            ),
+            (
+                "$locale_matches(\"zh-TW\", [\"zh-Hans\"])",
+                EvalResultTypes::Null,
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        for (text, message) in [
+            ("\"en-\".region", "Invalid locale 'en-'"),
+            (
+                "$locale_matches(\"en\", [\"en\", 1])",
+                "Expected locale string, got integer",
+            ),
+            ("$locale_matches(\"en\", [\"??\"])", "Invalid locale '??'"),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, None).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_ip_builtins() {
+        pyo3::prepare_freethreaded_python();
+        let boolean = EvalResultTypes::Boolean;
+        for (text, expected) in [
+            ("$ip(\"10.1.2.3\") in $cidr(\"10.0.0.0/8\")", boolean(true)),
+            (
+                "$ip(\"11.1.2.3\") not in $cidr(\"10.0.0.0/8\")",
+                boolean(true),
+            ),
+            ("$ip(\"::1\") in $cidr(\"10.0.0.0/8\")", boolean(false)),
+            (
+                "$ip(\"2001:db8::1\") in $cidr(\"2001:db8::/32\")",
+                boolean(true),
+            ),
+            ("$ip(\"10.0.0.1\") in $cidr(\"10.0.0.1\")", boolean(true)),
+            ("\"a\" in \"cat\"", boolean(true)),
+            ("2 in [1, 2]", boolean(true)),
+            ("3 not in {1, 2}", boolean(true)),
+            ("$ip(\"10.0.0.2\") > $ip(\"10.0.0.10\")", boolean(false)),
+            ("$ip(\"10.0.0.1\") == $ip(\" 10.0.0.1\")", boolean(true)),
+            (
+                "$ip(\"::ffff:1.2.3.4\") == $ip(\"1.2.3.4\")",
+                boolean(false),
+            ),
+            ("\"192.168.1.1\".is_private", boolean(true)),
+            ("$ip(\"172.32.0.1\").is_private", boolean(false)),
+            ("$ip(\"fd00::1\").is_private", boolean(true)),
+            ("$ip(\"::ffff:10.0.0.1\").is_private", boolean(true)),
+            ("$ip(\"127.0.0.2\").is_loopback", boolean(true)),
+            ("$ip(\"::1\").is_loopback", boolean(true)),
+            ("$ip(\"8.8.8.8\").is_loopback", boolean(false)),
+            ("$ip(\"::1\").ip_version", EvalResultTypes::Integer(6)),
+            ("$ip(\"1.2.3.4\") is ip", boolean(true)),
+            ("$cidr(\"1.2.3.0/24\") is network", boolean(true)),
+            (
+                "$str($cidr(\"2001:db8::/32\"))",
+                EvalResultTypes::String("2001:db8::/32".into()),
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            assert_eq!(rule.evaluate(None, None).unwrap(), expected, "{}", text);
+        }
+        for (text, message) in [
+            ("$ip(\"10.0.0.256\")", "Invalid IP address '10.0.0.256'"),
+            (
+                "\"localhost\".is_loopback",
+                "Invalid IP address 'localhost'",
+            ),
+            ("$cidr(\"10.0.0.0/33\")", "Invalid network '10.0.0.0/33'"),
+            (
+                "$cidr(\"10.1.0.0/8\")",
+                "Invalid network '10.1.0.0/8', it has host bits set (did you mean 10.0.0.0/8?)",
+            ),
+            ("1 in 2", "Cannot check whether integer is in integer"),
+            (
+                "$ip(\"1.2.3.4\") < \"1.2.3.5\"",
+                "Cannot compare ip with string",
+            ),
+        ] {
+            let rule = Rule::new(text.into()).unwrap();
+            let err = rule.evaluate(None, None).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", text, err);
+        }
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "import ipaddress\nrequest = {'remote_addr': ipaddress.ip_address('10.1.2.3'), 'office': ipaddress.ip_network('10.1.0.0/16')}",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let dict: &PyDict = locals.get_item("request").unwrap().downcast().unwrap();
+            let rule =
+                Rule::new("remote_addr in office and remote_addr.is_private".into()).unwrap();
+            assert_eq!(rule.evaluate(Some(dict), None).unwrap(), boolean(true));
+            let rule = Rule::new("office".into()).unwrap();
+            let result = rule.evaluate(Some(dict), None).unwrap();
+            let expected = py.eval("request['office']", None, Some(locals)).unwrap();
+            assert!(result.into_py(py).as_ref(py).eq(expected).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_builtin_registry() {
+        pyo3::prepare_freethreaded_python();
+        let mut ctx = Context::new(None);
+        ctx.register_builtin(
+            Builtin::function(
+                "tax.vat",
+                vec![Parameter::required("amount", &[ValueType::Number])],
+                |_, args| match args.as_slice() {
+                    [EvalResultTypes::Integer(amount)] => Ok(EvalResultTypes::Integer(amount / 5)),
+                    _ => Err(EvaluationError::new("Expected an integer")),
+                },
+            )
+            .with_doc("Value added tax"),
+        )
+        .unwrap();
+        // Names alone must stay unambiguous, including against the standard builtins
+        for name in ["crm.vat", "round"] {
+            assert!(ctx
+                .register_builtin(Builtin::function(name, vec![], |_, _| {
+                    Ok(EvalResultTypes::Null)
+                }))
+                .is_err());
+        }
+        ctx.bind("price", EvalResultTypes::Integer(100));
+        ctx.set_clock(Clock::Fixed(
+            parse_datetime("2024-01-01T00:00:00Z").unwrap(),
+        ));
+        for text in [
+            "$vat(price)",
+            "$tax.vat(price)",
+            "price.vat()",
+            "vat(price)",
+        ] {
+            let rule = Rule::py_new(text.into(), Some(&ctx)).unwrap();
+            assert_eq!(
+                rule.evaluate(None, Some(&ctx)).unwrap(),
+                EvalResultTypes::Integer(20),
+                "{}",
+                text
+            );
+        }
+        // Standard builtins can also be called by their qualified names
+        let rule = Rule::new("$math.round(2.5m) == round(2.5m) and $time.now == $now".into());
+        assert!(rule
+            .unwrap()
+            .evaluate(None, Some(&ctx))
+            .unwrap()
+            .is_truthy());
+        // Without the context the custom builtin is unknown
+        assert!(Rule::new("$vat(price)".into()).is_err());
+        assert!(Rule::new("$tax.vat()".into()).is_err());
+        assert!(Rule::new("$math".into())
+            .unwrap()
+            .evaluate(None, None)
+            .is_err());
+        assert!(Rule::new("$math.now".into()).is_err());
+        Python::with_gil(|py| {
+            let descriptions = ctx.describe_builtins(py).unwrap();
+            let vat = descriptions.last().unwrap().as_ref(py);
+            let field = |name: &str| vat.get_item(name).unwrap().to_string();
+            assert_eq!(field("name"), "tax.vat");
+            assert_eq!(field("kind"), "function");
+            assert_eq!(field("signature"), "vat(amount: number)");
+            assert_eq!(field("doc"), "Value added tax");
+        });
     }
 }
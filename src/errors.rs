@@ -1,20 +1,62 @@
-use pyo3::exceptions::PyValueError;
-use pyo3::PyErr;
+use pyo3::exceptions::{PyOverflowError, PyTypeError, PyValueError};
+use pyo3::{PyErr, Python};
 use std::fmt;
 
+/// The Python exception an error is raised as. It is kept when the error is converted to a more
+/// general one, so an error raised deep in an evaluation reaches Python as the same exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Value,
+    Type,
+    Overflow,
+}
+
+impl ErrorKind {
+    fn to_py_err(self, message: String) -> PyErr {
+        match self {
+            ErrorKind::Value => PyErr::new::<PyValueError, _>(message),
+            ErrorKind::Type => PyErr::new::<PyTypeError, _>(message),
+            ErrorKind::Overflow => PyErr::new::<PyOverflowError, _>(message),
+        }
+    }
+}
+
 macro_rules! define_error {
     ($name:ident, $base:ident) => {
+        define_error!($name, $base, Value);
+    };
+    ($name:ident, $base:ident, $kind:ident) => {
         #[derive(Debug)]
         pub struct $name {
             message: String,
+            /// The Python exception this error was raised from, if any
+            cause: Option<PyErr>,
+            kind: ErrorKind,
         }
 
         impl $name {
             pub fn new(message: &str) -> Self {
                 $name {
                     message: message.to_string(),
+                    cause: None,
+                    kind: ErrorKind::$kind,
                 }
             }
+
+            /// An error raised by Python code, which is kept as the cause of the exception raised
+            /// to Python so that its traceback isn't lost
+            pub fn with_cause(message: &str, cause: PyErr) -> Self {
+                $name {
+                    message: message.to_string(),
+                    cause: Some(cause),
+                    kind: ErrorKind::$kind,
+                }
+            }
+
+            #[allow(dead_code)]
+            pub fn kind(&self) -> ErrorKind {
+                self.kind
+            }
         }
 
         impl fmt::Display for $name {
@@ -27,27 +69,38 @@ macro_rules! define_error {
 
         impl From<$name> for $base {
             fn from(err: $name) -> Self {
-                $base::new(&err.message)
+                let mut base = $base::new(&err.message);
+                base.cause = err.cause;
+                base.kind = err.kind;
+                base
             }
         }
 
         impl From<$name> for PyErr {
             fn from(err: $name) -> Self {
-                // FIXME: Need to solve for real error types
-                PyErr::new::<PyValueError, _>(err.message)
+                let py_err = err.kind.to_py_err(err.message);
+                if let Some(cause) = err.cause {
+                    Python::with_gil(|py| py_err.set_cause(py, Some(cause)));
+                }
+                py_err
             }
         }
     };
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct EngineError {
     message: String,
+    cause: Option<PyErr>,
+    kind: ErrorKind,
 }
 impl EngineError {
     pub fn new(message: &str) -> Self {
         EngineError {
             message: message.to_string(),
+            cause: None,
+            kind: ErrorKind::Value,
         }
     }
 }
@@ -60,7 +113,21 @@ impl fmt::Display for EngineError {
 define_error!(EvaluationError, EngineError);
 define_error!(SymbolResolutionError, EvaluationError);
 define_error!(InvalidParameterTypeError, EvaluationError);
+define_error!(IncomparableTypesError, EvaluationError, Type);
+define_error!(IntegerOverflowError, EvaluationError, Overflow);
 define_error!(TypeConversionError, EngineError);
+define_error!(ContextError, EngineError);
+
+/// A builtin property failing while an attribute is resolved, e.g. a registered Python callable
+impl From<EvaluationError> for SymbolResolutionError {
+    fn from(err: EvaluationError) -> Self {
+        SymbolResolutionError {
+            message: err.message,
+            cause: err.cause,
+            kind: err.kind,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -1,10 +1,18 @@
+// pyo3 0.19's `#[pymethods]` expansion trips this lint on newer compilers
+#![allow(non_local_definitions)]
 extern crate core;
 
 pub mod ast;
 mod builtins;
+mod calendar;
 pub mod engine;
 mod errors;
+mod locale;
+mod mapping;
+mod money;
 mod parser;
+mod registry;
+mod set;
 mod utils;
 
 use pyo3::prelude::*;
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use unic_langid::subtags::Language;
+use unic_langid::LanguageIdentifier;
+
+use crate::errors::EvaluationError;
+
+/// English names of languages by code. The first name is the one `language_name` gives and the
+/// others are aliases that are accepted wherever a locale is, e.g. `"Farsi".language_code` is `fa`.
+const LANGUAGE_NAMES: &[(&str, &[&str])] = &[
+    ("af", &["Afrikaans"]),
+    ("am", &["Amharic"]),
+    ("ar", &["Arabic"]),
+    ("as", &["Assamese"]),
+    ("az", &["Azerbaijani", "Azeri"]),
+    ("be", &["Belarusian"]),
+    ("bg", &["Bulgarian"]),
+    ("bn", &["Bengali", "Bangla"]),
+    ("bs", &["Bosnian"]),
+    ("ca", &["Catalan", "Valencian"]),
+    ("cs", &["Czech"]),
+    ("cy", &["Welsh"]),
+    ("da", &["Danish"]),
+    ("de", &["German", "Deutsch"]),
+    ("el", &["Greek"]),
+    ("en", &["English"]),
+    ("eo", &["Esperanto"]),
+    ("es", &["Spanish", "Castilian", "Español"]),
+    ("et", &["Estonian"]),
+    ("eu", &["Basque"]),
+    ("fa", &["Persian", "Farsi"]),
+    ("fi", &["Finnish"]),
+    ("fil", &["Filipino", "Tagalog"]),
+    ("fr", &["French", "Français"]),
+    ("ga", &["Irish", "Gaelic", "Irish Gaelic"]),
+    ("gd", &["Scottish Gaelic"]),
+    ("gl", &["Galician"]),
+    ("gsw", &["Swiss German"]),
+    ("gu", &["Gujarati"]),
+    ("ha", &["Hausa"]),
+    ("he", &["Hebrew"]),
+    ("hi", &["Hindi"]),
+    ("hr", &["Croatian"]),
+    ("ht", &["Haitian Creole", "Haitian"]),
+    ("hu", &["Hungarian"]),
+    ("hy", &["Armenian"]),
+    ("id", &["Indonesian", "Bahasa Indonesia"]),
+    ("ig", &["Igbo"]),
+    ("is", &["Icelandic"]),
+    ("it", &["Italian", "Italiano"]),
+    ("ja", &["Japanese"]),
+    ("jv", &["Javanese"]),
+    ("ka", &["Georgian"]),
+    ("kk", &["Kazakh"]),
+    ("km", &["Khmer", "Cambodian"]),
+    ("kn", &["Kannada"]),
+    ("ko", &["Korean"]),
+    ("ku", &["Kurdish"]),
+    ("ky", &["Kyrgyz", "Kirghiz"]),
+    ("la", &["Latin"]),
+    ("lb", &["Luxembourgish"]),
+    ("lo", &["Lao", "Laotian"]),
+    ("lt", &["Lithuanian"]),
+    ("lv", &["Latvian"]),
+    ("mg", &["Malagasy"]),
+    ("mi", &["Maori"]),
+    ("mk", &["Macedonian"]),
+    ("ml", &["Malayalam"]),
+    ("mn", &["Mongolian"]),
+    ("mr", &["Marathi"]),
+    ("ms", &["Malay", "Bahasa Melayu"]),
+    ("mt", &["Maltese"]),
+    ("my", &["Burmese", "Myanmar"]),
+    (
+        "nb",
+        &["Norwegian Bokmål", "Bokmål", "Norwegian Bokmal", "Bokmal"],
+    ),
+    ("ne", &["Nepali"]),
+    ("nl", &["Dutch", "Flemish"]),
+    ("nn", &["Norwegian Nynorsk", "Nynorsk"]),
+    ("no", &["Norwegian"]),
+    ("pa", &["Punjabi", "Panjabi"]),
+    ("pl", &["Polish"]),
+    ("ps", &["Pashto", "Pushto"]),
+    ("pt", &["Portuguese", "Português"]),
+    ("ro", &["Romanian", "Moldavian", "Moldovan"]),
+    ("ru", &["Russian"]),
+    ("rw", &["Kinyarwanda"]),
+    ("sd", &["Sindhi"]),
+    ("si", &["Sinhala", "Sinhalese"]),
+    ("sk", &["Slovak"]),
+    ("sl", &["Slovenian", "Slovene"]),
+    ("so", &["Somali"]),
+    ("sq", &["Albanian"]),
+    ("sr", &["Serbian"]),
+    ("sv", &["Swedish"]),
+    ("sw", &["Swahili", "Kiswahili"]),
+    ("ta", &["Tamil"]),
+    ("te", &["Telugu"]),
+    ("tg", &["Tajik"]),
+    ("th", &["Thai"]),
+    ("tk", &["Turkmen"]),
+    ("tr", &["Turkish"]),
+    ("uk", &["Ukrainian"]),
+    ("ur", &["Urdu"]),
+    ("uz", &["Uzbek"]),
+    ("vi", &["Vietnamese"]),
+    ("xh", &["Xhosa"]),
+    ("yi", &["Yiddish"]),
+    ("yo", &["Yoruba"]),
+    ("yue", &["Cantonese"]),
+    ("zh", &["Chinese", "Mandarin"]),
+    ("zu", &["Zulu"]),
+];
+
+/// Names of languages as spoken in a region or written in a script
+const LOCALE_NAMES: &[(&str, &str)] = &[
+    ("American English", "en-US"),
+    ("British English", "en-GB"),
+    ("Australian English", "en-AU"),
+    ("Canadian English", "en-CA"),
+    ("Canadian French", "fr-CA"),
+    ("Brazilian Portuguese", "pt-BR"),
+    ("European Portuguese", "pt-PT"),
+    ("European Spanish", "es-ES"),
+    ("Latin American Spanish", "es-419"),
+    ("Mexican Spanish", "es-MX"),
+    ("Simplified Chinese", "zh-Hans"),
+    ("Traditional Chinese", "zh-Hant"),
+];
+
+/// Codes replaced by CLDR, and ISO 639-2 codes of languages that have a shorter code
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("iw", "he"),
+    ("in", "id"),
+    ("ji", "yi"),
+    ("jw", "jv"),
+    ("mo", "ro"),
+    ("tl", "fil"),
+    ("ara", "ar"),
+    ("chi", "zh"),
+    ("zho", "zh"),
+    ("cze", "cs"),
+    ("ces", "cs"),
+    ("dan", "da"),
+    ("dut", "nl"),
+    ("nld", "nl"),
+    ("eng", "en"),
+    ("fin", "fi"),
+    ("fre", "fr"),
+    ("fra", "fr"),
+    ("ger", "de"),
+    ("deu", "de"),
+    ("gre", "el"),
+    ("ell", "el"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hun", "hu"),
+    ("ita", "it"),
+    ("jpn", "ja"),
+    ("kor", "ko"),
+    ("nor", "no"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("rum", "ro"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("swe", "sv"),
+    ("tha", "th"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("vie", "vi"),
+];
+
+/// Locales by lowercase English name
+fn locales_by_name() -> &'static HashMap<String, &'static str> {
+    static NAMES: OnceLock<HashMap<String, &'static str>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let languages = LANGUAGE_NAMES
+            .iter()
+            .flat_map(|(code, names)| names.iter().map(move |name| (*name, *code)));
+        languages
+            .chain(LOCALE_NAMES.iter().copied())
+            .map(|(name, tag)| (name.to_lowercase(), tag))
+            .collect()
+    })
+}
+
+/// Parses a BCP-47 tag such as `zh-Hant-TW`, also accepting the English name of a language such
+/// as `Brazilian Portuguese` and POSIX locales such as `en_US.UTF-8`. Subtags are given their
+/// canonical case and replaced language codes their current one, e.g. `iw` becomes `he`.
+pub fn parse_locale(text: &str) -> Result<LanguageIdentifier, EvaluationError> {
+    let text = text.trim();
+    let tag = match locales_by_name().get(&text.to_lowercase()) {
+        Some(tag) => tag,
+        // A POSIX locale's codeset and modifier aren't part of the language tag
+        None => text.split(['.', '@']).next().unwrap_or_default(),
+    };
+    let mut locale = LanguageIdentifier::from_str(tag)
+        .ok()
+        .filter(|_| !tag.is_empty())
+        .ok_or_else(|| EvaluationError::new(&format!("Invalid locale '{}'", text)))?;
+    let alias = LANGUAGE_ALIASES
+        .iter()
+        .find(|(code, _)| locale.language == *code);
+    if let Some((_, replacement)) = alias {
+        // Every replacement is a valid language subtag
+        locale.language = Language::from_str(replacement).unwrap_or(locale.language);
+    }
+    Ok(locale)
+}
+
+/// The English name of a locale's language, if it is one of the languages known by name
+pub fn language_name(locale: &LanguageIdentifier) -> Option<&'static str> {
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(code, _)| locale.language == *code)
+        .map(|(_, names)| names[0])
+}
+
+fn maximized(locale: &LanguageIdentifier) -> LanguageIdentifier {
+    let mut locale = locale.clone();
+    locale.maximize();
+    locale
+}
+
+/// Picks the available locale that best serves the first requested locale that any of them
+/// serves. For each requested locale, in order of preference, an available locale is accepted when
+/// it
+///
+/// 1. is the same locale,
+/// 2. is less specific, e.g. `en` for `en-US`,
+/// 3. has the same likely script and region, e.g. `zh-Hant-TW` for `zh-TW` or `en-US` for `en`,
+/// 4. is more specific, e.g. `en-GB` for `en`, or
+/// 5. has the same language and likely script in another region, e.g. `en-GB` for `en-AU` but not
+///    `zh-CN` for `zh-TW`.
+///
+/// Returns the index of the chosen locale within `available`.
+pub fn negotiate(
+    requested: &[LanguageIdentifier],
+    available: &[LanguageIdentifier],
+) -> Option<usize> {
+    let available_maximized: Vec<LanguageIdentifier> = available.iter().map(maximized).collect();
+    for wanted in requested {
+        let wanted_maximized = maximized(wanted);
+        let steps: [&dyn Fn(usize) -> bool; 5] = [
+            &|index| available[index] == *wanted,
+            &|index| available[index].matches(wanted, true, false),
+            &|index| available_maximized[index] == wanted_maximized,
+            &|index| wanted.matches(&available[index], true, false),
+            &|index| {
+                available_maximized[index].language == wanted_maximized.language
+                    && available_maximized[index].script == wanted_maximized.script
+            },
+        ];
+        for step in steps {
+            if let Some(index) = (0..available.len()).find(|index| step(*index)) {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locales(tags: &[&str]) -> Vec<LanguageIdentifier> {
+        tags.iter().map(|tag| parse_locale(tag).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        for (text, expected) in [
+            ("EN_us", "en-US"),
+            ("en_US.UTF-8", "en-US"),
+            ("sr-latn-rs", "sr-Latn-RS"),
+            ("iw-IL", "he-IL"),
+            ("eng", "en"),
+            ("  farsi ", "fa"),
+            ("Brazilian Portuguese", "pt-BR"),
+        ] {
+            assert_eq!(
+                parse_locale(text).unwrap().to_string(),
+                expected,
+                "{}",
+                text
+            );
+        }
+        assert!(parse_locale("").is_err());
+        assert!(parse_locale("en-").is_err());
+        assert!(parse_locale("not a locale").is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let available = locales(&["en-GB", "en", "fr-CA", "zh-Hant", "zh-Hans-CN", "pt"]);
+        for (requested, expected) in [
+            (vec!["en-GB"], Some(0)),
+            (vec!["en-US"], Some(1)),
+            (vec!["fr"], Some(2)),
+            (vec!["fr-FR"], Some(2)),
+            (vec!["zh-TW"], Some(3)),
+            (vec!["zh-SG"], Some(4)),
+            (vec!["pt-BR"], Some(5)),
+            (vec!["de", "en-AU"], Some(1)),
+            (vec!["de"], None),
+        ] {
+            assert_eq!(
+                negotiate(&locales(&requested), &available),
+                expected,
+                "{:?}",
+                requested
+            );
+        }
+        // A region-specific locale is preferred to a sibling region
+        let available = locales(&["en-GB", "en-US"]);
+        assert_eq!(negotiate(&locales(&["en"]), &available), Some(1));
+    }
+}
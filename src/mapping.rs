@@ -0,0 +1,113 @@
+use indexmap::IndexMap;
+
+use crate::ast::EvalResultTypes;
+use crate::errors::EvaluationError;
+use crate::set::SetKey;
+
+/// Mapping keys are matched like set elements, so `1` and `1.0` are the same key, as in Python.
+/// Money can't be a key since it is a dict in Python, which isn't hashable.
+fn key_of(key: &EvalResultTypes) -> Result<SetKey, EvaluationError> {
+    match key {
+        EvalResultTypes::List(_)
+        | EvalResultTypes::Set(_)
+        | EvalResultTypes::Mapping(_)
+        | EvalResultTypes::Money(_) => Err(EvaluationError::new(&format!(
+            "Cannot use a {} as a mapping key",
+            key.type_name()
+        ))),
+        _ => SetKey::of(key),
+    }
+}
+
+/// A mapping from distinct keys to values, like a Python dict. Entries are kept in insertion order
+/// so that `keys`, `values` and `items` are deterministic.
+#[derive(Clone, Debug, Default)]
+pub struct ValueMap {
+    items: IndexMap<SetKey, (EvalResultTypes, EvalResultTypes)>,
+}
+impl ValueMap {
+    pub fn new() -> Self {
+        ValueMap::default()
+    }
+
+    /// Sets the value of a key. Like Python, the key that was inserted first is the one kept when
+    /// an equal key replaces its value.
+    pub fn insert(
+        &mut self,
+        key: EvalResultTypes,
+        value: EvalResultTypes,
+    ) -> Result<(), EvaluationError> {
+        self.items
+            .entry(key_of(&key)?)
+            .and_modify(|entry| entry.1 = value.clone())
+            .or_insert((key, value));
+        Ok(())
+    }
+
+    /// Keys that can't be mapping keys are never present
+    pub fn get(&self, key: &EvalResultTypes) -> Option<&EvalResultTypes> {
+        let key = key_of(key).ok()?;
+        self.items.get(&key).map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &EvalResultTypes> {
+        self.items.values().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &EvalResultTypes> {
+        self.items.values().map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&EvalResultTypes, &EvalResultTypes)> {
+        self.items.values().map(|(key, value)| (key, value))
+    }
+}
+impl PartialEq for ValueMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.items.iter().all(|(key, (_, value))| {
+                other
+                    .items
+                    .get(key)
+                    .is_some_and(|(_, other_value)| value == other_value)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> EvalResultTypes {
+        EvalResultTypes::String(text.to_string())
+    }
+
+    #[test]
+    fn test_equal_keys_share_an_entry() {
+        let mut mapping = ValueMap::new();
+        mapping
+            .insert(EvalResultTypes::Integer(1), string("one"))
+            .unwrap();
+        mapping
+            .insert(EvalResultTypes::Float(1.0), string("uno"))
+            .unwrap();
+        assert_eq!(mapping.len(), 1);
+        assert_eq!(mapping.keys().next(), Some(&EvalResultTypes::Integer(1)));
+        assert_eq!(
+            mapping.get(&EvalResultTypes::Integer(1)),
+            Some(&string("uno"))
+        );
+        assert_eq!(mapping.get(&EvalResultTypes::List(vec![])), None);
+        assert!(mapping
+            .insert(EvalResultTypes::List(vec![]), EvalResultTypes::Null)
+            .is_err());
+    }
+}
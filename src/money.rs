@@ -0,0 +1,125 @@
+use rust_decimal::Decimal;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::{EvaluationError, TypeConversionError};
+
+/// The active ISO 4217 alphabetic codes, sorted so that they can be binary searched
+const CURRENCY_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD",
+    "CAD", "CDF", "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP",
+    "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP",
+    "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS",
+    "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW",
+    "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD",
+    "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN",
+    "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR",
+    "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SOS",
+    "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD",
+    "TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS", "VED", "VES", "VND",
+    "VUV", "WST", "XAF", "XAG", "XAU", "XBA", "XBB", "XBC", "XBD", "XCD", "XCG", "XDR", "XOF",
+    "XPD", "XPF", "XPT", "XSU", "XTS", "XUA", "XXX", "YER", "ZAR", "ZMW", "ZWG", "ZWL",
+];
+
+/// An amount of a single currency. Arithmetic and comparisons between different currencies are
+/// errors rather than silently mixing units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Money {
+    pub amount: Decimal,
+    /// An ISO-4217 code, e.g. `USD`
+    pub currency: String,
+}
+impl Money {
+    pub fn new(amount: Decimal, currency: &str) -> Result<Self, TypeConversionError> {
+        if CURRENCY_CODES.binary_search(&currency).is_err() {
+            return Err(TypeConversionError::new(&format!(
+                "Invalid currency code '{}', expected an ISO 4217 code such as 'USD'",
+                currency
+            )));
+        }
+        Ok(Money {
+            amount,
+            currency: currency.to_string(),
+        })
+    }
+
+    /// Fails unless both amounts are in the same currency
+    pub fn check_currency(&self, other: &Money, verb: &str) -> Result<(), EvaluationError> {
+        if self.currency != other.currency {
+            return Err(EvaluationError::new(&format!(
+                "Cannot {} {} and {}",
+                verb, self.currency, other.currency
+            )));
+        }
+        Ok(())
+    }
+
+    /// Builds an amount in the same currency, failing if the operation overflowed
+    pub fn with_amount(&self, amount: Option<Decimal>) -> Result<Money, EvaluationError> {
+        let amount = amount.ok_or_else(|| EvaluationError::new("Money out of range"))?;
+        Ok(Money {
+            amount,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+/// Parses the body of a money literal, e.g. `12.50 USD`
+impl FromStr for Money {
+    type Err = TypeConversionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            TypeConversionError::new(&format!(
+                "Invalid money '{}', expected an amount and a currency such as '12.50 USD'",
+                text
+            ))
+        };
+        let (amount, currency) = text.trim().split_once(' ').ok_or_else(invalid)?;
+        let amount = Decimal::from_str(amount.trim()).map_err(|_| invalid())?;
+        Money::new(amount, currency.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_money() {
+        let money: Money = "12.50 USD".parse().unwrap();
+        assert_eq!(money.amount, Decimal::new(1250, 2));
+        assert_eq!(money.currency, "USD");
+        assert_eq!(money.to_string(), "12.50 USD");
+        assert_eq!(
+            "-3 EUR".parse::<Money>().unwrap().amount,
+            Decimal::new(-3, 0)
+        );
+        assert!("12.50".parse::<Money>().is_err());
+        assert!("12.50 usd".parse::<Money>().is_err());
+        assert_eq!(
+            "12.50 XYZ".parse::<Money>().unwrap_err().to_string(),
+            "Invalid currency code 'XYZ', expected an ISO 4217 code such as 'USD'"
+        );
+        assert_eq!("1 JPY".parse::<Money>().unwrap().currency, "JPY");
+        assert!("twelve USD".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn test_check_currency() {
+        let usd: Money = "1 USD".parse().unwrap();
+        let eur: Money = "1 EUR".parse().unwrap();
+        assert!(usd.check_currency(&usd, "add").is_ok());
+        assert_eq!(
+            usd.check_currency(&eur, "add").unwrap_err().to_string(),
+            "Cannot add USD and EUR"
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use pyo3::prelude::*;
 
-use crate::ast::Statement;
+use crate::ast::Program;
+use crate::builtins::standard_builtins;
 use crate::engine::Context;
 
 use crate::errors::ParseError;
+use crate::registry::BuiltinRegistry;
 use lrlex::{lrlex_mod, DefaultLexerTypes};
 use lrpar::lrpar_mod;
 
@@ -16,7 +18,16 @@ pub struct Parser {
 }
 
 impl Parser {
-    pub fn parse_internal(&self, text: String) -> Result<Statement, ParseError> {
+    pub fn parse_internal(&self, text: String) -> Result<Program, ParseError> {
+        self.parse_with_builtins(text, &standard_builtins())
+    }
+
+    /// Parses a program that may reference the builtins of a registry besides the standard ones
+    pub fn parse_with_builtins(
+        &self,
+        text: String,
+        builtins: &BuiltinRegistry,
+    ) -> Result<Program, ParseError> {
         let lexer = self.lexerdef.lexer(&text);
         let (res, errs) = rule_y::parse(&lexer);
         if !errs.is_empty() {
@@ -25,8 +36,9 @@ impl Parser {
                 errs
             )));
         }
-        if let Some(Ok(r)) = res {
-            Ok(Statement::Expression(r))
+        if let Some(Ok(program)) = res {
+            program.validate(builtins)?;
+            Ok(program)
         } else {
             Err(ParseError::new("Failed to parse expression"))
         }
@@ -47,15 +59,15 @@ impl Parser {
     }
 
     pub fn parse(&self, text: String, context: &Context) -> PyResult<Py<PyAny>> {
-        let res = self.parse_internal(text).map_err(map_err_to_py)?;
+        let res = self
+            .parse_with_builtins(text, context.builtins())
+            .map_err(map_err_to_py)?;
         Python::with_gil(|py| -> Result<Py<PyAny>, PyErr> {
             // FIXME: We should be returning a statement from this parse function
             //  - Can an enum be a python class? Probably not so we'll need to wrap it in a struct or something
             match res.evaluate(context, None) {
                 Ok(result) => Ok(result.into_py(py)),
-                Err(e) => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                    e.to_string(),
-                )),
+                Err(e) => Err(e.into()),
             }
         })
     }
@@ -73,4 +85,15 @@ mod tests {
         // Test single quotes
         assert!(parser.parse_internal(String::from("\'hello\'")).is_ok());
     }
+
+    #[test]
+    fn test_recovery_inserting_builtins() {
+        let parser = Parser::new();
+        // Recovery picks one of the tokens that could complete the comparison, BUILTIN among
+        // them, in no fixed order. The action for an inserted BUILTIN must not panic on its empty
+        // span, so try enough times that it is almost surely picked.
+        for _ in 0..200 {
+            assert!(parser.parse_internal(String::from("1 ==")).is_err());
+        }
+    }
 }
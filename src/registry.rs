@@ -0,0 +1,615 @@
+use indexmap::IndexMap;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem::{discriminant, Discriminant};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::ast::{EvalResult, EvalResultTypes, ValueType};
+use crate::engine::Context;
+use crate::errors::{ContextError, EvaluationError, InvalidParameterTypeError};
+use crate::set::SetKey;
+use crate::utils::try_into_eval_result_types;
+
+/// The most results a pure Python builtin remembers. The cache is emptied when it fills up.
+const MAX_CACHED_RESULTS: usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    /// The types the argument may have, or any type when empty
+    pub types: Vec<ValueType>,
+    /// Parameters without a default are required and come before those with one
+    pub default: Option<EvalResultTypes>,
+}
+impl Parameter {
+    pub fn required(name: &str, types: &[ValueType]) -> Self {
+        Parameter {
+            name: name.to_string(),
+            types: types.to_vec(),
+            default: None,
+        }
+    }
+
+    pub fn optional(name: &str, types: &[ValueType], default: EvalResultTypes) -> Self {
+        Parameter {
+            default: Some(default),
+            ..Parameter::required(name, types)
+        }
+    }
+
+    fn accepts(&self, value: &EvalResultTypes) -> bool {
+        self.types.is_empty()
+            || self
+                .types
+                .iter()
+                .any(|value_type| value_type.matches(value))
+    }
+
+    fn expected(&self) -> String {
+        let names: Vec<&str> = self
+            .types
+            .iter()
+            .map(|value_type| value_type.name())
+            .collect();
+        names.join(" or ")
+    }
+}
+
+/// Written as in documentation, e.g. `places: number = 0`
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.types.is_empty() {
+            let names: Vec<&str> = self
+                .types
+                .iter()
+                .map(|value_type| value_type.name())
+                .collect();
+            write!(f, ": {}", names.join(" | "))?;
+        }
+        match &self.default {
+            None => Ok(()),
+            Some(EvalResultTypes::Null) => write!(f, " = null"),
+            Some(EvalResultTypes::String(value)) => write!(f, " = {:?}", value),
+            Some(EvalResultTypes::Integer(value)) => write!(f, " = {}", value),
+            Some(EvalResultTypes::Float(value)) => write!(f, " = {:?}", value),
+            Some(EvalResultTypes::Boolean(value)) => write!(f, " = {}", value),
+            Some(value) => write!(f, " = <{}>", value.type_name()),
+        }
+    }
+}
+
+/// The parameters of a builtin. Method calls pass the receiver as the first argument, so
+/// `name.starts_with("A")` and `$starts_with(name, "A")` bind the same parameters.
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    /// Whether the last parameter also accepts any number of further arguments. When it has a
+    /// default it may be given no arguments at all, and then nothing is passed for it.
+    pub variadic: bool,
+}
+impl Signature {
+    /// Checks the number of arguments, which is known before the rule is evaluated
+    pub fn check_arity(&self, count: usize) -> Result<(), EvaluationError> {
+        if let Some(missing) = self
+            .parameters
+            .iter()
+            .skip(count)
+            .find(|parameter| parameter.default.is_none())
+        {
+            return Err(EvaluationError::new(&format!(
+                "Missing argument '{}' for {}",
+                missing.name, self.name
+            )));
+        }
+        if count > self.parameters.len() && !self.variadic {
+            return Err(EvaluationError::new(&format!(
+                "{} expects at most {} arguments, got {}",
+                self.name,
+                self.parameters.len(),
+                count
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the arguments against the parameters and fills in the defaults of omitted ones
+    pub fn bind(
+        &self,
+        mut args: Vec<EvalResultTypes>,
+    ) -> Result<Vec<EvalResultTypes>, EvaluationError> {
+        self.check_arity(args.len())?;
+        for (index, arg) in args.iter().enumerate() {
+            let Some(parameter) = self.parameters.get(index).or(self.parameters.last()) else {
+                break;
+            };
+            if !parameter.accepts(arg) {
+                return Err(InvalidParameterTypeError::new(&format!(
+                    "Argument '{}' of {} must be {}, got {}",
+                    parameter.name,
+                    self.name,
+                    parameter.expected(),
+                    arg.type_name()
+                ))
+                .into());
+            }
+        }
+        let mut defaults: Vec<EvalResultTypes> = self
+            .parameters
+            .iter()
+            .skip(args.len())
+            .filter_map(|parameter| parameter.default.clone())
+            .collect();
+        if self.variadic && args.len() < self.parameters.len() {
+            defaults.pop();
+        }
+        args.extend(defaults);
+        Ok(args)
+    }
+}
+
+/// Written as in documentation, e.g. `round(value: number | money, places: number = 0)`
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parameters: Vec<String> = self.parameters.iter().map(ToString::to_string).collect();
+        if let (true, Some(last)) = (self.variadic, self.parameters.last()) {
+            let repeated = Parameter {
+                default: None,
+                ..last.clone()
+            };
+            parameters.pop();
+            parameters.push(match last.default {
+                Some(_) => format!("[{}...]", repeated),
+                None => format!("{}...", repeated),
+            });
+        }
+        write!(f, "{}({})", self.name, parameters.join(", "))
+    }
+}
+
+/// How a builtin is referenced within a rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuiltinKind {
+    /// A value of the engine, e.g. `$now`
+    Symbol,
+    /// Can be read like an attribute of its receiver, e.g. `name.as_lower`
+    Property,
+    /// Called with arguments, e.g. `$min(a, b)` or `created_at.to_tz("UTC")`
+    Function,
+}
+impl BuiltinKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinKind::Symbol => "symbol",
+            BuiltinKind::Property => "property",
+            BuiltinKind::Function => "function",
+        }
+    }
+}
+
+pub type BuiltinFunction = Arc<dyn Fn(&Context, Vec<EvalResultTypes>) -> EvalResult + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Builtin {
+    /// The namespace the builtin was registered in, e.g. `math` for `math.round`
+    pub namespace: Option<String>,
+    pub signature: Signature,
+    pub kind: BuiltinKind,
+    pub doc: String,
+    function: BuiltinFunction,
+}
+impl Builtin {
+    /// A builtin named with an optional namespace, e.g. `math.round`
+    pub fn function(
+        name: &str,
+        parameters: Vec<Parameter>,
+        function: impl Fn(&Context, Vec<EvalResultTypes>) -> EvalResult + Send + Sync + 'static,
+    ) -> Self {
+        let (namespace, name) = match name.rsplit_once('.') {
+            Some((namespace, name)) => (Some(namespace.to_string()), name),
+            None => (None, name),
+        };
+        Builtin {
+            namespace,
+            signature: Signature {
+                name: name.to_string(),
+                parameters,
+                variadic: false,
+            },
+            kind: BuiltinKind::Function,
+            doc: String::new(),
+            function: Arc::new(function),
+        }
+    }
+
+    pub fn property(
+        name: &str,
+        types: &[ValueType],
+        function: fn(EvalResultTypes) -> EvalResult,
+    ) -> Self {
+        let parameters = vec![Parameter::required("value", types)];
+        Builtin {
+            kind: BuiltinKind::Property,
+            ..Builtin::function(name, parameters, move |_, mut args| match args.pop() {
+                Some(value) => function(value),
+                None => Err(EvaluationError::new("Expected a value")),
+            })
+        }
+    }
+
+    pub fn symbol(name: &str, function: fn(&Context) -> EvalResult) -> Self {
+        Builtin {
+            kind: BuiltinKind::Symbol,
+            ..Builtin::function(name, vec![], move |ctx, _| function(ctx))
+        }
+    }
+
+    pub fn with_doc(self, doc: &str) -> Self {
+        Builtin {
+            doc: doc.to_string(),
+            ..self
+        }
+    }
+
+    /// Lets a function also be read like an attribute, e.g. `prices.sum`, which passes the defaults
+    /// of any parameters after the receiver
+    pub fn readable_as_attribute(self) -> Self {
+        Builtin {
+            kind: BuiltinKind::Property,
+            ..self
+        }
+    }
+
+    /// Lets the last parameter take any number of arguments
+    pub fn variadic(mut self) -> Self {
+        self.signature.variadic = true;
+        self
+    }
+
+    /// Wraps a Python callable. Its positional parameters become the builtin's parameters, keeping
+    /// their defaults, and `*args` takes any number of further arguments. Types are written as in
+    /// `is` tests, with `|` between alternatives, e.g. `"number | null"`. Results of a pure
+    /// function are cached by argument, except for calls passed lists or sets.
+    pub fn from_python(
+        py: Python,
+        name: &str,
+        function: PyObject,
+        arg_types: Option<Vec<Option<String>>>,
+        return_type: Option<String>,
+        pure: bool,
+    ) -> PyResult<Self> {
+        let inspect = py.import("inspect")?;
+        let empty = inspect.getattr("Parameter")?.getattr("empty")?;
+        let signature = inspect.call_method1("signature", (function.as_ref(py),))?;
+        let mut parameters = Vec::new();
+        let mut variadic = false;
+        for parameter in signature
+            .getattr("parameters")?
+            .call_method0("values")?
+            .iter()?
+        {
+            let parameter = parameter?;
+            let parameter_name: String = parameter.getattr("name")?.extract()?;
+            let default = parameter.getattr("default")?;
+            let kind: String = parameter.getattr("kind")?.getattr("name")?.extract()?;
+            match kind.as_str() {
+                "POSITIONAL_ONLY" | "POSITIONAL_OR_KEYWORD" if default.is(empty) => {
+                    parameters.push(Parameter::required(&parameter_name, &[]))
+                }
+                "POSITIONAL_ONLY" | "POSITIONAL_OR_KEYWORD" => parameters.push(
+                    Parameter::optional(&parameter_name, &[], try_into_eval_result_types(default)?),
+                ),
+                "VAR_POSITIONAL" => {
+                    parameters.push(Parameter::optional(
+                        &parameter_name,
+                        &[],
+                        EvalResultTypes::Null,
+                    ));
+                    variadic = true;
+                }
+                "KEYWORD_ONLY" if default.is(empty) => {
+                    return Err(ContextError::new(&format!(
+                        "Parameter {} of {} is keyword-only and has no default",
+                        parameter_name, name
+                    ))
+                    .into())
+                }
+                // Rules only pass arguments by position, so the rest are left to their defaults
+                _ => {}
+            }
+        }
+        if let Some(arg_types) = arg_types {
+            if arg_types.len() != parameters.len() {
+                return Err(ContextError::new(&format!(
+                    "{} has {} parameters but {} argument types were given",
+                    name,
+                    parameters.len(),
+                    arg_types.len()
+                ))
+                .into());
+            }
+            for (parameter, types) in parameters.iter_mut().zip(arg_types) {
+                if let Some(types) = types {
+                    parameter.types = parse_types(&types)?;
+                }
+            }
+        }
+        let return_types = return_type.map(|types| parse_types(&types)).transpose()?;
+        let doc: Option<String> = inspect
+            .call_method1("getdoc", (function.as_ref(py),))?
+            .extract()?;
+        let qualified_name = name.to_string();
+        let cache = Mutex::new(HashMap::new());
+        let call = move |_: &Context, args: Vec<EvalResultTypes>| {
+            // Values such as `1` and `1.0` are equal as set elements, but the function may still
+            // treat them differently, so the type is part of the key
+            let key: Option<Vec<(Discriminant<EvalResultTypes>, SetKey)>> = match pure {
+                true => args
+                    .iter()
+                    .map(|arg| Some((discriminant(arg), SetKey::of(arg).ok()?)))
+                    .collect(),
+                false => None,
+            };
+            if let Some(result) = key.as_ref().and_then(|key| {
+                let cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+                cache.get(key).cloned()
+            }) {
+                return Ok(result);
+            }
+            let result = call_python(&function, &qualified_name, args, return_types.as_deref())?;
+            if let Some(key) = key {
+                let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+                if cache.len() >= MAX_CACHED_RESULTS {
+                    cache.clear();
+                }
+                cache.insert(key, result.clone());
+            }
+            Ok(result)
+        };
+        let builtin = Builtin::function(name, parameters, call).with_doc(&doc.unwrap_or_default());
+        Ok(match variadic {
+            true => builtin.variadic(),
+            false => builtin,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.signature.name
+    }
+
+    pub fn qualified_name(&self) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}.{}", namespace, self.signature.name),
+            None => self.signature.name.clone(),
+        }
+    }
+
+    pub fn call(&self, ctx: &Context, args: Vec<EvalResultTypes>) -> EvalResult {
+        (self.function)(ctx, self.signature.bind(args)?)
+    }
+}
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("namespace", &self.namespace)
+            .field("signature", &self.signature)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+/// Parses types written as in `is` tests, with `|` between alternatives, e.g. `"number | null"`
+fn parse_types(text: &str) -> Result<Vec<ValueType>, ContextError> {
+    text.split('|')
+        .map(|name| {
+            ValueType::from_str(name.trim()).map_err(|err| ContextError::new(&err.to_string()))
+        })
+        .collect()
+}
+
+/// Calls a Python function with arguments converted to Python values, converting its result back
+fn call_python(
+    function: &PyObject,
+    name: &str,
+    args: Vec<EvalResultTypes>,
+    return_types: Option<&[ValueType]>,
+) -> EvalResult {
+    Python::with_gil(|py| {
+        let args = PyTuple::new(py, args.into_iter().map(|arg| arg.into_py(py)));
+        let result = function
+            .call1(py, args)
+            .map_err(|err| EvaluationError::with_cause(&format!("{} raised {}", name, err), err))?;
+        let result = try_into_eval_result_types(result.as_ref(py)).map_err(|err| {
+            EvaluationError::new(&format!("{} returned an unsupported value: {}", name, err))
+        })?;
+        match return_types {
+            Some(types) if !types.iter().any(|value_type| value_type.matches(&result)) => {
+                let expected: Vec<&str> =
+                    types.iter().map(|value_type| value_type.name()).collect();
+                Err(EvaluationError::new(&format!(
+                    "{} returned {}, expected {}",
+                    name,
+                    result.type_name(),
+                    expected.join(" or ")
+                )))
+            }
+            _ => Ok(result),
+        }
+    })
+}
+
+/// The builtins a rule can reference. Builtins are found by their qualified name, e.g.
+/// `$math.round(x)`, or by their name alone, e.g. `x.round()`. No two builtins may share a name
+/// alone, so a name always refers to the same builtin whichever namespace it was registered in.
+#[derive(Clone, Debug, Default)]
+pub struct BuiltinRegistry {
+    /// Keyed by qualified name, in registration order
+    builtins: IndexMap<String, Builtin>,
+    /// The qualified name each unqualified name refers to
+    unqualified: HashMap<String, String>,
+    namespaces: HashSet<String>,
+}
+impl BuiltinRegistry {
+    pub fn new() -> Self {
+        BuiltinRegistry::default()
+    }
+
+    /// Adds a builtin, replacing any registered under the same qualified name. Fails when a
+    /// builtin in another namespace has the same name, e.g. `crm.score` after `risk.score`, since
+    /// `score(x)` could then mean either.
+    pub fn register(&mut self, builtin: Builtin) -> Result<(), ContextError> {
+        let qualified_name = builtin.qualified_name();
+        match self.unqualified.get(builtin.name()) {
+            Some(existing) if *existing != qualified_name => {
+                return Err(ContextError::new(&format!(
+                    "Cannot register {}, its name is already taken by {}",
+                    qualified_name, existing
+                )));
+            }
+            Some(_) => {}
+            None => {
+                self.unqualified
+                    .insert(builtin.name().to_string(), qualified_name.clone());
+            }
+        }
+        if let Some(namespace) = &builtin.namespace {
+            self.namespaces.insert(namespace.clone());
+        }
+        self.builtins.insert(qualified_name, builtin);
+        Ok(())
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<&Builtin> {
+        self.builtins.get(name).or_else(|| {
+            self.unqualified
+                .get(name)
+                .and_then(|qualified_name| self.builtins.get(qualified_name))
+        })
+    }
+
+    /// A builtin referenced like a value with `$`
+    pub fn symbol(&self, name: &str) -> Option<&Builtin> {
+        self.resolve(name)
+            .filter(|builtin| builtin.kind == BuiltinKind::Symbol)
+    }
+
+    /// A builtin read like an attribute, e.g. `name.as_lower`
+    pub fn property(&self, name: &str) -> Option<&Builtin> {
+        self.resolve(name)
+            .filter(|builtin| builtin.kind == BuiltinKind::Property)
+    }
+
+    /// A builtin that can be called with arguments
+    pub fn callable(&self, name: &str) -> Option<&Builtin> {
+        self.resolve(name)
+            .filter(|builtin| builtin.kind != BuiltinKind::Symbol)
+    }
+
+    pub fn is_namespace(&self, name: &str) -> bool {
+        self.namespaces.contains(name)
+    }
+
+    /// Every builtin in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &Builtin> {
+        self.builtins.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(_ctx: &Context, mut args: Vec<EvalResultTypes>) -> EvalResult {
+        Ok(args.swap_remove(0))
+    }
+
+    #[test]
+    fn test_signature() {
+        let builtin = Builtin::function(
+            "math.round",
+            vec![
+                Parameter::required("value", &[ValueType::Number, ValueType::Money]),
+                Parameter::optional(
+                    "mode",
+                    &[ValueType::String],
+                    EvalResultTypes::String("half_even".into()),
+                ),
+            ],
+            first,
+        );
+        assert_eq!(
+            builtin.signature.to_string(),
+            "round(value: number | money, mode: string = \"half_even\")"
+        );
+        assert_eq!(
+            builtin
+                .signature
+                .bind(vec![EvalResultTypes::Integer(1)])
+                .unwrap(),
+            vec![
+                EvalResultTypes::Integer(1),
+                EvalResultTypes::String("half_even".into())
+            ]
+        );
+        assert_eq!(
+            builtin.signature.check_arity(0).unwrap_err().to_string(),
+            "Missing argument 'value' for round"
+        );
+        assert_eq!(
+            builtin
+                .signature
+                .bind(vec![EvalResultTypes::String("1".into())])
+                .unwrap_err()
+                .to_string(),
+            "Argument 'value' of round must be number or money, got string"
+        );
+    }
+
+    #[test]
+    fn test_resolution() {
+        let mut registry = BuiltinRegistry::new();
+        registry
+            .register(Builtin::function("math.first", vec![], first).with_doc("Math"))
+            .unwrap();
+        registry
+            .register(Builtin::function("last", vec![], first))
+            .unwrap();
+        assert_eq!(registry.resolve("first").unwrap().doc, "Math");
+        assert_eq!(registry.resolve("math.first").unwrap().doc, "Math");
+        assert!(registry.is_namespace("math"));
+        assert!(registry.symbol("first").is_none());
+        // Registering a qualified name again replaces it in place
+        registry
+            .register(Builtin::function("math.first", vec![], first).with_doc("New"))
+            .unwrap();
+        assert_eq!(registry.resolve("first").unwrap().doc, "New");
+        assert_eq!(registry.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_registering_a_taken_name_fails() {
+        let mut registry = BuiltinRegistry::new();
+        registry
+            .register(Builtin::function("risk.score", vec![], first).with_doc("Risk"))
+            .unwrap();
+        for name in ["crm.score", "score"] {
+            assert_eq!(
+                registry
+                    .register(Builtin::function(name, vec![], first))
+                    .unwrap_err()
+                    .to_string(),
+                format!(
+                    "Cannot register {}, its name is already taken by risk.score",
+                    name
+                )
+            );
+        }
+        assert_eq!(registry.resolve("score").unwrap().doc, "Risk");
+        assert!(registry.resolve("crm.score").is_none());
+        assert_eq!(registry.iter().count(), 1);
+    }
+}
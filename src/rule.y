@@ -1,55 +1,116 @@
-%start Expr
+%start Program
 %expect-unused Unmatched "UNMATCHED"
 %%
+Program -> Result<Program, ()>:
+      Statements Expr {
+        let mut statements = $1?;
+        statements.push(Statement::Expression($2?));
+        Ok(Program::new(statements))
+      }
+;
+
+Statements -> Result<Vec<Statement>, ()>:
+      /* No leading statements */
+      { Ok(Vec::new()) }
+    | Statements Statement 'SEMICOLON' {
+        let mut statements = $1?;
+        statements.push($2?);
+        Ok(statements)
+      }
+;
+
+Statement -> Result<Statement, ()>:
+      'LET' 'SYMBOL' 'ASSIGN' Expr {
+        let name = $lexer.span_str($2.map_err(|_| ())?.span()).to_string();
+        Ok(Statement::Let(name, $4?))
+      }
+    | 'FN' 'SYMBOL' 'LPAREN' Parameters 'RPAREN' 'ASSIGN' Expr {
+        let name = $lexer.span_str($2.map_err(|_| ())?.span()).to_string();
+        Ok(Statement::Function(Arc::new(FunctionDefinition::new(name, $4?, $7?))))
+      }
+;
+
+Parameters -> Result<Vec<String>, ()>:
+    /* No parameters */
+    { Ok(Vec::new()) }
+    | NonEmptyParameters { $1 }
+;
+
+NonEmptyParameters -> Result<Vec<String>, ()>:
+    'SYMBOL' { Ok(vec![$lexer.span_str($1.map_err(|_| ())?.span()).to_string()]) }
+    | NonEmptyParameters 'COMMA' 'SYMBOL' {
+        let mut params = $1?;
+        params.push($lexer.span_str($3.map_err(|_| ())?.span()).to_string());
+        Ok(params)
+    }
+;
+
 Expr -> Result<Expression, ()>:
       Logical { Ok(Expression::Logical($1?)) }
 ;
 
 Logical -> Result<LogicalExpression, ()>:
-      Equality 'AND' Equality {
+      Comparison 'AND' Comparison {
         Ok(LogicalExpression::And(Box::new($1?), Box::new($3?)) )
       }
-    | Equality 'OR' Equality {
+    | Comparison 'OR' Comparison {
         Ok(LogicalExpression::Or(Box::new($1?), Box::new($3?)) )
       }
-    | Equality { Ok(LogicalExpression::Equality($1?)) }
-;
-
-Equality -> Result<EqualityExpression, ()>:
-      Comparison 'EQ' Comparison {
-        Ok(EqualityExpression::Equal(Box::new($1?), Box::new($3?)) )
-      }
-    | Comparison 'NEQ' Comparison { 
-        Ok(EqualityExpression::NotEqual(Box::new($1?), Box::new($3?)) )
-      }
-    | Comparison { Ok(EqualityExpression::Comparison($1?)) }
+    | Comparison { Ok(LogicalExpression::Comparison($1?)) }
 ;
 
 Comparison -> Result<ComparisonExpression, ()>:
-       Additive 'LT' Additive {
-        Ok(ComparisonExpression::LessThan(Box::new($1?), Box::new($3?)) )
-       }
-     | Additive 'GT' Additive {
-        Ok(ComparisonExpression::GreaterThan(Box::new($1?), Box::new($3?)) )
+       Additive ComparisonChain {
+        Ok(ComparisonExpression::Chain(Box::new($1?), $2?))
        }
-     | Additive 'LTE' Additive {
-        Ok(ComparisonExpression::LessThanOrEqual(Box::new($1?), Box::new($3?)) )
+     | Additive 'IS' TypeName {
+        Ok(ComparisonExpression::Is(Box::new($1?), $3?, false))
        }
-     | Additive 'GTE' Additive {
-        Ok(ComparisonExpression::GreaterThanOrEqual(Box::new($1?), Box::new($3?)) )
+     | Additive 'IS' 'NOT' TypeName {
+        Ok(ComparisonExpression::Is(Box::new($1?), $4?, true))
        }
      | Additive { Ok(ComparisonExpression::Additive($1?)) }
 ;
 
+TypeName -> Result<ValueType, ()>:
+      'SYMBOL' { $lexer.span_str($span).parse::<ValueType>().map_err(|_| ()) }
+    | 'NULL' { Ok(ValueType::Null) }
+;
+
+ComparisonChain -> Result<Vec<(ComparisonOperator, AdditiveExpression)>, ()>:
+      ComparisonOperator Additive { Ok(vec![($1?, $2?)]) }
+    | ComparisonChain ComparisonOperator Additive {
+        let mut chain = $1?;
+        chain.push(($2?, $3?));
+        Ok(chain)
+      }
+;
+
+ComparisonOperator -> Result<ComparisonOperator, ()>:
+      'EQ' { Ok(ComparisonOperator::Equal) }
+    | 'NEQ' { Ok(ComparisonOperator::NotEqual) }
+    | 'LT' { Ok(ComparisonOperator::LessThan) }
+    | 'GT' { Ok(ComparisonOperator::GreaterThan) }
+    | 'LTE' { Ok(ComparisonOperator::LessThanOrEqual) }
+    | 'GTE' { Ok(ComparisonOperator::GreaterThanOrEqual) }
+    | 'APPROX' { Ok(ComparisonOperator::ApproximatelyEqual) }
+    | 'IN' { Ok(ComparisonOperator::In) }
+    | 'NOT' 'IN' { Ok(ComparisonOperator::NotIn) }
+;
+
 Additive -> Result<AdditiveExpression, ()>:
     Factor 'ADD' Factor { Ok(AdditiveExpression::Add($1?, $3?)) }
     | Factor 'SUB' Factor { Ok(AdditiveExpression::Subtract($1?, $3?)) }
+    | Factor 'PIPE' Factor { Ok(AdditiveExpression::Union($1?, $3?)) }
+    | Factor 'AMP' Factor { Ok(AdditiveExpression::Intersection($1?, $3?)) }
+    | Factor 'CARET' Factor { Ok(AdditiveExpression::SymmetricDifference($1?, $3?)) }
     | Factor { Ok(AdditiveExpression::Factor($1?)) }
 ;
 
 Factor -> Result<FactorExpression, ()>:
     Unary 'MUL' Unary { Ok(FactorExpression::Multiply($1?, $3?)) }
     | Unary 'DIV' Unary { Ok(FactorExpression::Divide($1?, $3?)) }
+    | Unary 'FLOORDIV' Unary { Ok(FactorExpression::FloorDivide($1?, $3?)) }
     | Unary 'MOD' Unary { Ok(FactorExpression::Modulo($1?, $3?)) }
     | Unary { Ok(FactorExpression::Unary($1?)) }
 ;
@@ -61,21 +122,89 @@ Unary -> Result<UnaryExpression, ()>:
 ;
 
 Primary -> Result<PrimaryExpression, ()>:
-    'FLOAT' { Ok(PrimaryExpression::Float(
-        $lexer.span_str($span).parse::<f64>().unwrap()
-    ))}
+    'INTEGER' { Ok(PrimaryExpression::Integer(parse_integer($lexer.span_str($span))?)) }
+    | 'FLOAT' {
+        let text = $lexer.span_str($span);
+        let decimal = Decimal::from_str(text).or_else(|_| Decimal::from_scientific(text)).ok();
+        Ok(PrimaryExpression::Float(text.parse::<f64>().map_err(|_| ())?, decimal))
+    }
+    | 'DECIMAL' {
+        let text = $lexer.span_str($span);
+        let digits = text.strip_suffix('m').ok_or(())?;
+        Ok(PrimaryExpression::Decimal(Decimal::from_str(digits).map_err(|_| ())?))
+    }
+    | 'NULL' { Ok(PrimaryExpression::Null) }
     | 'TRUE' { Ok(PrimaryExpression::True) }
     | 'FALSE' { Ok(PrimaryExpression::False) }
     | 'SYMBOL'  { Ok(PrimaryExpression::Symbol($lexer.span_str($span).to_string())) }
+    | 'QUOTED_SYMBOL' {
+        let text = $lexer.span_str($span);
+        let name = text.get(1..text.len().saturating_sub(1)).ok_or(())?;
+        Ok(PrimaryExpression::Symbol(name.to_string()))
+    }
+    | 'SYMBOL' 'LPAREN' ExprList 'RPAREN' {
+        let name = $lexer.span_str($1.map_err(|_| ())?.span()).to_string();
+        Ok(PrimaryExpression::Call(Box::new(FunctionCall::new(name, $3?))))
+    }
     | 'ATTRIBUTE'  { Ok(PrimaryExpression::Attribute($lexer.span_str($span).to_string())) }
+    | 'ATTRIBUTE' 'LPAREN' ExprList 'RPAREN' {
+        let raw_attr = $lexer.span_str($1.map_err(|_| ())?.span());
+        Ok(PrimaryExpression::MethodCall(Box::new(MethodCall::from_attribute(raw_attr, $3?))))
+    }
+    | 'BUILTIN' {
+        // Error recovery may insert a BUILTIN with an empty span, which has no sigil to strip
+        let name = $lexer.span_str($span).get(1..).ok_or(())?;
+        Ok(PrimaryExpression::Builtin(name.to_string()))
+    }
+    | 'BUILTIN' 'LPAREN' ExprList 'RPAREN' {
+        let name = $lexer.span_str($1.map_err(|_| ())?.span()).get(1..).ok_or(())?;
+        Ok(PrimaryExpression::BuiltinCall(Box::new(FunctionCall::new(name.to_string(), $3?))))
+    }
     | 'STRING_DOUBLE'  { Ok(PrimaryExpression::String(
         $lexer.span_str($span).to_string().trim_matches('"').to_string()
     ))}
     | 'STRING_SINGLE'  { Ok(PrimaryExpression::String(
         $lexer.span_str($span).to_string().trim_matches('"').to_string()
     ))}
+    | 'DATETIME' {
+        let text = prefixed_literal_body($lexer.span_str($span))?;
+        Ok(PrimaryExpression::Datetime(parse_datetime(text).map_err(|_| ())?))
+    }
+    | 'MONEY' {
+        let text = prefixed_literal_body($lexer.span_str($span))?;
+        Ok(PrimaryExpression::Money(text.parse::<Money>().map_err(|_| ())?))
+    }
+    | 'BYTES' {
+        let text = prefixed_literal_body($lexer.span_str($span))?;
+        Ok(PrimaryExpression::Bytes(parse_bytes(text).map_err(|_| ())?))
+    }
+    | 'HEX' {
+        let text = prefixed_literal_body($lexer.span_str($span))?;
+        Ok(PrimaryExpression::Bytes(parse_hex(text).map_err(|_| ())?))
+    }
+    | 'TIMEDELTA' {
+        let text = prefixed_literal_body($lexer.span_str($span))?;
+        Ok(PrimaryExpression::Timedelta(parse_timedelta(text).map_err(|_| ())?))
+    }
+    | Primary 'MEMBER' {
+        let name = member_name($lexer.span_str($2.map_err(|_| ())?.span()))?;
+        Ok(PrimaryExpression::Member(Box::new($1?), name))
+    }
+    | Primary 'MEMBER' 'LPAREN' ExprList 'RPAREN' {
+        let name = member_name($lexer.span_str($2.map_err(|_| ())?.span()))?;
+        Ok(PrimaryExpression::MethodCall(Box::new(MethodCall::new($1?, name, $4?))))
+    }
+    | Primary 'DOT' 'SYMBOL' {
+        let name = $lexer.span_str($3.map_err(|_| ())?.span()).to_string();
+        Ok(PrimaryExpression::Member(Box::new($1?), name))
+    }
+    | Primary 'DOT' 'SYMBOL' 'LPAREN' ExprList 'RPAREN' {
+        let name = $lexer.span_str($3.map_err(|_| ())?.span()).to_string();
+        Ok(PrimaryExpression::MethodCall(Box::new(MethodCall::new($1?, name, $5?))))
+    }
     | 'LPAREN' Expr 'RPAREN' { Ok(PrimaryExpression::Grouping(Box::new($2?))) }
     | 'LBRACKET' ExprList 'RBRACKET' { Ok(PrimaryExpression::List($2?)) }
+    | 'LBRACE' NonEmptyExprList 'RBRACE' { Ok(PrimaryExpression::Set($2?)) }
 ;
 
 ExprList -> Result<Vec<Expression>, ()>:
@@ -98,4 +227,32 @@ Unmatched -> ():
     ; 
 %%
 
-use crate::ast::*;
\ No newline at end of file
+use crate::ast::*;
+use crate::utils::{parse_bytes, parse_datetime, parse_hex, parse_timedelta};
+use crate::money::Money;
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Strips the dot from a member such as `.days`, which is lexed as one token so that keywords can
+/// be members and members can be chained
+fn member_name(text: &str) -> Result<String, ()> {
+    text.strip_prefix('.').map(str::to_string).ok_or(())
+}
+
+/// Parses a whole-number literal, which may be binary, octal or hexadecimal, e.g. `0b101`
+fn parse_integer(text: &str) -> Result<BigInt, ()> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0b") => (&text[2..], 2),
+        Some("0o") => (&text[2..], 8),
+        Some("0x") => (&text[2..], 16),
+        _ => (text, 10),
+    };
+    BigInt::parse_bytes(digits.as_bytes(), radix).ok_or(())
+}
+
+/// Strips the prefix and quotes from literals such as `d"2024-01-01"`
+fn prefixed_literal_body(text: &str) -> Result<&str, ()> {
+    text.get(2..).and_then(|body| body.strip_suffix('"')).ok_or(())
+}
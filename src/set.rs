@@ -0,0 +1,210 @@
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use indexmap::IndexMap;
+use ipnet::IpNet;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use std::net::IpAddr;
+
+use crate::ast::{float_to_decimal, EvalResultTypes};
+use crate::errors::EvaluationError;
+
+/// The hashable identity of a set element. Values that compare equal share a key, so numbers are
+/// keyed by value regardless of type and `{1, 1.0}` holds a single element, like in Python.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SetKey {
+    Null,
+    Boolean(bool),
+    /// Any number holding a whole value
+    Integer(BigInt),
+    /// Any other number with a decimal equivalent, normalized so `0.50m` and `0.5` match
+    Fraction(Decimal),
+    /// Floats without a decimal equivalent, keyed by their bits
+    Float(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    Datetime(DateTime<FixedOffset>),
+    Timedelta(TimeDelta),
+    Money(Decimal, String),
+    IpAddress(IpAddr),
+    Network(IpNet),
+}
+impl SetKey {
+    pub fn of(value: &EvalResultTypes) -> Result<Self, EvaluationError> {
+        Ok(match value {
+            EvalResultTypes::Null => SetKey::Null,
+            EvalResultTypes::Boolean(value) => SetKey::Boolean(*value),
+            EvalResultTypes::Integer(value) => SetKey::Integer(BigInt::from(*value)),
+            EvalResultTypes::BigInt(value) => SetKey::Integer(value.clone()),
+            EvalResultTypes::Float(value) => match BigInt::from_f64(*value) {
+                Some(whole) if value.fract() == 0.0 => SetKey::Integer(whole),
+                _ => match float_to_decimal(*value) {
+                    Ok(decimal) => SetKey::Fraction(decimal.normalize()),
+                    Err(_) => SetKey::Float(value.to_bits()),
+                },
+            },
+            EvalResultTypes::Decimal(value) if value.fract().is_zero() => {
+                // A whole decimal always fits, its mantissa is only 96 bits
+                SetKey::Integer(BigInt::from(value.to_i128().unwrap_or_default()))
+            }
+            EvalResultTypes::Decimal(value) => SetKey::Fraction(value.normalize()),
+            EvalResultTypes::String(value) => SetKey::String(value.clone()),
+            EvalResultTypes::Bytes(value) => SetKey::Bytes(value.clone()),
+            EvalResultTypes::Datetime(value) => SetKey::Datetime(*value),
+            EvalResultTypes::Timedelta(value) => SetKey::Timedelta(*value),
+            EvalResultTypes::Money(value) => {
+                SetKey::Money(value.amount.normalize(), value.currency.clone())
+            }
+            EvalResultTypes::IpAddress(value) => SetKey::IpAddress(*value),
+            EvalResultTypes::Network(value) => SetKey::Network(*value),
+            EvalResultTypes::List(_) | EvalResultTypes::Set(_) | EvalResultTypes::Mapping(_) => {
+                return Err(EvaluationError::new(&format!(
+                    "Cannot add a {} to a set",
+                    value.type_name()
+                )))
+            }
+        })
+    }
+}
+
+/// An unordered collection of distinct values. Elements are kept in insertion order so that
+/// results are deterministic.
+#[derive(Clone, Debug, Default)]
+pub struct ValueSet {
+    items: IndexMap<SetKey, EvalResultTypes>,
+}
+impl ValueSet {
+    pub fn new() -> Self {
+        ValueSet::default()
+    }
+
+    /// Adds a value unless an equal one is already present. Lists and sets can't be elements.
+    pub fn insert(&mut self, value: EvalResultTypes) -> Result<(), EvaluationError> {
+        self.items.entry(SetKey::of(&value)?).or_insert(value);
+        Ok(())
+    }
+
+    pub fn from_values(
+        values: impl IntoIterator<Item = EvalResultTypes>,
+    ) -> Result<Self, EvaluationError> {
+        let mut set = ValueSet::new();
+        for value in values {
+            set.insert(value)?;
+        }
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EvalResultTypes> {
+        self.items.values()
+    }
+
+    /// Values that can't be elements are never contained
+    pub fn contains(&self, value: &EvalResultTypes) -> bool {
+        SetKey::of(value).is_ok_and(|key| self.items.contains_key(&key))
+    }
+
+    fn filtered(&self, keep: impl Fn(&SetKey) -> bool) -> ValueSet {
+        let items = self
+            .items
+            .iter()
+            .filter(|(key, _)| keep(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        ValueSet { items }
+    }
+
+    pub fn union(&self, other: &ValueSet) -> ValueSet {
+        let mut union = self.clone();
+        for (key, value) in &other.items {
+            union
+                .items
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        union
+    }
+
+    pub fn intersection(&self, other: &ValueSet) -> ValueSet {
+        self.filtered(|key| other.items.contains_key(key))
+    }
+
+    pub fn difference(&self, other: &ValueSet) -> ValueSet {
+        self.filtered(|key| !other.items.contains_key(key))
+    }
+
+    pub fn symmetric_difference(&self, other: &ValueSet) -> ValueSet {
+        self.difference(other).union(&other.difference(self))
+    }
+
+    pub fn is_subset(&self, other: &ValueSet) -> bool {
+        self.items.keys().all(|key| other.items.contains_key(key))
+    }
+
+    pub fn is_superset(&self, other: &ValueSet) -> bool {
+        other.is_subset(self)
+    }
+
+    pub fn is_disjoint(&self, other: &ValueSet) -> bool {
+        !self.items.keys().any(|key| other.items.contains_key(key))
+    }
+}
+impl PartialEq for ValueSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(values: Vec<EvalResultTypes>) -> ValueSet {
+        ValueSet::from_values(values).unwrap()
+    }
+
+    #[test]
+    fn test_equal_numbers_share_an_element() {
+        let values = set(vec![
+            EvalResultTypes::Integer(1),
+            EvalResultTypes::Float(1.0),
+            EvalResultTypes::Decimal(Decimal::new(100, 2)),
+            EvalResultTypes::Float(0.5),
+            EvalResultTypes::Decimal(Decimal::new(50, 2)),
+            EvalResultTypes::Boolean(true),
+        ]);
+        assert_eq!(values.len(), 3);
+        // The first of the equal values is the one kept
+        assert_eq!(values.iter().next(), Some(&EvalResultTypes::Integer(1)));
+        assert!(values.contains(&EvalResultTypes::BigInt(BigInt::from(1))));
+        assert!(!values.contains(&EvalResultTypes::List(vec![])));
+        assert!(ValueSet::from_values(vec![EvalResultTypes::List(vec![])]).is_err());
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let numbers = |values: &[i64]| {
+            set(values
+                .iter()
+                .map(|value| EvalResultTypes::Integer(*value))
+                .collect())
+        };
+        let lhs = numbers(&[1, 2, 3]);
+        let rhs = numbers(&[3, 4]);
+        assert_eq!(lhs.union(&rhs), numbers(&[1, 2, 3, 4]));
+        assert_eq!(lhs.intersection(&rhs), numbers(&[3]));
+        assert_eq!(lhs.difference(&rhs), numbers(&[1, 2]));
+        assert_eq!(lhs.symmetric_difference(&rhs), numbers(&[4, 2, 1]));
+        assert!(numbers(&[1, 2]).is_subset(&lhs));
+        assert!(lhs.is_superset(&numbers(&[2])));
+        assert!(!lhs.is_disjoint(&rhs));
+        assert!(numbers(&[1]).is_disjoint(&rhs));
+    }
+}
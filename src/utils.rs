@@ -1,7 +1,17 @@
-use pyo3::types::{PyAny, PyBool, PyDict, PyFloat, PyInt, PyString};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use num_bigint::BigInt;
+use pyo3::types::{
+    PyAny, PyBool, PyByteArray, PyBytes, PyDate, PyDateAccess, PyDateTime, PyDelta, PyDict,
+    PyFloat, PyFrozenSet, PyInt, PyList, PySet, PyString, PyTimeAccess, PyTuple,
+};
 use pyo3::PyResult;
+use rust_decimal::Decimal;
 
 use crate::ast::EvalResultTypes;
+use crate::errors::TypeConversionError;
+use crate::mapping::ValueMap;
+use crate::money::Money;
+use crate::set::ValueSet;
 
 /// Get a potentially nested value from a python dict.
 ///
@@ -29,20 +39,327 @@ pub fn get_value_from_py_dict(
     try_into_eval_result_types(current_value).map(Some)
 }
 
-fn try_into_eval_result_types(value: &PyAny) -> PyResult<EvalResultTypes> {
+pub fn try_into_eval_result_types(value: &PyAny) -> PyResult<EvalResultTypes> {
+    if value.is_none() {
+        return Ok(EvalResultTypes::Null);
+    }
     if let Ok(py_str) = value.extract::<&PyString>() {
         return Ok(EvalResultTypes::String(py_str.to_string()));
     }
+    if let Ok(py_bytes) = value.downcast::<PyBytes>() {
+        return Ok(EvalResultTypes::Bytes(py_bytes.as_bytes().to_vec()));
+    }
+    if let Ok(py_bytearray) = value.downcast::<PyByteArray>() {
+        return Ok(EvalResultTypes::Bytes(py_bytearray.to_vec()));
+    }
     // Boolean MUST be checked before int and float. Otherwise the bool will be casted to an int
     if let Ok(py_bool) = value.extract::<&PyBool>() {
         return Ok(EvalResultTypes::Boolean(py_bool.extract()?));
     }
     // Int MUST be checked before float. Otherwise the int will be casted to a float
     if let Ok(py_int) = value.extract::<&PyInt>() {
-        return Ok(EvalResultTypes::Float(py_int.extract()?));
+        if let Ok(value) = py_int.extract::<i64>() {
+            return Ok(EvalResultTypes::Integer(value));
+        }
+        return Ok(EvalResultTypes::BigInt(py_int.extract::<BigInt>()?));
     }
     if let Ok(py_float) = value.extract::<&PyFloat>() {
         return Ok(EvalResultTypes::Float(py_float.extract()?));
     }
+    let decimal_type = value.py().import("decimal")?.getattr("Decimal")?;
+    if value.is_instance(decimal_type)? {
+        // NaN and the infinities have no equivalent and fail to convert
+        return Ok(EvalResultTypes::Decimal(value.extract::<Decimal>()?));
+    }
+    // Datetime MUST be checked before date since it is a subclass of date
+    if let Ok(py_datetime) = value.extract::<&PyDateTime>() {
+        return Ok(EvalResultTypes::Datetime(py_datetime_to_datetime(
+            py_datetime,
+        )?));
+    }
+    if let Ok(py_date) = value.extract::<&PyDate>() {
+        let date = NaiveDate::from_ymd_opt(
+            py_date.get_year(),
+            py_date.get_month().into(),
+            py_date.get_day().into(),
+        )
+        .ok_or_else(|| pyo3::exceptions::PyValueError::new_err("Date out of range"))?;
+        return Ok(EvalResultTypes::Datetime(
+            date.and_time(NaiveTime::MIN).and_utc().fixed_offset(),
+        ));
+    }
+    if let Ok(py_delta) = value.extract::<&PyDelta>() {
+        return Ok(EvalResultTypes::Timedelta(py_delta.extract()?));
+    }
+    let ipaddress = value.py().import("ipaddress")?;
+    if value.is_instance(ipaddress.getattr("IPv4Address")?)?
+        || value.is_instance(ipaddress.getattr("IPv6Address")?)?
+    {
+        let address = value.str()?.to_str()?.parse().map_err(|err| {
+            pyo3::exceptions::PyValueError::new_err(format!("Invalid IP address: {}", err))
+        })?;
+        return Ok(EvalResultTypes::IpAddress(address));
+    }
+    if value.is_instance(ipaddress.getattr("IPv4Network")?)?
+        || value.is_instance(ipaddress.getattr("IPv6Network")?)?
+    {
+        let network = value.str()?.to_str()?.parse().map_err(|err| {
+            pyo3::exceptions::PyValueError::new_err(format!("Invalid network: {}", err))
+        })?;
+        return Ok(EvalResultTypes::Network(network));
+    }
+    if let Ok(py_list) = value.downcast::<PyList>() {
+        return py_list
+            .iter()
+            .map(try_into_eval_result_types)
+            .collect::<PyResult<_>>()
+            .map(EvalResultTypes::List);
+    }
+    if let Ok(py_tuple) = value.downcast::<PyTuple>() {
+        return py_tuple
+            .iter()
+            .map(try_into_eval_result_types)
+            .collect::<PyResult<_>>()
+            .map(EvalResultTypes::List);
+    }
+    if let Ok(py_set) = value.downcast::<PySet>() {
+        return py_set_to_eval_result_types(py_set.iter());
+    }
+    if let Ok(py_frozenset) = value.downcast::<PyFrozenSet>() {
+        return py_set_to_eval_result_types(py_frozenset.iter());
+    }
+    // Mappings shaped like `{"amount": ..., "currency": "USD"}` are money, as long as they hold a
+    // valid amount and currency code, otherwise they are left as plain mappings
+    if let Ok(py_dict) = value.downcast::<PyDict>() {
+        if let (2, Some(amount), Some(currency)) = (
+            py_dict.len(),
+            py_dict.get_item("amount"),
+            py_dict.get_item("currency"),
+        ) {
+            if let (Ok(amount), Ok(currency)) = (amount.extract(), currency.extract::<&str>()) {
+                if let Ok(money) = Money::new(amount, currency) {
+                    return Ok(EvalResultTypes::Money(money));
+                }
+            }
+        }
+        let mut mapping = ValueMap::new();
+        for (key, value) in py_dict.iter() {
+            mapping
+                .insert(
+                    try_into_eval_result_types(key)?,
+                    try_into_eval_result_types(value)?,
+                )
+                .map_err(|err| pyo3::exceptions::PyTypeError::new_err(err.to_string()))?;
+        }
+        return Ok(EvalResultTypes::Mapping(mapping));
+    }
     Err(pyo3::exceptions::PyTypeError::new_err("Unsupported type"))
 }
+
+fn py_set_to_eval_result_types<'py>(
+    items: impl Iterator<Item = &'py PyAny>,
+) -> PyResult<EvalResultTypes> {
+    let mut set = ValueSet::new();
+    for item in items {
+        set.insert(try_into_eval_result_types(item)?)
+            .map_err(|err| pyo3::exceptions::PyTypeError::new_err(err.to_string()))?;
+    }
+    Ok(EvalResultTypes::Set(set))
+}
+
+/// Converts a Python datetime, treating naive values as UTC. The offset is read through
+/// `utcoffset()` so that any tzinfo implementation (e.g. `zoneinfo`) is supported.
+pub fn py_datetime_to_datetime(value: &PyDateTime) -> PyResult<DateTime<FixedOffset>> {
+    let out_of_range = || pyo3::exceptions::PyValueError::new_err("Datetime out of range");
+    let naive = NaiveDate::from_ymd_opt(
+        value.get_year(),
+        value.get_month().into(),
+        value.get_day().into(),
+    )
+    .and_then(|date| {
+        date.and_hms_micro_opt(
+            value.get_hour().into(),
+            value.get_minute().into(),
+            value.get_second().into(),
+            value.get_microsecond(),
+        )
+    })
+    .ok_or_else(out_of_range)?;
+    let offset = match value
+        .call_method0("utcoffset")?
+        .extract::<Option<TimeDelta>>()?
+    {
+        Some(offset) => {
+            FixedOffset::east_opt(offset.num_seconds() as i32).ok_or_else(out_of_range)?
+        }
+        None => FixedOffset::east_opt(0).unwrap(),
+    };
+    naive
+        .and_local_timezone(offset)
+        .single()
+        .ok_or_else(out_of_range)
+}
+
+/// Parse an ISO-8601 datetime such as `2024-01-01T00:00:00Z`. Values without an offset are
+/// treated as UTC and a bare date (`2024-01-01`) refers to midnight.
+pub fn parse_datetime(text: &str) -> Result<DateTime<FixedOffset>, TypeConversionError> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Ok(datetime);
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Ok(datetime.and_utc().fixed_offset());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc().fixed_offset());
+    }
+    Err(TypeConversionError::new(&format!(
+        "Invalid datetime '{}'",
+        text
+    )))
+}
+
+/// Parse an ISO-8601 duration such as `P1DT2H` or `-PT30M`. Only weeks, days, hours, minutes and
+/// seconds are supported since years and months don't have a fixed length.
+pub fn parse_timedelta(text: &str) -> Result<TimeDelta, TypeConversionError> {
+    let invalid = || TypeConversionError::new(&format!("Invalid timedelta '{}'", text));
+    let (negative, body) = match text.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, text),
+    };
+    let body = body.strip_prefix('P').ok_or_else(invalid)?;
+    if body.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = TimeDelta::zero();
+    let mut in_time = false;
+    let mut number = String::new();
+    for c in body.chars() {
+        let seconds_per_unit = match (c, in_time) {
+            ('0'..='9' | '.', _) => {
+                number.push(c);
+                continue;
+            }
+            ('T', false) if number.is_empty() => {
+                in_time = true;
+                continue;
+            }
+            ('W', false) => 604_800.0,
+            ('D', false) => 86_400.0,
+            ('H', true) => 3_600.0,
+            ('M', true) => 60.0,
+            ('S', true) => 1.0,
+            _ => return Err(invalid()),
+        };
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        let component = TimeDelta::microseconds((value * seconds_per_unit * 1e6).round() as i64);
+        total = total.checked_add(&component).ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(if negative { -total } else { total })
+}
+
+/// Parse the body of a bytes literal such as `b"\x89PNG"`. Characters must be ASCII, other values
+/// are written as `\xNN` escapes. `\n`, `\r`, `\t`, `\0`, `\\` and `\"` are also supported.
+pub fn parse_bytes(text: &str) -> Result<Vec<u8>, TypeConversionError> {
+    let invalid = || TypeConversionError::new(&format!("Invalid bytes '{}'", text));
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii() {
+            return Err(invalid());
+        }
+        if c != '\\' {
+            bytes.push(c as u8);
+            continue;
+        }
+        let byte = match chars.next().ok_or_else(invalid)? {
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid());
+                }
+                u8::from_str_radix(&digits, 16).map_err(|_| invalid())?
+            }
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            '\\' => b'\\',
+            '"' => b'"',
+            _ => return Err(invalid()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Parse the body of a hex literal such as `x"deadbeef"`, two digits per byte
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, TypeConversionError> {
+    hex::decode(text).map_err(|_| TypeConversionError::new(&format!("Invalid hex '{}'", text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(
+            parse_datetime("2024-01-01T12:30:00+02:00").unwrap(),
+            DateTime::parse_from_rfc3339("2024-01-01T10:30:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_datetime("2024-01-01T12:30:00").unwrap(),
+            DateTime::parse_from_rfc3339("2024-01-01T12:30:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_datetime("2005-01-01").unwrap(),
+            DateTime::parse_from_rfc3339("2005-01-01T00:00:00Z").unwrap()
+        );
+        assert!(parse_datetime("2024-13-01").is_err());
+        assert!(parse_datetime("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_timedelta() {
+        assert_eq!(parse_timedelta("PT").unwrap(), TimeDelta::zero());
+        assert_eq!(parse_timedelta("P1W").unwrap(), TimeDelta::weeks(1));
+        assert_eq!(
+            parse_timedelta("P1DT2H").unwrap(),
+            TimeDelta::days(1) + TimeDelta::hours(2)
+        );
+        assert_eq!(
+            parse_timedelta("PT1M30.5S").unwrap(),
+            TimeDelta::milliseconds(90_500)
+        );
+        assert_eq!(parse_timedelta("-PT30M").unwrap(), -TimeDelta::minutes(30));
+        assert!(parse_timedelta("P").is_err());
+        assert!(parse_timedelta("1W").is_err());
+        assert!(parse_timedelta("p1w").is_err());
+        assert!(parse_timedelta("P1M").is_err());
+        assert!(parse_timedelta("PT1H2").is_err());
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("abc").unwrap(), b"abc");
+        assert_eq!(parse_bytes("\\x89PNG\\r\\n").unwrap(), b"\x89PNG\r\n");
+        assert_eq!(parse_bytes("\\\\\\\"\\0").unwrap(), b"\\\"\0");
+        assert!(parse_bytes("\\x8").is_err());
+        assert!(parse_bytes("\\xzz").is_err());
+        assert!(parse_bytes("\\x+1").is_err());
+        assert!(parse_bytes("\\q").is_err());
+        assert!(parse_bytes("caf\u{e9}").is_err());
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("DEADbeef").unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_hex("").unwrap(), Vec::<u8>::new());
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+}